@@ -1,5 +1,9 @@
+mod history;
 mod map;
 mod player;
+mod session;
 
+pub use self::history::*;
 pub use self::map::*;
 pub use self::player::*;
+pub use self::session::*;
@@ -0,0 +1,289 @@
+use common::network::edit::{EditError, MapEdit};
+
+/// Most undo steps kept around
+const HISTORY_LENGTH: usize = 100;
+
+/// A single undo or redo step, made up of the edits that reverse each edit in it, in the order they were made
+type Step = Vec<Vec<MapEdit>>;
+
+/// Undo and redo for the map editor.
+///
+/// Steps only hold the edits that reverse them. Undoing one applies those, and what reverses *them* becomes the
+/// redo step, so it never needs to know what the edits actually do.
+#[derive(Default)]
+pub struct EditHistory {
+    undo: Vec<Step>,
+    redo: Vec<Step>,
+    /// The step being built while a stroke is in progress
+    stroke: Option<Step>,
+}
+
+impl EditHistory {
+    /// Groups every edit recorded until `end_stroke` into one step, so a whole drag is undone at once
+    pub fn begin_stroke(&mut self) {
+        if self.stroke.is_none() {
+            self.stroke = Some(Vec::new());
+        }
+    }
+
+    pub fn end_stroke(&mut self) {
+        if let Some(step) = self.stroke.take() {
+            if !step.is_empty() {
+                self.push_undo(step);
+            }
+        }
+    }
+
+    /// Records the edits that reverse one that was just made, anything that could be redone is forgotten
+    pub fn record(&mut self, inverse: Vec<MapEdit>) {
+        self.redo.clear();
+
+        match &mut self.stroke {
+            Some(stroke) => stroke.push(inverse),
+            None => self.push_undo(vec![inverse]),
+        }
+    }
+
+    /// Undoes the last step with `apply`, which applies an edit and returns what reverses it.
+    ///
+    /// Returns `false` if there was nothing to undo.
+    pub fn undo(&mut self, apply: impl FnMut(&MapEdit) -> Result<Vec<MapEdit>, EditError>) -> bool {
+        self.end_stroke();
+
+        match self.undo.pop() {
+            Some(step) => {
+                let step = replay(step, apply);
+                self.redo.push(step);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Redoes the last undone step, the same way as `undo`
+    pub fn redo(&mut self, apply: impl FnMut(&MapEdit) -> Result<Vec<MapEdit>, EditError>) -> bool {
+        self.end_stroke();
+
+        match self.redo.pop() {
+            Some(step) => {
+                let step = replay(step, apply);
+                self.push_undo(step);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adjusts every step for an edit someone else made, dropping anything that no longer applies
+    pub fn rebase(&mut self, earlier: &MapEdit) {
+        let steps = self.undo.iter_mut().chain(&mut self.redo).chain(&mut self.stroke);
+
+        for edits in steps.flatten() {
+            *edits = edits.iter().filter_map(|edit| edit.rebase(earlier)).collect();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    fn push_undo(&mut self, step: Step) {
+        self.undo.push(step);
+        if self.undo.len() > HISTORY_LENGTH {
+            self.undo.remove(0);
+        }
+    }
+}
+
+/// Applies a step's edits in reverse, returning the step that would reverse it again
+fn replay(step: Step, mut apply: impl FnMut(&MapEdit) -> Result<Vec<MapEdit>, EditError>) -> Step {
+    let mut reverse = Vec::with_capacity(step.len());
+
+    for edits in step.into_iter().rev() {
+        for edit in &edits {
+            match apply(edit) {
+                Ok(inverse) => reverse.push(inverse),
+                // whatever it was changing is gone, someone else probably got to it first
+                Err(e) => log::warn!("Couldn't replay map edit: {e}"),
+            }
+        }
+    }
+
+    reverse
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use common::network::TileAttribute;
+    use mint::Point2;
+
+    use super::*;
+
+    fn set(x: u32, attribute: TileAttribute) -> MapEdit {
+        MapEdit::SetAttribute {
+            position: Point2 { x, y: 0 },
+            attribute,
+        }
+    }
+
+    /// Just enough of a map to undo and redo attribute edits on
+    #[derive(Default)]
+    struct Attributes(HashMap<u32, TileAttribute>);
+
+    impl Attributes {
+        fn get(&self, x: u32) -> TileAttribute {
+            self.0.get(&x).copied().unwrap_or_default()
+        }
+
+        fn apply(&mut self, edit: &MapEdit) -> Result<Vec<MapEdit>, EditError> {
+            match edit {
+                MapEdit::SetAttribute { position, attribute } => {
+                    let previous = self.0.insert(position.x, *attribute).unwrap_or_default();
+                    Ok(vec![set(position.x, previous)])
+                }
+                _ => Err(EditError::OutOfBounds),
+            }
+        }
+
+        fn edit(&mut self, history: &mut EditHistory, edit: MapEdit) {
+            let inverse = self.apply(&edit).unwrap();
+            history.record(inverse);
+        }
+
+        fn undo(&mut self, history: &mut EditHistory) -> bool {
+            history.undo(|edit| self.apply(edit))
+        }
+
+        fn redo(&mut self, history: &mut EditHistory) -> bool {
+            history.redo(|edit| self.apply(edit))
+        }
+    }
+
+    #[test]
+    fn undoes_newest_first() {
+        let mut history = EditHistory::default();
+        let mut map = Attributes::default();
+
+        map.edit(&mut history, set(0, TileAttribute::Blocked));
+        map.edit(&mut history, set(0, TileAttribute::Water));
+
+        assert!(map.undo(&mut history));
+        assert_eq!(map.get(0), TileAttribute::Blocked);
+        assert!(map.undo(&mut history));
+        assert_eq!(map.get(0), TileAttribute::None);
+        assert!(!map.undo(&mut history), "there should be nothing left to undo");
+    }
+
+    #[test]
+    fn redoes_what_was_undone() {
+        let mut history = EditHistory::default();
+        let mut map = Attributes::default();
+
+        map.edit(&mut history, set(0, TileAttribute::Blocked));
+        map.edit(&mut history, set(1, TileAttribute::Water));
+        map.undo(&mut history);
+        map.undo(&mut history);
+
+        assert!(map.redo(&mut history));
+        assert_eq!((map.get(0), map.get(1)), (TileAttribute::Blocked, TileAttribute::None));
+        assert!(map.redo(&mut history));
+        assert_eq!((map.get(0), map.get(1)), (TileAttribute::Blocked, TileAttribute::Water));
+        assert!(!map.redo(&mut history), "there should be nothing left to redo");
+
+        // and it can all be undone again
+        map.undo(&mut history);
+        map.undo(&mut history);
+        assert_eq!((map.get(0), map.get(1)), (TileAttribute::None, TileAttribute::None));
+    }
+
+    #[test]
+    fn new_edits_forget_redo() {
+        let mut history = EditHistory::default();
+        let mut map = Attributes::default();
+
+        map.edit(&mut history, set(0, TileAttribute::Blocked));
+        map.edit(&mut history, set(0, TileAttribute::Water));
+        map.undo(&mut history);
+        map.edit(&mut history, set(1, TileAttribute::Blocked));
+
+        assert!(!map.redo(&mut history), "the undone edit is on another branch now");
+        assert_eq!(map.get(0), TileAttribute::Blocked);
+
+        map.undo(&mut history);
+        assert_eq!((map.get(0), map.get(1)), (TileAttribute::Blocked, TileAttribute::None));
+        map.undo(&mut history);
+        assert_eq!(map.get(0), TileAttribute::None);
+    }
+
+    #[test]
+    fn strokes_are_one_step() {
+        let mut history = EditHistory::default();
+        let mut map = Attributes::default();
+
+        map.edit(&mut history, set(0, TileAttribute::Water));
+        history.begin_stroke();
+        for x in 0..3 {
+            map.edit(&mut history, set(x, TileAttribute::Blocked));
+        }
+        history.end_stroke();
+
+        map.undo(&mut history);
+        assert_eq!(map.get(0), TileAttribute::Water);
+        assert_eq!((map.get(1), map.get(2)), (TileAttribute::None, TileAttribute::None));
+
+        map.redo(&mut history);
+        assert!((0..3).all(|x| map.get(x) == TileAttribute::Blocked));
+    }
+
+    #[test]
+    fn undo_ends_the_stroke() {
+        let mut history = EditHistory::default();
+        let mut map = Attributes::default();
+
+        history.begin_stroke();
+        map.edit(&mut history, set(0, TileAttribute::Blocked));
+        map.edit(&mut history, set(1, TileAttribute::Blocked));
+
+        assert!(map.undo(&mut history));
+        assert_eq!((map.get(0), map.get(1)), (TileAttribute::None, TileAttribute::None));
+    }
+
+    #[test]
+    fn keeps_a_limited_number_of_steps() {
+        let mut history = EditHistory::default();
+        let mut map = Attributes::default();
+
+        for x in 0..HISTORY_LENGTH as u32 + 10 {
+            map.edit(&mut history, set(x, TileAttribute::Blocked));
+        }
+
+        let mut undone = 0;
+        while map.undo(&mut history) {
+            undone += 1;
+        }
+
+        assert_eq!(undone, HISTORY_LENGTH);
+        assert_eq!(map.get(9), TileAttribute::Blocked);
+        assert_eq!(map.get(10), TileAttribute::None);
+    }
+
+    #[test]
+    fn rebases_on_other_edits() {
+        let mut history = EditHistory::default();
+        history.record(vec![MapEdit::RemoveZone(2)]);
+        history.record(vec![MapEdit::RemoveZone(0)]);
+
+        // someone else removed the first zone, so ours moved down and the one we'd remove is gone
+        history.rebase(&MapEdit::RemoveZone(0));
+
+        let mut replayed = Vec::new();
+        while history.undo(|edit| {
+            replayed.push(edit.clone());
+            Ok(Vec::new())
+        }) {}
+
+        assert_eq!(replayed, vec![MapEdit::RemoveZone(1)]);
+    }
+}
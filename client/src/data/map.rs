@@ -2,18 +2,25 @@ use std::collections::HashMap;
 use std::path::PathBuf;
 
 use anyhow::Result;
-use common::network::{Map as NetworkMap, MapHash, MapLayer, MapSettings, TileAnimation, ZoneData};
-use common::TILE_SIZE;
+use common::network::edit::{EditError, MapEdit};
+use common::network::{
+    Map as NetworkMap, MapHash, MapLayer, MapSettings, Tile as NetworkTile, TileAnimation, TileAttribute, ZoneData,
+};
+use common::{physics, TILE_SIZE};
 use macroquad::prelude::*;
-use ndarray::{azip, indices, Array2, Zip};
+use mint::Point2;
+use ndarray::{azip, indices, s, Array2, Zip};
 use strum::{EnumCount, IntoEnumIterator};
 
 use crate::assets::Assets;
 use crate::utils::draw_text_shadow;
 use crate::utils::ping_pong;
 
+mod clipboard;
 mod interop;
 
+pub use clipboard::{Clipboard, CopyOptions};
+
 const OFFSETS: &[(i32, i32)] = &[(0, -1), (1, 0), (0, 1), (-1, 0), (1, -1), (1, 1), (-1, 1), (-1, -1)];
 
 fn autotile_a(neighbors: u8) -> IVec2 {
@@ -83,7 +90,7 @@ fn autotile_d(neighbors: u8) -> IVec2 {
     ivec2(x, y)
 }
 
-#[derive(Copy, Clone, Debug, Default)]
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
 pub struct Tile {
     pub texture: IVec2,
     pub autotile: bool,
@@ -194,7 +201,44 @@ impl AttributeDataEx for ZoneData {
     }
 }
 
+impl AttributeDataEx for TileAttribute {
+    fn text(&self) -> String {
+        match self {
+            TileAttribute::Directional(blocked) => {
+                let directions = [
+                    (blocked.north, "N"),
+                    (blocked.east, "E"),
+                    (blocked.south, "S"),
+                    (blocked.west, "W"),
+                ];
+
+                directions
+                    .iter()
+                    .filter(|(blocked, _)| *blocked)
+                    .map(|(_, name)| *name)
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            }
+            TileAttribute::Ledge(direction) => format!("Ledge {direction}"),
+            attribute => attribute.name().to_string(),
+        }
+    }
+    fn color(&self) -> Color {
+        match self {
+            TileAttribute::None => BLANK,
+            TileAttribute::Blocked => RED,
+            TileAttribute::Directional(_) => ORANGE,
+            TileAttribute::Water => BLUE,
+            TileAttribute::Ledge(_) => YELLOW,
+        }
+    }
+}
+
 pub fn draw_zone(position: Rect, data: &ZoneData, assets: &Assets) {
+    draw_overlay(position, data, assets);
+}
+
+fn draw_overlay(position: Rect, data: &impl AttributeDataEx, assets: &Assets) {
     let color = data.color();
     let text = data.text();
 
@@ -230,6 +274,28 @@ impl Zone {
     pub fn draw(&self, assets: &Assets) {
         draw_zone(self.position, &self.data, assets);
     }
+
+    /// Checks if the zone is still on a map of this size, it has to match the server exactly or zone indices won't line up
+    fn fits(&self, width: u32, height: u32) -> bool {
+        let Rect { x, y, w, h } = self.position;
+        map_bounds(width, height).overlaps(&physics::Rect::new(x, y, w, h))
+    }
+
+    /// Checks if the zone is entirely on a map of this size, the server won't accept new zones that aren't
+    fn inside(&self, width: u32, height: u32) -> bool {
+        let Rect { x, y, w, h } = self.position;
+        map_bounds(width, height).contains(&physics::Rect::new(x, y, w, h))
+    }
+}
+
+/// The area covered by a map of this size, in pixels
+fn map_bounds(width: u32, height: u32) -> physics::Rect {
+    physics::Rect::new(
+        0.0,
+        0.0,
+        width as f32 * TILE_SIZE as f32,
+        height as f32 * TILE_SIZE as f32,
+    )
 }
 
 #[derive(Clone)]
@@ -241,6 +307,7 @@ pub struct Map {
     pub settings: MapSettings,
     layers: HashMap<MapLayer, Array2<Option<Tile>>>,
     autotiles: HashMap<MapLayer, Array2<Option<AutoTile>>>,
+    attributes: Array2<TileAttribute>,
     pub zones: Vec<Zone>,
 }
 
@@ -289,6 +356,7 @@ impl Map {
             settings,
             layers,
             autotiles,
+            attributes: Array2::default((width as usize, height as usize)),
             zones,
         }
     }
@@ -304,10 +372,6 @@ impl Map {
         pos.x >= 0 && pos.x < self.width as i32 && pos.y >= 0 && pos.y < self.height as i32
     }
 
-    pub fn fill(&mut self, layer: MapLayer, tile: Option<Tile>) {
-        self.layers.get_mut(&layer).unwrap().fill(tile);
-    }
-
     pub fn tile(&self, layer: MapLayer, position: IVec2) -> Option<&Tile> {
         self.layers[&layer]
             .get((position.x as usize, position.y as usize))
@@ -340,6 +404,96 @@ impl Map {
             .and_then(Option::take)
     }
 
+    /// The corners of the rectangle between two positions, clipped to the map. Returns `None` if it's entirely off of it.
+    pub fn clip_rect(&self, a: IVec2, b: IVec2) -> Option<(IVec2, IVec2)> {
+        let last = ivec2(self.width as i32 - 1, self.height as i32 - 1);
+        let (min, max) = (a.min(b), a.max(b));
+
+        if max.x < 0 || max.y < 0 || min.x > last.x || min.y > last.y {
+            return None;
+        }
+
+        Some((min.max(IVec2::ZERO), max.min(last)))
+    }
+
+    /// Every tile connected to `start` through tiles that match it, what the bucket fills
+    pub fn flood_region(&self, layer: MapLayer, start: IVec2) -> Vec<IVec2> {
+        if !self.valid(start) {
+            return Vec::new();
+        }
+
+        let tiles = &self.layers[&layer];
+        let index = |position: IVec2| (position.x as usize, position.y as usize);
+        let target = tiles[index(start)];
+
+        let mut visited = Array2::from_elem(tiles.dim(), false);
+        let mut stack = vec![start];
+        let mut region = Vec::new();
+        visited[index(start)] = true;
+
+        while let Some(position) = stack.pop() {
+            region.push(position);
+
+            for offset in &OFFSETS[..4] {
+                let neighbor = position + IVec2::from(*offset);
+                if self.valid(neighbor) && !visited[index(neighbor)] && tiles[index(neighbor)] == target {
+                    visited[index(neighbor)] = true;
+                    stack.push(neighbor);
+                }
+            }
+        }
+
+        region
+    }
+
+    /// Lays `stamp` over `positions`, repeating it so its top left corner lines up with `origin`.
+    /// Positions off of the map are left out.
+    pub fn stamp_tiles(
+        &self,
+        stamp: &Array2<Tile>,
+        origin: IVec2,
+        positions: impl IntoIterator<Item = IVec2>,
+    ) -> Vec<(Point2<u32>, Option<NetworkTile>)> {
+        let (width, height) = stamp.dim();
+        if width == 0 || height == 0 {
+            return Vec::new();
+        }
+
+        positions
+            .into_iter()
+            .filter(|position| self.valid(*position))
+            .map(|position| {
+                let offset = position - origin;
+                let x = offset.x.rem_euclid(width as i32) as usize;
+                let y = offset.y.rem_euclid(height as i32) as usize;
+
+                let point = Point2 {
+                    x: position.x as u32,
+                    y: position.y as u32,
+                };
+                (point, Some(stamp[(x, y)].into()))
+            })
+            .collect()
+    }
+
+    pub fn attribute(&self, position: IVec2) -> TileAttribute {
+        self.attributes
+            .get((position.x as usize, position.y as usize))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    // Sets a tile's attribute, returning the previous one if the position was valid
+    pub fn set_attribute(&mut self, position: IVec2, attribute: TileAttribute) -> Option<TileAttribute> {
+        self.attributes
+            .get_mut((position.x as usize, position.y as usize))
+            .map(|inner| std::mem::replace(inner, attribute))
+    }
+
+    pub fn attributes(&self) -> &Array2<TileAttribute> {
+        &self.attributes
+    }
+
     pub fn tiles(&self, layer: MapLayer) -> impl Iterator<Item = Option<&Tile>> {
         self.layers[&layer].iter().map(Option::as_ref)
     }
@@ -364,6 +518,16 @@ impl Map {
         }
     }
 
+    pub fn draw_attributes(&self, assets: &Assets) {
+        for ((x, y), attribute) in self.attributes.indexed_iter() {
+            if *attribute != TileAttribute::None {
+                let position = ivec2(x as i32, y as i32).as_f32() * TILE_SIZE as f32;
+                let rect = Rect::new(position.x, position.y, TILE_SIZE as f32, TILE_SIZE as f32);
+                draw_overlay(rect, attribute, assets);
+            }
+        }
+    }
+
     pub fn update_autotile_cache(&mut self) {
         for layer in MapLayer::iter() {
             let texture_map = self.layers[&layer].map(|tile| match tile {
@@ -409,28 +573,421 @@ impl Map {
         }
     }
 
-    pub fn resize(&mut self, width: u32, height: u32) {
+    fn resize(&mut self, width: u32, height: u32) {
         let dimensions = (width as usize, height as usize);
         let mut layers = HashMap::with_capacity(MapLayer::COUNT);
 
         for layer in MapLayer::iter() {
-            let tiles = Zip::from(indices(dimensions)).map_collect(|index| self.layers[&layer][index]);
+            let tiles = Zip::from(indices(dimensions))
+                .map_collect(|index| self.layers[&layer].get(index).copied().unwrap_or_default());
             layers.insert(layer, tiles);
         }
 
-        let map_rect = Rect::new(
-            0.0,
-            0.0,
-            width as f32 * TILE_SIZE as f32,
-            height as f32 * TILE_SIZE as f32,
-        );
+        let attributes =
+            Zip::from(indices(dimensions)).map_collect(|index| self.attributes.get(index).copied().unwrap_or_default());
 
-        self.zones.retain(|zone| map_rect.overlaps(&zone.position));
+        self.zones.retain(|zone| zone.fits(width, height));
 
         self.width = width;
         self.height = height;
         self.layers = layers;
+        self.attributes = attributes;
+    }
+
+    /// Works out the edits that would undo `edit`, this has to be called before it's applied
+    pub fn inverse(&self, edit: &MapEdit) -> Vec<MapEdit> {
+        match edit {
+            MapEdit::SetTile { layer, position, .. } | MapEdit::ClearTile { layer, position } => {
+                vec![self.restore_tile(*layer, *position)]
+            }
+            MapEdit::SetTiles { layer, tiles } => vec![MapEdit::SetTiles {
+                layer: *layer,
+                tiles: self.previous_tiles(*layer, tiles.iter().map(|(position, _)| *position)),
+            }],
+            MapEdit::FillRegion { layer, from, to, .. } => {
+                let positions = (from.y..=to.y).flat_map(|y| (from.x..=to.x).map(move |x| Point2 { x, y }));
+                let tiles = self.previous_tiles(*layer, positions);
+
+                // filling an empty layer is the usual case, that's undone by clearing it the same way
+                match tiles.first() {
+                    Some(&(_, first)) if tiles.iter().all(|(_, tile)| *tile == first) => vec![MapEdit::FillRegion {
+                        layer: *layer,
+                        from: *from,
+                        to: *to,
+                        tile: first,
+                    }],
+                    _ => vec![MapEdit::SetTiles { layer: *layer, tiles }],
+                }
+            }
+            MapEdit::SetAttribute { position, .. } => vec![MapEdit::SetAttribute {
+                position: *position,
+                attribute: self.attribute(ivec2(position.x as i32, position.y as i32)),
+            }],
+            MapEdit::AddZone(_) => vec![MapEdit::RemoveZone(self.zones.len())],
+            MapEdit::RemoveZone(index) => self
+                .zones
+                .get(*index)
+                .map(|zone| MapEdit::InsertZone(*index, zone.clone().into()))
+                .into_iter()
+                .collect(),
+            MapEdit::InsertZone(index, _) => vec![MapEdit::RemoveZone(*index)],
+            MapEdit::ModifyZone(index, _) => self
+                .zones
+                .get(*index)
+                .map(|zone| MapEdit::ModifyZone(*index, zone.clone().into()))
+                .into_iter()
+                .collect(),
+            MapEdit::ChangeSettings(_) => vec![MapEdit::ChangeSettings(self.settings.clone())],
+            &MapEdit::Resize { width, height } => {
+                let mut edits = vec![MapEdit::Resize {
+                    width: self.width,
+                    height: self.height,
+                }];
+
+                // put back everything the resize cuts off
+                let cut_off = |(x, y): (usize, usize)| x >= width as usize || y >= height as usize;
+                let point = |(x, y): (usize, usize)| Point2 {
+                    x: x as u32,
+                    y: y as u32,
+                };
+
+                for layer in MapLayer::iter() {
+                    let tiles = self.layers[&layer]
+                        .indexed_iter()
+                        .filter(|(index, tile)| cut_off(*index) && tile.is_some())
+                        .map(|(index, tile)| (point(index), tile.map(Into::into)))
+                        .collect::<Vec<_>>();
+
+                    if !tiles.is_empty() {
+                        edits.push(MapEdit::SetTiles { layer, tiles });
+                    }
+                }
+
+                for (index, attribute) in self.attributes.indexed_iter() {
+                    if cut_off(index) && *attribute != TileAttribute::None {
+                        edits.push(MapEdit::SetAttribute {
+                            position: point(index),
+                            attribute: *attribute,
+                        });
+                    }
+                }
+
+                // going from first to last puts each of them back where it was
+                for (index, zone) in self.zones.iter().enumerate() {
+                    if !zone.fits(width, height) {
+                        edits.push(MapEdit::InsertZone(index, zone.clone().into()));
+                    }
+                }
+
+                edits
+            }
+        }
+    }
+
+    /// The edit that puts a tile back to how it is now
+    fn restore_tile(&self, layer: MapLayer, position: Point2<u32>) -> MapEdit {
+        match self.network_tile(layer, position) {
+            Some(tile) => MapEdit::SetTile { layer, position, tile },
+            None => MapEdit::ClearTile { layer, position },
+        }
+    }
+
+    fn previous_tiles(
+        &self,
+        layer: MapLayer,
+        positions: impl Iterator<Item = Point2<u32>>,
+    ) -> Vec<(Point2<u32>, Option<NetworkTile>)> {
+        positions
+            .map(|position| (position, self.network_tile(layer, position)))
+            .collect()
+    }
+
+    fn network_tile(&self, layer: MapLayer, position: Point2<u32>) -> Option<NetworkTile> {
+        self.tile(layer, ivec2(position.x as i32, position.y as i32))
+            .map(|tile| (*tile).into())
+    }
+
+    /// Applies an edit made in the map editor, either here or by someone else
+    pub fn apply(&mut self, edit: &MapEdit) -> Result<(), EditError> {
+        edit.validate(self.width, self.height, self.zones.len())?;
+
+        match edit {
+            MapEdit::SetTile { layer, position, tile } => {
+                self.set_tile(*layer, ivec2(position.x as i32, position.y as i32), (*tile).into());
+            }
+            MapEdit::ClearTile { layer, position } => {
+                self.clear_tile(*layer, ivec2(position.x as i32, position.y as i32));
+            }
+            MapEdit::SetTiles { layer, tiles } => {
+                let layer = self.layers.get_mut(layer).unwrap();
+                for (position, tile) in tiles {
+                    layer[(position.x as usize, position.y as usize)] = tile.map(Into::into);
+                }
+            }
+            MapEdit::FillRegion { layer, from, to, tile } => {
+                let (from, to) = ((from.x as usize, from.y as usize), (to.x as usize, to.y as usize));
+                self.layers
+                    .get_mut(layer)
+                    .unwrap()
+                    .slice_mut(s![from.0..=to.0, from.1..=to.1])
+                    .fill(tile.map(Into::into));
+            }
+            MapEdit::SetAttribute { position, attribute } => {
+                self.set_attribute(ivec2(position.x as i32, position.y as i32), *attribute);
+            }
+            MapEdit::AddZone(zone) => self.zones.push(zone.clone().into()),
+            MapEdit::RemoveZone(index) => {
+                self.zones.remove(*index);
+            }
+            MapEdit::InsertZone(index, zone) => self.zones.insert(*index, zone.clone().into()),
+            MapEdit::ModifyZone(index, zone) => self.zones[*index] = zone.clone().into(),
+            MapEdit::ChangeSettings(settings) => {
+                let cache_key = self.settings.cache_key;
+                self.settings = settings.clone();
+                self.settings.cache_key = cache_key;
+            }
+            &MapEdit::Resize { width, height } => self.resize(width, height),
+        }
+
+        if edit.changes_tiles() {
+            self.update_autotile_cache();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::network::Zone as NetworkZone;
+    use mint::Vector2;
+
+    use super::*;
+
+    /// A one tile zone at column `x`, named so it can be told apart from the others
+    fn zone(x: u32, name: &str) -> NetworkZone {
+        NetworkZone {
+            position: Point2 {
+                x: (x as i32 * TILE_SIZE) as f32,
+                y: 0.0,
+            },
+            size: Vector2 {
+                x: TILE_SIZE as f32,
+                y: TILE_SIZE as f32,
+            },
+            data: ZoneData::Warp(name.to_string(), Point2 { x: 0.0, y: 0.0 }, None),
+        }
+    }
+
+    fn zone_names(map: &Map) -> Vec<&str> {
+        map.zones
+            .iter()
+            .map(|zone| match &zone.data {
+                ZoneData::Warp(name, _, _) => name.as_str(),
+                ZoneData::Blocked => "",
+            })
+            .collect()
+    }
+
+    fn map_with_zones(names: &[(u32, &str)]) -> Map {
+        let mut map = Map::new("test", 10, 10);
+        for (x, name) in names {
+            map.apply(&MapEdit::AddZone(zone(*x, name))).unwrap();
+        }
+
+        map
+    }
+
+    /// Applies an edit and then the edits that undo it
+    fn apply_and_undo(map: &mut Map, edit: MapEdit) {
+        let inverse = map.inverse(&edit);
+        map.apply(&edit).unwrap();
+
+        for edit in &inverse {
+            map.apply(edit).unwrap();
+        }
+    }
+
+    fn tile(x: i32) -> Tile {
+        Tile {
+            texture: ivec2(x, 0),
+            ..Default::default()
+        }
+    }
+
+    /// Sorted so regions can be compared no matter what order they were found in
+    fn sorted(positions: Vec<IVec2>) -> Vec<(i32, i32)> {
+        let mut positions = positions
+            .into_iter()
+            .map(|position| (position.x, position.y))
+            .collect::<Vec<_>>();
+        positions.sort_unstable();
+        positions
+    }
+
+    #[test]
+    fn clips_rects_to_the_map() {
+        let map = Map::new("test", 10, 8);
+
+        assert_eq!(
+            map.clip_rect(ivec2(2, 3), ivec2(4, 5)),
+            Some((ivec2(2, 3), ivec2(4, 5)))
+        );
+        assert_eq!(
+            map.clip_rect(ivec2(-5, -5), ivec2(20, 20)),
+            Some((ivec2(0, 0), ivec2(9, 7)))
+        );
+        assert_eq!(
+            map.clip_rect(ivec2(8, 6), ivec2(12, 1)),
+            Some((ivec2(8, 1), ivec2(9, 6)))
+        );
+        assert_eq!(map.clip_rect(ivec2(-3, -1), ivec2(-1, 4)), None);
+        assert_eq!(map.clip_rect(ivec2(10, 0), ivec2(12, 2)), None);
+    }
+
+    #[test]
+    fn clips_reversed_rects() {
+        let map = Map::new("test", 10, 8);
+
+        assert_eq!(
+            map.clip_rect(ivec2(4, 5), ivec2(2, 3)),
+            Some((ivec2(2, 3), ivec2(4, 5)))
+        );
+        assert_eq!(
+            map.clip_rect(ivec2(4, 1), ivec2(2, 3)),
+            Some((ivec2(2, 1), ivec2(4, 3)))
+        );
+        assert_eq!(
+            map.clip_rect(ivec2(20, 20), ivec2(-5, -5)),
+            Some((ivec2(0, 0), ivec2(9, 7)))
+        );
+    }
+
+    #[test]
+    fn floods_bounded_regions() {
+        let mut map = Map::new("test", 6, 6);
+
+        // a wall around the 2x2 square at (1, 1), with a gap in the corner that diagonals can't get through
+        for i in 0..4 {
+            for position in [ivec2(i, 0), ivec2(i, 3), ivec2(0, i), ivec2(3, i)] {
+                map.set_tile(MapLayer::Ground, position, tile(1));
+            }
+        }
+        map.clear_tile(MapLayer::Ground, ivec2(3, 3));
+
+        let region = map.flood_region(MapLayer::Ground, ivec2(1, 1));
+        assert_eq!(sorted(region), [(1, 1), (1, 2), (2, 1), (2, 2)]);
+
+        // the wall itself is one connected region
+        let region = map.flood_region(MapLayer::Ground, ivec2(0, 0));
+        assert_eq!(region.len(), 11);
+    }
+
+    #[test]
+    fn floods_unbounded_regions() {
+        let mut map = Map::new("test", 6, 4);
+        map.set_tile(MapLayer::Ground, ivec2(2, 2), tile(1));
+
+        let region = map.flood_region(MapLayer::Ground, ivec2(0, 0));
+        assert_eq!(region.len(), 6 * 4 - 1);
+        assert!(!region.contains(&ivec2(2, 2)));
+
+        // other layers don't get in the way
+        assert_eq!(map.flood_region(MapLayer::Mask, ivec2(0, 0)).len(), 6 * 4);
+    }
+
+    #[test]
+    fn floods_nothing_off_the_map() {
+        let map = Map::new("test", 4, 4);
+
+        assert!(map.flood_region(MapLayer::Ground, ivec2(-1, 0)).is_empty());
+        assert!(map.flood_region(MapLayer::Ground, ivec2(0, 4)).is_empty());
+    }
+
+    #[test]
+    fn stamps_repeat_from_the_origin() {
+        let map = Map::new("test", 10, 10);
+        let stamp = Array2::from_shape_fn((2, 2), |(x, y)| tile(x as i32 + y as i32 * 2));
+
+        let positions = indices((4, 3))
+            .into_iter()
+            .map(|(x, y)| ivec2(x as i32 + 1, y as i32 + 1));
+        let tiles = map.stamp_tiles(&stamp, ivec2(1, 1), positions);
+
+        assert_eq!(tiles.len(), 12);
+        for (position, stamped) in tiles {
+            let x = (position.x as i32 - 1) % 2;
+            let y = (position.y as i32 - 1) % 2;
+            assert_eq!(stamped, Some(tile(x + y * 2).into()), "wrong tile at {position:?}");
+        }
+
+        // positions before the origin wrap around too
+        let tiles = map.stamp_tiles(&stamp, ivec2(3, 3), [ivec2(2, 2)]);
+        assert_eq!(tiles[0].1, Some(tile(3).into()));
+    }
+
+    #[test]
+    fn stamps_are_clipped_at_the_edge() {
+        let map = Map::new("test", 4, 3);
+        let stamp = Array2::from_elem((3, 3), tile(1));
+
+        let positions = indices((3, 3))
+            .into_iter()
+            .map(|(x, y)| ivec2(x as i32 + 2, y as i32 + 1));
+        let tiles = map.stamp_tiles(&stamp, ivec2(2, 1), positions);
+
+        let mut stamped = tiles
+            .iter()
+            .map(|(position, _)| (position.x, position.y))
+            .collect::<Vec<_>>();
+        stamped.sort_unstable();
+        assert_eq!(stamped, [(2, 1), (2, 2), (3, 1), (3, 2)]);
+
+        let tiles = map.stamp_tiles(&stamp, ivec2(-1, -1), [ivec2(-1, -1), ivec2(0, -1), ivec2(0, 0)]);
+        assert_eq!(tiles.len(), 1);
+    }
+
+    #[test]
+    fn empty_stamps_place_nothing() {
+        let map = Map::new("test", 4, 4);
+        let stamp = Array2::<Tile>::default((0, 0));
+
+        assert!(map.stamp_tiles(&stamp, IVec2::ZERO, [IVec2::ZERO]).is_empty());
+    }
+
+    #[test]
+    fn undoing_zone_removal_keeps_order() {
+        let mut map = map_with_zones(&[(0, "a"), (1, "b"), (2, "c")]);
+
+        apply_and_undo(&mut map, MapEdit::RemoveZone(0));
+        assert_eq!(zone_names(&map), ["a", "b", "c"]);
+
+        apply_and_undo(&mut map, MapEdit::RemoveZone(1));
+        assert_eq!(zone_names(&map), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn undoing_zone_insertion_removes_it() {
+        let mut map = map_with_zones(&[(0, "a"), (1, "b")]);
+
+        apply_and_undo(&mut map, MapEdit::InsertZone(1, zone(2, "c")));
+        assert_eq!(zone_names(&map), ["a", "b"]);
+    }
+
+    #[test]
+    fn undoing_resize_puts_zones_back_in_order() {
+        let mut map = map_with_zones(&[(0, "a"), (8, "b"), (1, "c"), (9, "d"), (2, "e")]);
+
+        let edit = MapEdit::Resize { width: 5, height: 5 };
+        let inverse = map.inverse(&edit);
+        map.apply(&edit).unwrap();
+        assert_eq!(zone_names(&map), ["a", "c", "e"]);
+
+        for edit in &inverse {
+            map.apply(edit).unwrap();
+        }
 
-        self.update_autotile_cache();
+        assert_eq!((map.width, map.height), (10, 10));
+        assert_eq!(zone_names(&map), ["a", "b", "c", "d", "e"]);
     }
 }
@@ -0,0 +1,458 @@
+use std::collections::HashMap;
+
+use common::network::{edit::MapEdit, BlockedDirections, Direction, MapLayer, TileAttribute};
+use common::TILE_SIZE;
+use macroquad::prelude::*;
+use mint::Point2;
+use ndarray::{s, Array2};
+use strum::IntoEnumIterator;
+
+use crate::assets::Assets;
+
+use super::{draw_zone, Map, Tile, Zone};
+
+/// What gets copied out of a selection
+#[derive(Clone)]
+pub struct CopyOptions {
+    pub layers: Vec<MapLayer>,
+    pub attributes: bool,
+    pub zones: bool,
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        Self {
+            layers: MapLayer::iter().collect(),
+            attributes: true,
+            zones: true,
+        }
+    }
+}
+
+/// Part of a map copied in the map editor, it can be pasted back onto it or any other map
+#[derive(Clone)]
+pub struct Clipboard {
+    width: u32,
+    height: u32,
+    layers: HashMap<MapLayer, Array2<Option<Tile>>>,
+    attributes: Option<Array2<TileAttribute>>,
+    /// Zones that were entirely inside of the selection, relative to its top left corner
+    zones: Vec<Zone>,
+}
+
+impl Clipboard {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn flip_horizontal(&mut self) {
+        let (width, height) = (self.width, self.height);
+        let right = width as f32 * TILE_SIZE as f32;
+
+        self.rearrange(
+            (width, height),
+            |x, y| (width as usize - 1 - x, y),
+            |direction| match direction {
+                Direction::East => Direction::West,
+                Direction::West => Direction::East,
+                direction => direction,
+            },
+            |rect| Rect::new(right - rect.x - rect.w, rect.y, rect.w, rect.h),
+        );
+    }
+
+    pub fn flip_vertical(&mut self) {
+        let (width, height) = (self.width, self.height);
+        let bottom = height as f32 * TILE_SIZE as f32;
+
+        self.rearrange(
+            (width, height),
+            |x, y| (x, height as usize - 1 - y),
+            |direction| match direction {
+                Direction::North => Direction::South,
+                Direction::South => Direction::North,
+                direction => direction,
+            },
+            |rect| Rect::new(rect.x, bottom - rect.y - rect.h, rect.w, rect.h),
+        );
+    }
+
+    /// Rotates everything a quarter turn clockwise
+    pub fn rotate(&mut self) {
+        let (width, height) = (self.width, self.height);
+        let bottom = height as f32 * TILE_SIZE as f32;
+
+        self.rearrange(
+            (height, width),
+            |x, y| (y, height as usize - 1 - x),
+            |direction| match direction {
+                Direction::North => Direction::East,
+                Direction::East => Direction::South,
+                Direction::South => Direction::West,
+                Direction::West => Direction::North,
+            },
+            |rect| Rect::new(bottom - rect.y - rect.h, rect.x, rect.h, rect.w),
+        );
+    }
+
+    /// Moves everything around to fit a clipboard of `size`. `source` gives where each new position came from,
+    /// `turn` is how attributes that care about direction change and `zone` moves a zone.
+    ///
+    /// Tiles themselves can't be flipped or rotated, only where they are.
+    fn rearrange(
+        &mut self,
+        (width, height): (u32, u32),
+        source: impl Fn(usize, usize) -> (usize, usize),
+        turn: impl Fn(Direction) -> Direction,
+        zone: impl Fn(Rect) -> Rect,
+    ) {
+        let dimensions = (width as usize, height as usize);
+
+        for tiles in self.layers.values_mut() {
+            *tiles = Array2::from_shape_fn(dimensions, |(x, y)| tiles[source(x, y)]);
+        }
+
+        if let Some(attributes) = &mut self.attributes {
+            *attributes = Array2::from_shape_fn(dimensions, |(x, y)| turn_attribute(attributes[source(x, y)], &turn));
+        }
+
+        for copied in &mut self.zones {
+            copied.position = zone(copied.position);
+        }
+
+        self.width = width;
+        self.height = height;
+    }
+
+    /// Draws what would be pasted with its top left corner at `origin`
+    pub fn draw(&self, origin: IVec2, time: f64, assets: &Assets) {
+        let offset = (origin * TILE_SIZE).as_f32();
+
+        for layer in MapLayer::iter() {
+            if let Some(tiles) = self.layers.get(&layer) {
+                for ((x, y), tile) in tiles.indexed_iter() {
+                    if let Some(tile) = tile {
+                        let position = offset + ivec2(x as i32, y as i32).as_f32() * TILE_SIZE as f32;
+                        tile.draw(position, time, assets);
+                    }
+                }
+            }
+        }
+
+        for zone in &self.zones {
+            draw_zone(zone.position.offset(offset), &zone.data, assets);
+        }
+
+        let size = vec2(self.width as f32, self.height as f32) * TILE_SIZE as f32;
+        draw_rectangle_lines(offset.x, offset.y, size.x, size.y, 2.0, YELLOW);
+    }
+}
+
+impl Map {
+    /// Copies everything from `from` to `to` inclusive, they have to be on the map
+    pub fn copy(&self, from: IVec2, to: IVec2, options: &CopyOptions) -> Clipboard {
+        let (xs, ys) = (from.x as usize..=to.x as usize, from.y as usize..=to.y as usize);
+
+        let layers = options
+            .layers
+            .iter()
+            .map(|layer| (*layer, self.layers[layer].slice(s![xs.clone(), ys.clone()]).to_owned()))
+            .collect();
+
+        let attributes = options
+            .attributes
+            .then(|| self.attributes.slice(s![xs.clone(), ys.clone()]).to_owned());
+
+        let offset = (from * TILE_SIZE).as_f32();
+        let zones = if options.zones {
+            self.zones_within(from, to)
+                .map(|(_, zone)| Zone {
+                    position: zone.position.offset(-offset),
+                    data: zone.data.clone(),
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Clipboard {
+            width: (to.x - from.x + 1) as u32,
+            height: (to.y - from.y + 1) as u32,
+            layers,
+            attributes,
+            zones,
+        }
+    }
+
+    /// The edits that clear out everything `copy` would copy
+    pub fn cut(&self, from: IVec2, to: IVec2, options: &CopyOptions) -> Vec<MapEdit> {
+        let positions = || (from.y..=to.y).flat_map(move |y| (from.x..=to.x).map(move |x| ivec2(x, y)));
+        let mut edits = Vec::new();
+
+        for layer in &options.layers {
+            let tiles = positions()
+                .filter(|position| self.tile(*layer, *position).is_some())
+                .map(|position| (point(position), None))
+                .collect::<Vec<_>>();
+
+            if !tiles.is_empty() {
+                edits.push(MapEdit::SetTiles { layer: *layer, tiles });
+            }
+        }
+
+        if options.attributes {
+            let attributes = positions()
+                .filter(|position| self.attribute(*position) != TileAttribute::None)
+                .map(|position| MapEdit::SetAttribute {
+                    position: point(position),
+                    attribute: TileAttribute::None,
+                });
+
+            edits.extend(attributes);
+        }
+
+        if options.zones {
+            // from the back, so removing one doesn't move the rest
+            let indices = self.zones_within(from, to).map(|(index, _)| index).collect::<Vec<_>>();
+            edits.extend(indices.into_iter().rev().map(MapEdit::RemoveZone));
+        }
+
+        edits
+    }
+
+    /// The edits that paste `clipboard` with its top left corner at `origin`, anything that ends up off of the map is left out
+    pub fn paste(&self, clipboard: &Clipboard, origin: IVec2) -> Vec<MapEdit> {
+        let mut edits = Vec::new();
+
+        for (layer, tiles) in &clipboard.layers {
+            let tiles = tiles
+                .indexed_iter()
+                .map(|((x, y), tile)| (origin + ivec2(x as i32, y as i32), tile))
+                .filter(|(position, _)| self.valid(*position))
+                .map(|(position, tile)| (point(position), tile.map(Into::into)))
+                .collect::<Vec<_>>();
+
+            if !tiles.is_empty() {
+                edits.push(MapEdit::SetTiles { layer: *layer, tiles });
+            }
+        }
+
+        if let Some(attributes) = &clipboard.attributes {
+            for ((x, y), attribute) in attributes.indexed_iter() {
+                let position = origin + ivec2(x as i32, y as i32);
+                if self.valid(position) && self.attribute(position) != *attribute {
+                    edits.push(MapEdit::SetAttribute {
+                        position: point(position),
+                        attribute: *attribute,
+                    });
+                }
+            }
+        }
+
+        let offset = (origin * TILE_SIZE).as_f32();
+        for zone in &clipboard.zones {
+            let zone = Zone {
+                position: zone.position.offset(offset),
+                data: zone.data.clone(),
+            };
+
+            if zone.inside(self.width, self.height) {
+                edits.push(MapEdit::AddZone(zone.into()));
+            }
+        }
+
+        edits
+    }
+
+    /// Zones entirely inside of the tiles from `from` to `to`, along with their indices
+    fn zones_within(&self, from: IVec2, to: IVec2) -> impl Iterator<Item = (usize, &Zone)> {
+        let min = (from * TILE_SIZE).as_f32();
+        let max = ((to + IVec2::ONE) * TILE_SIZE).as_f32();
+
+        self.zones.iter().enumerate().filter(move |(_, zone)| {
+            let Rect { x, y, w, h } = zone.position;
+            x >= min.x && y >= min.y && x + w <= max.x && y + h <= max.y
+        })
+    }
+}
+
+/// Turns the directions an attribute cares about, everything else stays as it is
+fn turn_attribute(attribute: TileAttribute, turn: impl Fn(Direction) -> Direction) -> TileAttribute {
+    match attribute {
+        TileAttribute::Directional(blocked) => {
+            let mut turned = BlockedDirections::default();
+            let directions = [Direction::North, Direction::East, Direction::South, Direction::West];
+
+            for direction in directions.into_iter().filter(|direction| blocked.contains(*direction)) {
+                match turn(direction) {
+                    Direction::North => turned.north = true,
+                    Direction::East => turned.east = true,
+                    Direction::South => turned.south = true,
+                    Direction::West => turned.west = true,
+                }
+            }
+
+            TileAttribute::Directional(turned)
+        }
+        TileAttribute::Ledge(direction) => TileAttribute::Ledge(turn(direction)),
+        attribute => attribute,
+    }
+}
+
+fn point(position: IVec2) -> Point2<u32> {
+    Point2 {
+        x: position.x as u32,
+        y: position.y as u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::network::{Zone as NetworkZone, ZoneData};
+    use mint::Vector2;
+
+    use super::*;
+
+    fn tile(x: i32) -> Tile {
+        Tile {
+            texture: ivec2(x, 0),
+            ..Default::default()
+        }
+    }
+
+    fn set_attribute(map: &mut Map, x: u32, y: u32, attribute: TileAttribute) {
+        let edit = MapEdit::SetAttribute {
+            position: Point2 { x, y },
+            attribute,
+        };
+        map.apply(&edit).unwrap();
+    }
+
+    /// A 3x2 clipboard with a different tile everywhere, a couple of directional attributes and a zone
+    fn clipboard() -> Clipboard {
+        let mut map = Map::new("test", 10, 10);
+        for x in 0..3 {
+            for y in 0..2 {
+                map.set_tile(MapLayer::Ground, ivec2(x, y), tile(x + y * 3));
+            }
+        }
+
+        let blocked = BlockedDirections {
+            north: true,
+            west: true,
+            ..Default::default()
+        };
+        set_attribute(&mut map, 1, 0, TileAttribute::Directional(blocked));
+        set_attribute(&mut map, 2, 1, TileAttribute::Ledge(Direction::North));
+
+        let zone = NetworkZone {
+            position: Point2 { x: 0.0, y: 0.0 },
+            size: Vector2 {
+                x: TILE_SIZE as f32,
+                y: TILE_SIZE as f32 / 2.0,
+            },
+            data: ZoneData::Blocked,
+        };
+        map.apply(&MapEdit::AddZone(zone)).unwrap();
+
+        map.copy(ivec2(0, 0), ivec2(2, 1), &CopyOptions::default())
+    }
+
+    fn assert_same(actual: &Clipboard, expected: &Clipboard) {
+        assert_eq!((actual.width, actual.height), (expected.width, expected.height));
+        assert_eq!(actual.layers, expected.layers);
+        assert_eq!(actual.attributes, expected.attributes);
+
+        let positions = |clipboard: &Clipboard| clipboard.zones.iter().map(|zone| zone.position).collect::<Vec<_>>();
+        assert_eq!(positions(actual), positions(expected));
+    }
+
+    #[test]
+    fn rotating_four_times_changes_nothing() {
+        let original = clipboard();
+        let mut rotated = original.clone();
+
+        for _ in 0..4 {
+            rotated.rotate();
+        }
+
+        assert_same(&rotated, &original);
+    }
+
+    #[test]
+    fn flipping_twice_changes_nothing() {
+        let original = clipboard();
+
+        let mut flipped = original.clone();
+        flipped.flip_horizontal();
+        flipped.flip_horizontal();
+        assert_same(&flipped, &original);
+
+        flipped.flip_vertical();
+        flipped.flip_vertical();
+        assert_same(&flipped, &original);
+    }
+
+    #[test]
+    fn rotating_moves_everything_clockwise() {
+        let mut clipboard = clipboard();
+        clipboard.rotate();
+
+        assert_eq!((clipboard.width, clipboard.height), (2, 3));
+
+        // the top left corner ends up in the top right, and the bottom left in the top left
+        let ground = &clipboard.layers[&MapLayer::Ground];
+        assert_eq!(ground[(1, 0)], Some(tile(0)));
+        assert_eq!(ground[(0, 0)], Some(tile(3)));
+        assert_eq!(ground[(0, 2)], Some(tile(5)));
+
+        let zone = clipboard.zones[0].position;
+        let size = TILE_SIZE as f32;
+        assert_eq!(zone, Rect::new(size * 1.5, 0.0, size / 2.0, size));
+    }
+
+    #[test]
+    fn rotating_turns_directional_attributes() {
+        let mut clipboard = clipboard();
+        clipboard.rotate();
+
+        let attributes = clipboard.attributes.as_ref().unwrap();
+        let blocked = BlockedDirections {
+            north: true,
+            east: true,
+            ..Default::default()
+        };
+        assert_eq!(attributes[(1, 1)], TileAttribute::Directional(blocked));
+        assert_eq!(attributes[(0, 2)], TileAttribute::Ledge(Direction::East));
+    }
+
+    #[test]
+    fn flipping_mirrors_directional_attributes() {
+        let mut clipboard = clipboard();
+        clipboard.flip_horizontal();
+
+        let attributes = clipboard.attributes.as_ref().unwrap();
+        let blocked = BlockedDirections {
+            north: true,
+            east: true,
+            ..Default::default()
+        };
+        assert_eq!(attributes[(1, 0)], TileAttribute::Directional(blocked));
+        assert_eq!(attributes[(0, 1)], TileAttribute::Ledge(Direction::North));
+
+        clipboard.flip_vertical();
+
+        let attributes = clipboard.attributes.as_ref().unwrap();
+        let blocked = BlockedDirections {
+            south: true,
+            east: true,
+            ..Default::default()
+        };
+        assert_eq!(attributes[(1, 1)], TileAttribute::Directional(blocked));
+        assert_eq!(attributes[(0, 0)], TileAttribute::Ledge(Direction::South));
+    }
+}
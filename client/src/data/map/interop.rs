@@ -36,6 +36,14 @@ impl TryFrom<NetworkMap> for Map {
             autotiles.insert(layer, Array2::default(contents.dim()));
         }
 
+        // maps from before tile attributes existed won't have any
+        let dimensions = (other.width as usize, other.height as usize);
+        let attributes = if other.attributes.dim() == dimensions {
+            other.attributes
+        } else {
+            Array2::default(dimensions)
+        };
+
         let mut map = Self {
             id: other.id,
             hash: other.hash,
@@ -44,6 +52,7 @@ impl TryFrom<NetworkMap> for Map {
             settings: other.settings,
             layers,
             autotiles,
+            attributes,
             zones: other.zones.into_iter().map(Into::into).collect(),
         };
 
@@ -71,6 +80,7 @@ impl From<Map> for NetworkMap {
             height: other.height,
             settings: other.settings,
             layers,
+            attributes: other.attributes,
             zones: other.zones.into_iter().map(Into::into).collect(),
         }
     }
@@ -1,3 +1,5 @@
+use std::collections::VecDeque;
+
 use common::{
     network::{ClientId, Direction, Player as NetworkPlayer, PlayerFlags},
     SPRITE_SIZE, TILE_SIZE,
@@ -6,9 +8,46 @@ use macroquad::prelude::*;
 
 use crate::{
     assets::Assets,
-    utils::{draw_text_outline, ping_pong},
+    utils::{draw_text_outline, ping_pong, wrap_text},
 };
 
+/// Most lines a speech bubble is allowed to take up
+const SPEECH_LINES: usize = 4;
+const SPEECH_FONT_SIZE: u16 = 14;
+/// Widest a line in a speech bubble can be before it's wrapped
+const SPEECH_WIDTH: f32 = 160.0;
+
+/// How long a speech bubble stays up for in seconds, longer messages stay up longer so they can be read
+fn speech_duration(message: &str) -> f64 {
+    (2.0 + message.chars().count() as f64 * 0.05).min(8.0)
+}
+
+/// Something a player said, wrapped to fit in a bubble as soon as it arrives so it isn't redone every frame
+pub struct Speech {
+    /// Each line along with how wide it is
+    lines: Vec<(String, f32)>,
+    said: f64,
+    duration: f64,
+}
+
+impl Speech {
+    pub fn new(message: &str, time: f64, assets: &Assets) -> Self {
+        let lines = wrap_text(message, SPEECH_WIDTH, assets.font, SPEECH_FONT_SIZE, SPEECH_LINES)
+            .into_iter()
+            .map(|line| {
+                let width = measure_text(&line, Some(assets.font), SPEECH_FONT_SIZE, 1.0).width;
+                (line, width)
+            })
+            .collect();
+
+        Self {
+            lines,
+            said: time,
+            duration: speech_duration(message),
+        }
+    }
+}
+
 pub enum Animation {
     Standing,
     Walking {
@@ -50,6 +89,10 @@ pub struct Player {
     pub sprite: u32,
     pub direction: Direction,
     pub flags: PlayerFlags,
+    /// Positions from snapshots and when they arrived, oldest first
+    pub snapshots: VecDeque<(f64, Vec2)>,
+    /// What the player last said in say chat, shown over their head until it runs out
+    pub speech: Option<Speech>,
 }
 
 impl Player {
@@ -71,9 +114,43 @@ impl Player {
             direction: data.direction,
             last_update: time,
             flags: data.flags,
+            snapshots: VecDeque::new(),
+            speech: None,
         }
     }
 
+    pub fn say(&mut self, message: &str, time: f64, assets: &Assets) {
+        self.speech = Some(Speech::new(message, time, assets));
+    }
+
+    /// Moves the player to where the buffered snapshots say they were at `time`,
+    /// returns `false` if there aren't any left so they should be moved normally instead.
+    pub fn interpolate(&mut self, time: f64) -> bool {
+        // keep one snapshot from before `time` to interpolate from
+        while self.snapshots.len() >= 2 && self.snapshots[1].0 <= time {
+            self.snapshots.pop_front();
+        }
+
+        match (self.snapshots.front(), self.snapshots.get(1)) {
+            (Some(&(from_time, from)), Some(&(to_time, to))) => {
+                let t = ((time - from_time) / (to_time - from_time)).clamp(0.0, 1.0);
+                self.position = from.lerp(to, t as f32);
+            }
+            (Some(&(last_time, position)), None) => {
+                self.position = position;
+
+                // snapshots leave out players that keep doing the same thing, so anyone walking carries on from here
+                if time >= last_time {
+                    self.snapshots.clear();
+                    return false;
+                }
+            }
+            (None, _) => return false,
+        }
+
+        true
+    }
+
     pub fn draw(&self, time: f64, assets: &Assets) {
         self.draw_text(assets, self.position);
         self.draw_sprite(assets, self.position, time);
@@ -98,6 +175,52 @@ impl Player {
             },
         );
     }
+
+    /// Draws what the player last said in a bubble just above their name, if it hasn't run out yet
+    pub fn draw_speech(&self, time: f64, assets: &Assets) {
+        const LINE_HEIGHT: f32 = 16.0;
+        const PADDING: f32 = 4.0;
+        const BACKGROUND: Color = Color::new(1.0, 1.0, 1.0, 0.9);
+
+        let lines = match &self.speech {
+            Some(speech) if time - speech.said < speech.duration => &speech.lines,
+            _ => return,
+        };
+
+        let width = lines.iter().map(|(_, width)| *width).fold(0.0, f32::max) + PADDING * 2.0;
+        let height = lines.len() as f32 * LINE_HEIGHT + PADDING * 2.0;
+
+        // clear of the name, which sits right on top of the sprite
+        let center = self.position.x + SPRITE_SIZE as f32 / 2.0;
+        let bottom = self.position.y - 24.0;
+        let bubble = Rect::new(center - width / 2.0, bottom - height, width, height);
+
+        draw_rectangle(bubble.x, bubble.y, bubble.w, bubble.h, BACKGROUND);
+        draw_rectangle_lines(bubble.x, bubble.y, bubble.w, bubble.h, 1.0, GRAY);
+        draw_triangle(
+            vec2(center - 5.0, bottom),
+            vec2(center + 5.0, bottom),
+            vec2(center, bottom + 6.0),
+            BACKGROUND,
+        );
+
+        for (index, (line, line_width)) in lines.iter().enumerate() {
+            // ? The text is drawn with the baseline being the supplied y
+            let baseline = bubble.y + PADDING + (index + 1) as f32 * LINE_HEIGHT - 4.0;
+            draw_text_ex(
+                line,
+                center - line_width / 2.0,
+                baseline,
+                TextParams {
+                    font_size: SPEECH_FONT_SIZE,
+                    font: assets.font,
+                    color: BLACK,
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
     fn draw_sprite(&self, assets: &Assets, position: Vec2, time: f64) {
         let offset = self.animation.get_animation_offset(time, self.direction);
 
@@ -0,0 +1,222 @@
+use std::collections::VecDeque;
+
+use common::network::{
+    edit::{EditError, MapEdit},
+    MAX_CLIENT_PACKET_SIZE,
+};
+
+use super::Map;
+
+/// Most tiles sent in one `SetTiles` edit, bigger ones are split up so they can go out over several batches
+const MAX_TILES_PER_EDIT: usize = 512;
+
+/// How big the edits in a batch can get before the rest wait for the next one, in bytes
+const MAX_BATCH_SIZE: usize = MAX_CLIENT_PACKET_SIZE / 2;
+
+/// Keeps the local map in step with everyone else editing it.
+///
+/// Our own edits show up straight away, but the server decides the order everyone's edits really happen in.
+/// Until it's echoed ours back, the map is rebuilt from the last state it confirmed plus whatever's still pending.
+#[derive(Default)]
+pub struct EditSession {
+    /// Last revision of the map the server told us about
+    pub revision: u32,
+    /// The map as of `revision`, only kept while we have edits the server hasn't confirmed
+    confirmed: Option<Map>,
+    /// Batches sent to the server that haven't been echoed back yet, along with their numbers, oldest first
+    sent: VecDeque<(u32, Vec<MapEdit>)>,
+    /// Edits made since the last batch was sent
+    unsent: Vec<MapEdit>,
+    /// Number for the next batch that's sent, it keeps counting across maps so late echoes can't be mistaken for new ones
+    next_batch: u32,
+}
+
+impl EditSession {
+    /// Starts over on a fresh copy of a map, forgetting about any edits still pending
+    pub fn reset(&mut self, revision: u32) {
+        *self = Self {
+            revision,
+            next_batch: self.next_batch,
+            ..Default::default()
+        };
+    }
+
+    /// Applies one of our own edits, it goes out with the next batch
+    pub fn edit(&mut self, map: &mut Map, edit: MapEdit) -> Result<(), EditError> {
+        edit.validate(map.width, map.height, map.zones.len())?;
+
+        if self.confirmed.is_none() {
+            self.confirmed = Some(map.clone());
+        }
+
+        map.apply(&edit)?;
+        match edit {
+            MapEdit::SetTiles { layer, tiles } if tiles.len() > MAX_TILES_PER_EDIT => {
+                let chunks = tiles.chunks(MAX_TILES_PER_EDIT).map(|tiles| MapEdit::SetTiles {
+                    layer,
+                    tiles: tiles.to_vec(),
+                });
+                self.unsent.extend(chunks);
+            }
+            edit => self.unsent.push(edit),
+        }
+
+        Ok(())
+    }
+
+    /// Takes as much as fits in a batch, returning the batch's number, the revision it was made against and the edits.
+    /// Anything left over goes out with the next one.
+    pub fn flush(&mut self) -> Option<(u32, u32, Vec<MapEdit>)> {
+        if self.unsent.is_empty() {
+            return None;
+        }
+
+        let batch = self.next_batch;
+        self.next_batch = self.next_batch.wrapping_add(1);
+
+        let mut size = 0;
+        let fits = self
+            .unsent
+            .iter()
+            .take_while(|edit| {
+                size += rmp_serde::to_vec(edit).map(|bytes| bytes.len()).unwrap_or_default();
+                size <= MAX_BATCH_SIZE
+            })
+            .count();
+
+        let edits = self.unsent.drain(..fits.max(1)).collect::<Vec<_>>();
+        self.sent.push_back((batch, edits.clone()));
+
+        Some((batch, self.revision, edits))
+    }
+
+    /// Applies edits the server has made, `batch` is set if they're the echo of one we sent
+    pub fn receive(
+        &mut self,
+        map: &mut Map,
+        revision: u32,
+        batch: Option<u32>,
+        edits: &[MapEdit],
+    ) -> Result<(), EditError> {
+        self.revision = revision;
+
+        // after a resync there's nothing left to match the echo up with, so it's treated like anyone else's
+        let own = match batch {
+            Some(batch) if self.sent.iter().any(|(sent, _)| *sent == batch) => {
+                // echoes come back in order, so anything older was dropped without one
+                while let Some((sent, _)) = self.sent.pop_front() {
+                    if sent == batch {
+                        break;
+                    }
+                    log::warn!("The server dropped batch {sent} of our map edits");
+                }
+                true
+            }
+            _ => false,
+        };
+
+        let mut confirmed = match self.confirmed.take() {
+            Some(confirmed) => confirmed,
+            None => {
+                // nothing of ours is pending, so the map already matches the server
+                for edit in edits {
+                    map.apply(edit)?;
+                }
+                return Ok(());
+            }
+        };
+
+        for edit in edits {
+            confirmed.apply(edit)?;
+        }
+
+        if !own {
+            // someone else got there first, what we've still got pending happens after them
+            for earlier in edits {
+                for pending in self.pending() {
+                    *pending = pending.iter().filter_map(|edit| edit.rebase(earlier)).collect();
+                }
+            }
+        }
+
+        // pending edits that don't fit any more are dropped, the server will do the same
+        *map = confirmed.clone();
+        for pending in self.pending() {
+            pending.retain(|edit| map.apply(edit).is_ok());
+        }
+
+        if !self.sent.is_empty() || !self.unsent.is_empty() {
+            self.confirmed = Some(confirmed);
+        }
+
+        Ok(())
+    }
+
+    /// Every edit that the server hasn't confirmed yet, in the order they were made
+    fn pending(&mut self) -> impl Iterator<Item = &mut Vec<MapEdit>> {
+        self.sent
+            .iter_mut()
+            .map(|(_, edits)| edits)
+            .chain(std::iter::once(&mut self.unsent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::network::{MapLayer, Tile};
+    use mint::Point2;
+
+    use super::*;
+
+    #[test]
+    fn big_edits_are_split_across_batches() {
+        let mut map = Map::new("test", 256, 256);
+        let mut session = EditSession::default();
+
+        let tile = Tile {
+            texture: Point2 { x: 1, y: 2 },
+            autotile: false,
+            animation: None,
+        };
+        let tiles = (0..256 * 256)
+            .map(|index| {
+                (
+                    Point2 {
+                        x: index % 256,
+                        y: index / 256,
+                    },
+                    Some(tile),
+                )
+            })
+            .collect::<Vec<_>>();
+        session
+            .edit(
+                &mut map,
+                MapEdit::SetTiles {
+                    layer: MapLayer::Ground,
+                    tiles,
+                },
+            )
+            .unwrap();
+
+        let mut sent = 0;
+        while let Some((_, _, edits)) = session.flush() {
+            let packet = common::network::client::Packet::EditMap {
+                batch: 0,
+                revision: 0,
+                edits: edits.clone(),
+            };
+            assert!(rmp_serde::to_vec(&packet).unwrap().len() <= MAX_CLIENT_PACKET_SIZE);
+
+            sent += edits
+                .iter()
+                .map(|edit| match edit {
+                    MapEdit::SetTiles { tiles, .. } => tiles.len(),
+                    _ => 0,
+                })
+                .sum::<usize>();
+        }
+
+        assert_eq!(sent, 256 * 256);
+    }
+}
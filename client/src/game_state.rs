@@ -1,28 +1,60 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::rc::Rc;
 
 use common::{
-    network::{client::Packet, server::Packet as ServerPacket, ChatChannel, ClientId, Direction, MapLayer, ZoneData},
-    RUN_SPEED, SPRITE_SIZE, TILE_SIZE, WALK_SPEED,
+    network::{
+        self,
+        client::Packet,
+        edit::{EditorCursor, MapEdit},
+        server::Packet as ServerPacket,
+        snapshot::{Snapshot, SnapshotState},
+        ChatChannel, ClientId, Direction, MapLayer, TileAttribute, ZoneData,
+    },
+    physics, RUN_SPEED, TILE_SIZE, WALK_SPEED,
 };
-use glam::{vec2, IVec2, Vec2};
+use glam::{ivec2, vec2, IVec2, Vec2};
 use macroquad::{color, prelude::*};
 use message_io::node::StoredNetEvent;
+use mint::Point2;
 
 use crate::{
     assets::Assets,
-    data::{draw_zone, Animation, Map, Player, Zone},
+    data::{draw_zone, Animation, EditHistory, EditSession, Map, Player, Tile, Zone},
     network::Network,
-    ui::{ChatWindow, MapEditor, Tab, Wants},
+    ui::{ChatWindow, MapEditor, Tab, Tool, Wants},
     utils::draw_text_shadow,
 };
 
+/// How far in the past other players are shown when they're moved by snapshots, in seconds,
+/// so there's usually a newer snapshot to interpolate towards
+const INTERPOLATION_DELAY: f64 = 0.1;
+
+/// Most decoded snapshots kept around to decode newer ones against
+const SNAPSHOT_HISTORY: usize = 32;
+
+/// How often snapshots are acknowledged, in seconds. The server only needs to know about a recent one to encode
+/// against, so acking every one would just eat into the rate limit.
+const SNAPSHOT_ACK_INTERVAL: f64 = 0.2;
+
+/// How often map edits are sent while in the map editor, in seconds
+const EDIT_SYNC_INTERVAL: f64 = 0.1;
+
+/// How often our cursor is sent while in the map editor, in seconds. It's only sent when it moves to another
+/// tile, but this keeps sweeping the mouse around from eating into the rate limit.
+const CURSOR_SYNC_INTERVAL: f64 = 0.2;
+
 struct UiState {
     map_editor: MapEditor,
     map_editor_shown: bool,
     chat_window: ChatWindow,
     last_tile: Option<(MouseButton, IVec2)>,
     drag_start: Option<Vec2>,
+    /// Where the rectangle tool started, and with which button
+    rect_start: Option<(MouseButton, IVec2)>,
+    /// Corners of the part of the map that's selected
+    selection: Option<(IVec2, IVec2)>,
+    /// If the clipboard is following the cursor, waiting to be placed
+    pasting: bool,
     block_pointer: bool,
     block_keyboard: bool,
 }
@@ -37,10 +69,20 @@ impl Default for UiState {
             block_pointer: false,
             block_keyboard: false,
             drag_start: Option::default(),
+            rect_start: None,
+            selection: None,
+            pasting: false,
         }
     }
 }
 
+/// A move we've sent to the server, kept around until it's acknowledged so it can be replayed
+struct PendingMove {
+    sequence: u32,
+    time: f64,
+    velocity: Option<Vec2>,
+}
+
 struct State {
     assets: Rc<Assets>,
     network: Network,
@@ -53,6 +95,23 @@ struct State {
     clip_rect: Rect,
     camera: Camera2D,
     last_movement: Option<(Direction, f64)>,
+    /// Moves the server hasn't acknowledged yet, oldest first
+    pending_moves: VecDeque<PendingMove>,
+    last_sequence: u32,
+    /// Recently received snapshots, oldest first
+    snapshots: VecDeque<(u32, SnapshotState)>,
+    last_snapshot_ack: f64,
+    session: EditSession,
+    history: EditHistory,
+    /// Set while we're waiting for the server to send the whole map, edits to it are already included
+    awaiting_map: bool,
+    /// Where everyone else in the map editor is pointing
+    cursors: HashMap<ClientId, EditorCursor>,
+    last_cursor: Option<EditorCursor>,
+    last_edit_sync: f64,
+    last_cursor_sync: f64,
+    /// Set once we've lost connection to the server, with the reason why
+    disconnected: Option<String>,
 }
 
 impl State {
@@ -65,10 +124,22 @@ impl State {
             map: Map::new("start", 20, 15),
             ui: UiState::default(),
             last_movement: None,
+            pending_moves: VecDeque::new(),
+            last_sequence: 0,
+            snapshots: VecDeque::new(),
+            last_snapshot_ack: 0.0,
+            session: EditSession::default(),
+            history: EditHistory::default(),
+            awaiting_map: false,
+            cursors: HashMap::new(),
+            last_cursor: None,
+            last_edit_sync: 0.0,
+            last_cursor_sync: 0.0,
             start_time: get_time(),
             time: get_time(),
             clip_rect: Rect::new(0.0, 0.0, screen_width(), screen_height()),
             camera: Camera2D::default(),
+            disconnected: None,
         }
     }
 
@@ -101,71 +172,124 @@ impl State {
 
         self.update_input();
         self.update_camera();
+        self.update_editing();
     }
 
     fn update_players(&mut self) {
-        let player_boxes = self
-            .players
-            .iter()
-            .map(|(cid, player)| {
-                (
-                    *cid,
-                    Rect::new(
-                        player.position.x,
-                        player.position.y + SPRITE_SIZE as f32 / 2.0,
-                        SPRITE_SIZE as f32,
-                        SPRITE_SIZE as f32 / 2.0,
-                    ),
-                )
-            })
-            .collect::<Vec<_>>();
-
-        for (client_id, player) in &mut self.players {
-            if let Some(&mut velocity) = player.velocity.as_mut() {
-                let offset = velocity * (self.time - player.last_update) as f32;
-                let new_position = player.position + offset;
-
-                // only block on the bottom half of the sprite, feels better
-                let sprite_rect = Rect::new(
-                    new_position.x,
-                    new_position.y + SPRITE_SIZE as f32 / 2.0,
-                    SPRITE_SIZE as f32,
-                    SPRITE_SIZE as f32 / 2.0,
-                );
-                let (map_width, map_height) = self.map.pixel_size();
+        let client_ids = self.players.keys().copied().collect::<Vec<_>>();
 
-                let mut valid = sprite_rect.left() >= 0.0
-                    && sprite_rect.top() >= 0.0
-                    && sprite_rect.right() < map_width
-                    && sprite_rect.bottom() < map_height;
+        let interpolation_time = self.time - INTERPOLATION_DELAY;
 
-                if !player.flags.in_map_editor {
-                    valid &= !player_boxes
-                        .iter()
-                        .filter(|(cid, _b)| cid != client_id)
-                        .any(|(_, b)| b.overlaps(&sprite_rect));
-
-                    valid &= !self
-                        .map
-                        .zones
-                        .iter()
-                        .filter(|zone| zone.data == ZoneData::Blocked)
-                        .any(|zone| zone.position.overlaps(&sprite_rect));
-                }
+        for client_id in client_ids {
+            let player = self.players.get_mut(&client_id).unwrap();
+            if client_id != self.local_player && player.interpolate(interpolation_time) {
+                player.last_update = self.time;
+                continue;
+            }
 
-                if valid {
-                    player.position = new_position;
-                }
+            let player = &self.players[&client_id];
+            if let Some(velocity) = player.velocity {
+                let dt = (self.time - player.last_update) as f32;
+                let position = self.step_player(client_id, player.position, velocity, dt);
 
                 // ? need to update anyway even if we don't change anything
                 // ? if we don't you can clip through stuff by walking against it for awhile
+                let player = self.players.get_mut(&client_id).unwrap();
+                player.position = position;
                 player.last_update = self.time;
             }
         }
     }
 
-    fn change_map(&mut self, map: Map) {
+    /// Moves a player from `position` for `dt` seconds, the same way the server does
+    fn step_player(&self, client_id: ClientId, position: Vec2, velocity: Vec2, dt: f32) -> Vec2 {
+        let (map_width, map_height) = self.map.pixel_size();
+        let bounds = physics::Rect::new(0.0, 0.0, map_width, map_height);
+
+        let in_map_editor = self
+            .players
+            .get(&client_id)
+            .map(|player| player.flags.in_map_editor)
+            .unwrap_or_default();
+
+        let blockers = if in_map_editor {
+            Vec::new()
+        } else {
+            self.map
+                .zones
+                .iter()
+                .filter(|zone| zone.data == ZoneData::Blocked)
+                .map(|zone| physics::Rect::new(zone.position.x, zone.position.y, zone.position.w, zone.position.h))
+                .chain(
+                    self.players
+                        .iter()
+                        .filter(|(cid, _player)| **cid != client_id)
+                        .map(|(_cid, player)| physics::sprite_hitbox(player.position.into())),
+                )
+                .collect::<Vec<_>>()
+        };
+
+        let obstacles = physics::Obstacles {
+            bounds,
+            tiles: (!in_map_editor).then_some(self.map.attributes()),
+            blockers: &blockers,
+        };
+
+        physics::step(position.into(), velocity.into(), dt, &obstacles).into()
+    }
+
+    /// Resets the local player to the state the server acknowledged, then replays every move it hasn't seen yet
+    fn reconcile(&mut self, position: Vec2, direction: Direction, velocity: Option<Vec2>, sequence: u32) {
+        // the server's state is from when it processed that move, or right now if it's one we've already dropped
+        let mut time = self
+            .pending_moves
+            .iter()
+            .find(|pending| pending.sequence == sequence)
+            .map(|pending| pending.time)
+            .unwrap_or(self.time);
+        self.pending_moves.retain(|pending| pending.sequence > sequence);
+
+        let mut position = position;
+        let mut current = velocity;
+        for pending in &self.pending_moves {
+            if let Some(velocity) = current {
+                if pending.time > time {
+                    position = self.step_player(self.local_player, position, velocity, (pending.time - time) as f32);
+                }
+            }
+            time = time.max(pending.time);
+            current = pending.velocity;
+        }
+        if let Some(velocity) = current {
+            position = self.step_player(self.local_player, position, velocity, (self.time - time) as f32);
+        }
+
+        let caught_up = self.pending_moves.is_empty();
+        if let Some(player) = self.players.get_mut(&self.local_player) {
+            player.position = position;
+            player.last_update = self.time;
+
+            // only take the server's movement if it's not one we're about to override, and it actually changed
+            if caught_up && player.velocity != velocity {
+                player.direction = direction;
+                player.velocity = velocity;
+                player.animation = match velocity {
+                    Some(velocity) => Animation::Walking {
+                        start: self.time,
+                        speed: velocity.length() as f64,
+                    },
+                    None => Animation::Standing,
+                };
+            }
+        }
+    }
+
+    fn change_map(&mut self, map: Map, revision: u32) {
         self.map = map;
+        self.session.reset(revision);
+        self.history.clear();
+        self.cursors.clear();
+        self.ui.selection = None;
         self.assets.toggle_music(self.map.settings.music.as_deref());
         self.assets.set_tileset(&self.map.settings.tileset).unwrap();
         if let Err(e) = self.map.save_cache() {
@@ -173,6 +297,133 @@ impl State {
         }
     }
 
+    fn request_map(&mut self) {
+        log::debug!("Requesting map..");
+        self.network.send(&Packet::RequestMap);
+        self.awaiting_map = true;
+    }
+
+    /// Applies an edit to the map, it's sent to the server and everyone else with the next batch
+    fn edit_map(&mut self, edit: MapEdit) {
+        let inverse = self.map.inverse(&edit);
+
+        match self.session.edit(&mut self.map, edit) {
+            Ok(()) => self.history.record(inverse),
+            Err(e) => log::warn!("Couldn't edit map: {e}"),
+        }
+    }
+
+    /// Applies several edits that are undone all at once
+    fn edit_map_all(&mut self, edits: Vec<MapEdit>) {
+        self.history.begin_stroke();
+        for edit in edits {
+            self.edit_map(edit);
+        }
+        self.history.end_stroke();
+    }
+
+    /// The selection, if there is one and it's still on the map
+    fn selection(&self) -> Option<(IVec2, IVec2)> {
+        self.ui.selection.and_then(|(from, to)| self.map.clip_rect(from, to))
+    }
+
+    fn copy_selection(&mut self) {
+        if let Some((from, to)) = self.selection() {
+            let clipboard = self.map.copy(from, to, self.ui.map_editor.copy_options());
+            self.ui.map_editor.set_clipboard(clipboard);
+        }
+    }
+
+    fn cut_selection(&mut self) {
+        if let Some((from, to)) = self.selection() {
+            self.copy_selection();
+
+            let edits = self.map.cut(from, to, self.ui.map_editor.copy_options());
+            self.edit_map_all(edits);
+        }
+    }
+
+    fn start_paste(&mut self) {
+        if self.ui.map_editor.clipboard().is_some() {
+            // selecting doesn't do anything on the click that places it
+            self.ui.map_editor.select_tool(Tool::Select);
+            self.ui.pasting = true;
+        }
+    }
+
+    /// Places the clipboard wherever's clicked, right clicking gives up on it
+    fn update_paste(&mut self) {
+        if is_mouse_button_pressed(MouseButton::Left) {
+            if let Some(clipboard) = self.ui.map_editor.clipboard() {
+                let edits = self.map.paste(clipboard, self.mouse_tile());
+                self.edit_map_all(edits);
+            }
+            self.ui.pasting = false;
+        } else if is_mouse_button_pressed(MouseButton::Right) {
+            self.ui.pasting = false;
+        }
+    }
+
+    fn mouse_tile(&self) -> IVec2 {
+        self.camera.screen_to_world(mouse_position().into()).as_i32() / TILE_SIZE
+    }
+
+    fn undo(&mut self) {
+        let (map, session) = (&mut self.map, &mut self.session);
+        self.history.undo(|edit| {
+            let inverse = map.inverse(edit);
+            session.edit(map, edit.clone()).map(|()| inverse)
+        });
+    }
+
+    fn redo(&mut self) {
+        let (map, session) = (&mut self.map, &mut self.session);
+        self.history.redo(|edit| {
+            let inverse = map.inverse(edit);
+            session.edit(map, edit.clone()).map(|()| inverse)
+        });
+    }
+
+    fn send_edits(&mut self) {
+        if let Some((batch, revision, edits)) = self.session.flush() {
+            self.network.send(&Packet::EditMap { batch, revision, edits });
+        }
+    }
+
+    /// Sends off our edits and which tile our cursor is on every so often while the map editor is open
+    fn update_editing(&mut self) {
+        if self.time - self.last_edit_sync >= EDIT_SYNC_INTERVAL {
+            self.last_edit_sync = self.time;
+            self.send_edits();
+        }
+
+        if self.time - self.last_cursor_sync < CURSOR_SYNC_INTERVAL {
+            return;
+        }
+
+        self.last_cursor_sync = self.time;
+
+        if !self.ui.map_editor_shown {
+            self.last_cursor = None;
+            return;
+        }
+
+        // only the tile matters to everyone else, so moving around inside one doesn't send anything
+        let position = self.camera.screen_to_world(mouse_position().into());
+        let position = (position / TILE_SIZE as f32).floor() * TILE_SIZE as f32;
+
+        let cursor = EditorCursor {
+            position: position.into(),
+            layer: self.ui.map_editor.layer(),
+            tile: (self.ui.map_editor.tab() == Tab::Tileset).then(|| self.ui.map_editor.tile().into()),
+        };
+
+        if self.last_cursor.as_ref() != Some(&cursor) {
+            self.network.send(&Packet::EditorCursor(Some(cursor.clone())));
+            self.last_cursor = Some(cursor);
+        }
+    }
+
     fn update_ui(&mut self, ctx: &egui::Context) {
         use egui::Window;
 
@@ -206,26 +457,39 @@ impl State {
         match self.ui.map_editor.wants() {
             None => (),
             Some(Wants::Save) => {
-                let (id, settings) = self.ui.map_editor.map_settings();
-                self.map.id = id.to_string();
-                self.map.settings = settings.clone();
+                let (_, settings) = self.ui.map_editor.map_settings();
+                if *settings != self.map.settings {
+                    self.edit_map(MapEdit::ChangeSettings(settings.clone()));
+                }
 
-                let data = Box::new(self.map.clone().into());
-                self.network.send(&Packet::SaveMap(data));
+                self.send_edits();
                 self.network.send(&Packet::MapEditor(false));
             }
             Some(Wants::Close) => {
+                self.send_edits();
                 self.network.send(&Packet::MapEditor(false));
-                self.network.send(&Packet::RequestMap);
             }
             Some(Wants::Resize(width, height)) => {
-                self.map.resize(width, height);
+                self.edit_map(MapEdit::Resize { width, height });
             }
+            Some(Wants::Undo) => self.undo(),
+            Some(Wants::Redo) => self.redo(),
+            Some(Wants::Copy) => self.copy_selection(),
+            Some(Wants::Cut) => self.cut_selection(),
+            Some(Wants::Paste) => self.start_paste(),
             Some(Wants::Warp(id)) => {
                 self.network.send(&Packet::Warp(id, None));
             }
             Some(Wants::Fill(layer, tile)) => {
-                self.map.fill(layer, tile);
+                self.edit_map(MapEdit::FillRegion {
+                    layer,
+                    from: Point2 { x: 0, y: 0 },
+                    to: Point2 {
+                        x: self.map.width - 1,
+                        y: self.map.height - 1,
+                    },
+                    tile: tile.map(Into::into),
+                });
             }
         }
 
@@ -254,6 +518,9 @@ impl State {
         }
         if !self.ui.block_pointer {
             self.update_pointer();
+        } else {
+            self.history.end_stroke();
+            self.ui.rect_start = None;
         }
     }
 
@@ -298,7 +565,15 @@ impl State {
                     None
                 };
 
+                self.last_sequence = self.last_sequence.wrapping_add(1);
+                self.pending_moves.push_back(PendingMove {
+                    sequence: self.last_sequence,
+                    time: self.time,
+                    velocity: velocity.map(Vec2::from),
+                });
+
                 self.network.send(&Packet::Move {
+                    sequence: self.last_sequence,
                     position: player.position.into(),
                     direction: player.direction,
                     velocity,
@@ -310,11 +585,39 @@ impl State {
         if is_key_pressed(KeyCode::F1) {
             self.network.send(&Packet::MapEditor(true));
         }
+
+        // Map editor
+        if self.ui.map_editor_shown {
+            let control = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+            let shift = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+
+            if control && (is_key_pressed(KeyCode::Y) || (shift && is_key_pressed(KeyCode::Z))) {
+                self.redo();
+            } else if control && is_key_pressed(KeyCode::Z) {
+                self.undo();
+            } else if control && is_key_pressed(KeyCode::C) {
+                self.copy_selection();
+            } else if control && is_key_pressed(KeyCode::X) {
+                self.cut_selection();
+            } else if control && is_key_pressed(KeyCode::V) {
+                self.start_paste();
+            }
+
+            if is_key_pressed(KeyCode::Escape) {
+                self.ui.pasting = false;
+                self.ui.selection = None;
+            }
+        }
     }
 
     fn update_pointer(&mut self) {
         // Map editor
         if self.ui.map_editor_shown {
+            if self.ui.pasting {
+                self.update_paste();
+                return;
+            }
+
             match self.ui.map_editor.tab() {
                 Tab::Tileset => {
                     let mouse_button = if is_mouse_button_down(MouseButton::Left) {
@@ -325,27 +628,48 @@ impl State {
                         None
                     };
 
+                    if mouse_button.is_none() {
+                        self.history.end_stroke();
+                        self.ui.last_tile = None;
+                    } else {
+                        // one drag is undone all at once
+                        self.history.begin_stroke();
+                    }
+
+                    self.use_tool(mouse_button, self.mouse_tile());
+                }
+                Tab::Attributes => {
+                    let mouse_button = if is_mouse_button_down(MouseButton::Left) {
+                        Some(MouseButton::Left)
+                    } else if is_mouse_button_down(MouseButton::Right) {
+                        Some(MouseButton::Right)
+                    } else {
+                        None
+                    };
+
+                    if mouse_button.is_none() {
+                        self.history.end_stroke();
+                    }
+
                     if let Some(mouse_button) = mouse_button {
+                        // one drag is undone all at once
+                        self.history.begin_stroke();
+
                         let mouse_position = self.camera.screen_to_world(mouse_position().into()).as_i32();
                         let tile_position = mouse_position / TILE_SIZE;
 
                         let current_tile = (mouse_button, tile_position);
 
-                        if self.ui.last_tile != Some(current_tile) {
-                            match mouse_button {
-                                MouseButton::Left => {
-                                    let layer = self.ui.map_editor.layer();
-                                    let tile = self.ui.map_editor.tile();
-                                    self.map.set_tile(layer, tile_position, tile);
-                                }
-                                MouseButton::Right => {
-                                    let layer = self.ui.map_editor.layer();
-                                    self.map.clear_tile(layer, tile_position);
-                                }
-                                _ => (),
+                        if self.ui.last_tile != Some(current_tile) && self.map.valid(tile_position) {
+                            let attribute = match mouse_button {
+                                MouseButton::Left => self.ui.map_editor.attribute(),
+                                _ => TileAttribute::None,
                             };
 
-                            self.map.update_autotile_cache();
+                            self.edit_map(MapEdit::SetAttribute {
+                                position: tile_point(tile_position),
+                                attribute,
+                            });
                             self.ui.last_tile = Some(current_tile);
                         }
                     }
@@ -353,26 +677,32 @@ impl State {
                 Tab::Zones => {
                     let mouse_position = self.camera.screen_to_world(mouse_position().into());
                     if is_mouse_button_pressed(MouseButton::Right) {
-                        for (i, attrib) in self.map.zones.iter().enumerate().rev() {
-                            if attrib.position.contains(mouse_position) {
-                                self.map.zones.swap_remove(i);
-                                break;
-                            }
+                        let zone = self
+                            .map
+                            .zones
+                            .iter()
+                            .rposition(|zone| zone.position.contains(mouse_position));
+                        if let Some(i) = zone {
+                            self.edit_map(MapEdit::RemoveZone(i));
                         }
                     }
 
                     let mouse_down = is_mouse_button_down(MouseButton::Left);
                     if self.ui.drag_start.is_some() && !mouse_down {
-                        let drag_start = self.ui.drag_start.take().unwrap();
+                        // zones have to be entirely on the map
+                        let map_size = (ivec2(self.map.width as i32, self.map.height as i32) * TILE_SIZE).as_f32();
+                        let drag_start = self.ui.drag_start.take().unwrap().clamp(Vec2::ZERO, map_size);
+                        let mouse_position = mouse_position.clamp(Vec2::ZERO, map_size);
                         let start = drag_start.min(mouse_position);
                         let size = (drag_start.max(mouse_position) - start).max(vec2(6.0, 6.0)); // assume that 6x6 is the smallest you can make.
 
                         let drag_rect = Rect::new(start.x, start.y, size.x, size.y);
 
-                        self.map.zones.push(Zone {
+                        let zone = Zone {
                             position: drag_rect,
                             data: self.ui.map_editor.zone_data().clone(),
-                        });
+                        };
+                        self.edit_map(MapEdit::AddZone(zone.into()));
                     } else if self.ui.drag_start.is_none() && mouse_down {
                         self.ui.drag_start = Some(mouse_position);
                     };
@@ -382,6 +712,117 @@ impl State {
         }
     }
 
+    /// Uses the map editor's current tool on a tile, `mouse_button` is whichever is held down
+    fn use_tool(&mut self, mouse_button: Option<MouseButton>, tile_position: IVec2) {
+        let layer = self.ui.map_editor.layer();
+
+        match self.ui.map_editor.tool() {
+            Tool::Pencil => {
+                let mouse_button = match mouse_button {
+                    Some(mouse_button) => mouse_button,
+                    None => return,
+                };
+
+                let current_tile = (mouse_button, tile_position);
+                if self.ui.last_tile == Some(current_tile) || !self.map.valid(tile_position) {
+                    return;
+                }
+
+                let edit = match mouse_button {
+                    MouseButton::Left => {
+                        let stamp = self.ui.map_editor.stamp();
+                        let (width, height) = stamp.dim();
+                        let positions = (0..height as i32)
+                            .flat_map(|y| (0..width as i32).map(move |x| tile_position + ivec2(x, y)));
+
+                        MapEdit::SetTiles {
+                            layer,
+                            tiles: self.map.stamp_tiles(&stamp, tile_position, positions),
+                        }
+                    }
+                    _ => MapEdit::ClearTile {
+                        layer,
+                        position: tile_point(tile_position),
+                    },
+                };
+
+                self.edit_map(edit);
+                self.ui.last_tile = Some(current_tile);
+            }
+            Tool::Rectangle => match (mouse_button, self.ui.rect_start) {
+                (Some(mouse_button), None) => self.ui.rect_start = Some((mouse_button, tile_position)),
+                (None, Some((mouse_button, start))) => {
+                    self.ui.rect_start = None;
+
+                    let (from, to) = match self.map.clip_rect(start, tile_position) {
+                        Some(corners) => corners,
+                        None => return,
+                    };
+
+                    let stamp = self.ui.map_editor.stamp();
+                    let edit = if mouse_button == MouseButton::Left && stamp.len() > 1 {
+                        let positions = (from.y..=to.y).flat_map(|y| (from.x..=to.x).map(move |x| ivec2(x, y)));
+                        MapEdit::SetTiles {
+                            layer,
+                            tiles: self.map.stamp_tiles(&stamp, start.min(tile_position), positions),
+                        }
+                    } else {
+                        MapEdit::FillRegion {
+                            layer,
+                            from: tile_point(from),
+                            to: tile_point(to),
+                            tile: (mouse_button == MouseButton::Left).then(|| self.ui.map_editor.tile().into()),
+                        }
+                    };
+
+                    self.edit_map(edit);
+                }
+                _ => (),
+            },
+            Tool::Select => match (mouse_button, self.ui.rect_start) {
+                // only on the click itself, so placing the clipboard doesn't start a selection
+                (Some(mouse_button), None) if is_mouse_button_pressed(mouse_button) => {
+                    self.ui.rect_start = Some((mouse_button, tile_position));
+                }
+                (None, Some((mouse_button, start))) => {
+                    self.ui.rect_start = None;
+                    self.ui.selection = match mouse_button {
+                        MouseButton::Left => self.map.clip_rect(start, tile_position),
+                        _ => None,
+                    };
+                }
+                _ => (),
+            },
+            Tool::Bucket => {
+                let clear = is_mouse_button_pressed(MouseButton::Right);
+                if !is_mouse_button_pressed(MouseButton::Left) && !clear {
+                    return;
+                }
+
+                let region = self.map.flood_region(layer, tile_position);
+                let tiles = if clear {
+                    region
+                        .into_iter()
+                        .map(|position| (tile_point(position), None))
+                        .collect()
+                } else {
+                    self.map.stamp_tiles(&self.ui.map_editor.stamp(), tile_position, region)
+                };
+
+                if !tiles.is_empty() {
+                    self.edit_map(MapEdit::SetTiles { layer, tiles });
+                }
+            }
+            Tool::Eyedropper => {
+                if is_mouse_button_pressed(MouseButton::Left) {
+                    if let Some(tile) = self.map.tile(layer, tile_position) {
+                        self.ui.map_editor.pick(*tile);
+                    }
+                }
+            }
+        }
+    }
+
     fn update_camera(&mut self) {
         if let Some(player) = self.players.get_mut(&self.local_player) {
             let min = Vec2::ZERO;
@@ -412,6 +853,42 @@ impl State {
         }
     }
 
+    /// Draws where someone else in the map editor is pointing, with the tile they'd place there
+    fn draw_cursor(&self, client_id: ClientId, cursor: &EditorCursor) {
+        let position = Vec2::from(cursor.position);
+        let tile_position = (position / TILE_SIZE as f32).floor() * TILE_SIZE as f32;
+
+        if let Some(tile) = cursor.tile {
+            Tile::from(tile).draw(tile_position, self.time, &self.assets);
+        }
+
+        draw_rectangle_lines(
+            tile_position.x,
+            tile_position.y,
+            TILE_SIZE as f32,
+            TILE_SIZE as f32,
+            2.0,
+            color::SKYBLUE,
+        );
+
+        let name = self
+            .players
+            .get(&client_id)
+            .map(|player| player.name.as_str())
+            .unwrap_or("Someone");
+
+        draw_text_shadow(
+            &format!("{name} ({})", cursor.layer),
+            position + vec2(12.0, 12.0),
+            TextParams {
+                font: self.assets.font,
+                font_size: 16,
+                color: color::SKYBLUE,
+                ..Default::default()
+            },
+        );
+    }
+
     fn draw(&self) {
         clear_background(color::BLACK);
 
@@ -426,13 +903,22 @@ impl State {
 
         players.sort_by(|a, b| a.position.y.partial_cmp(&b.position.y).unwrap());
 
-        for player in players {
+        for player in &players {
             player.draw(self.time, &self.assets);
         }
 
         self.map.draw_layer(MapLayer::Fringe, self.time, &self.assets);
         self.map.draw_layer(MapLayer::Fringe2, self.time, &self.assets);
 
+        // after the fringe layers so nothing covers them up
+        for player in &players {
+            player.draw_speech(self.time, &self.assets);
+        }
+
+        if self.ui.map_editor_shown && self.ui.map_editor.tab() == Tab::Attributes {
+            self.map.draw_attributes(&self.assets);
+        }
+
         if self.ui.map_editor_shown {
             self.map.draw_zones(&self.assets);
             if let Some(drag_start) = self.ui.drag_start {
@@ -444,6 +930,26 @@ impl State {
                 let drag_rect = Rect::new(start.x, start.y, size.x, size.y);
                 draw_zone(drag_rect, self.ui.map_editor.zone_data(), &self.assets);
             }
+
+            if let Some((_, start)) = self.ui.rect_start {
+                if let Some((from, to)) = self.map.clip_rect(start, self.mouse_tile()) {
+                    draw_tile_rect(from, to, color::WHITE);
+                }
+            }
+
+            if let Some((from, to)) = self.selection() {
+                draw_tile_rect(from, to, color::YELLOW);
+            }
+
+            if self.ui.pasting {
+                if let Some(clipboard) = self.ui.map_editor.clipboard() {
+                    clipboard.draw(self.mouse_tile(), self.time, &self.assets);
+                }
+            }
+
+            for (client_id, cursor) in &self.cursors {
+                self.draw_cursor(*client_id, cursor);
+            }
         }
 
         draw_text_shadow(
@@ -466,18 +972,90 @@ impl State {
             match event.network() {
                 StoredNetEvent::Connected(_, _) => (),
                 StoredNetEvent::Accepted(_, _) => unreachable!(),
-                StoredNetEvent::Message(_, bytes) => {
-                    let message = rmp_serde::from_slice(&bytes).unwrap();
-                    self.handle_message(message);
+                StoredNetEvent::Message(_, bytes) => match network::decode(&bytes) {
+                    Ok(message) => self.handle_message(message),
+                    Err(e) => {
+                        log::error!("Couldn't decode packet from the server: {e}");
+                        self.disconnected = Some(String::from("Received a malformed packet from the server."));
+                    }
+                },
+                StoredNetEvent::Disconnected(_) => {
+                    if self.disconnected.is_none() {
+                        self.disconnected = Some(String::from("Lost connection to the server."));
+                    }
                 }
-                StoredNetEvent::Disconnected(_) => todo!(),
             }
         }
     }
 
+    /// Decodes a snapshot against the one it was built from, and queues up new positions for everyone in it
+    fn apply_snapshot(&mut self, snapshot: Snapshot) {
+        let baseline = snapshot.baseline.and_then(|baseline| {
+            self.snapshots
+                .iter()
+                .find(|(sequence, _)| *sequence == baseline)
+                .map(|(_, state)| state)
+        });
+
+        let state = match snapshot.decode(baseline) {
+            Some(state) => state,
+            None => {
+                log::warn!(
+                    "Couldn't decode snapshot {}, it's baseline is missing",
+                    snapshot.sequence
+                );
+                return;
+            }
+        };
+
+        if self.time - self.last_snapshot_ack >= SNAPSHOT_ACK_INTERVAL {
+            self.last_snapshot_ack = self.time;
+            self.network.send(&Packet::AckSnapshot(snapshot.sequence));
+        }
+
+        // everyone left out is still doing the same thing, so they're left to be extrapolated
+        for client_id in snapshot.players.iter().map(|player| &player.client_id) {
+            // we already know where we are
+            if *client_id == self.local_player {
+                continue;
+            }
+
+            if let (Some(player), Some(player_state)) = (self.players.get_mut(client_id), state.get(client_id)) {
+                // start from wherever they've been extrapolated to
+                if player.snapshots.is_empty() {
+                    player
+                        .snapshots
+                        .push_back((self.time - INTERPOLATION_DELAY, player.position));
+                }
+                player.snapshots.push_back((self.time, player_state.position().into()));
+                player.direction = player_state.direction;
+
+                let velocity = player_state.velocity().map(Vec2::from);
+                if velocity != player.velocity {
+                    player.animation = match velocity {
+                        Some(velocity) => Animation::Walking {
+                            start: self.time,
+                            speed: velocity.length() as f64,
+                        },
+                        None => Animation::Standing,
+                    };
+                    player.velocity = velocity;
+                }
+            }
+        }
+
+        self.snapshots.push_back((snapshot.sequence, state));
+        if self.snapshots.len() > SNAPSHOT_HISTORY {
+            self.snapshots.pop_front();
+        }
+    }
+
     fn handle_message(&mut self, message: ServerPacket) {
         match &message {
             ServerPacket::MapData(_) => log::debug!("MapData(..)"),
+            ServerPacket::MapEdits { revision, edits, .. } => {
+                log::debug!("MapEdits {{ revision: {revision}, {} edits }}", edits.len())
+            }
             message => {
                 log::debug!("{message:?}");
             }
@@ -485,11 +1063,15 @@ impl State {
 
         let time = self.time;
         match message {
-            ServerPacket::JoinGame(_) | ServerPacket::FailedJoin(_) => unreachable!(),
+            message @ (ServerPacket::Hello { .. } | ServerPacket::JoinGame(_) | ServerPacket::FailedJoin(_)) => {
+                log::warn!("Ignoring {message:?}, it's only sent before joining the game");
+            }
 
             ServerPacket::PlayerData(id, player_data) => {
-                self.players
-                    .insert(id, Player::from_network(id, player_data, self.time));
+                let mut player = Player::from_network(id, player_data, self.time);
+                // keep the speech bubble up when their data is sent again, like after a sprite change
+                player.speech = self.players.get_mut(&id).and_then(|old| old.speech.take());
+                self.players.insert(id, player);
             }
             ServerPacket::RemoveData(id) => {
                 self.players.remove(&id);
@@ -497,7 +1079,12 @@ impl State {
             ServerPacket::ChatLog(channel, message) => {
                 self.ui.chat_window.insert(channel, message);
             }
-            ServerPacket::ChangeMap(id, cache_id) => {
+            ServerPacket::SpeechBubble(id, message) => {
+                if let Some(player) = self.players.get_mut(&id) {
+                    player.say(&message, self.time, &self.assets);
+                }
+            }
+            ServerPacket::ChangeMap(id, cache_id, revision) => {
                 self.players.clear();
                 self.ui.map_editor_shown = false;
 
@@ -508,11 +1095,10 @@ impl State {
                     .unwrap_or(true);
 
                 if needs_map {
-                    log::debug!("Requesting map..");
-                    self.network.send(&Packet::RequestMap);
+                    self.request_map();
                 } else {
-                    log::debug!("Loading map from");
-                    self.change_map(map.unwrap());
+                    log::debug!("Loading map from cache");
+                    self.change_map(map.unwrap(), revision);
                 }
             }
             ServerPacket::PlayerMove {
@@ -520,8 +1106,11 @@ impl State {
                 position,
                 direction,
                 velocity,
+                sequence,
             } => {
-                if let Some(player) = self.players.get_mut(&client_id) {
+                if client_id == self.local_player {
+                    self.reconcile(position.into(), direction, velocity.map(Vec2::from), sequence);
+                } else if let Some(player) = self.players.get_mut(&client_id) {
                     player.position = position.into();
                     player.direction = direction;
                     if let Some(velocity) = velocity {
@@ -538,9 +1127,46 @@ impl State {
                     }
                 }
             }
-            ServerPacket::MapData(remote) => {
+            ServerPacket::MapEdits {
+                revision,
+                author,
+                batch,
+                edits,
+            } => {
+                if !self.awaiting_map {
+                    let tileset = self.map.settings.tileset.clone();
+                    let own = author == self.local_player;
+
+                    if !own {
+                        for edit in &edits {
+                            self.history.rebase(edit);
+                        }
+                    }
+
+                    let batch = own.then_some(batch);
+                    if let Err(e) = self.session.receive(&mut self.map, revision, batch, &edits) {
+                        // we've fallen out of sync somehow, start over with the server's copy
+                        log::warn!("Couldn't apply map edit from server: {e}");
+                        self.request_map();
+                    }
+
+                    if self.map.settings.tileset != tileset {
+                        self.assets.set_tileset(&self.map.settings.tileset).unwrap();
+                    }
+                }
+            }
+            ServerPacket::EditorCursor(client_id, cursor) => match cursor {
+                Some(cursor) => {
+                    self.cursors.insert(client_id, cursor);
+                }
+                None => {
+                    self.cursors.remove(&client_id);
+                }
+            },
+            ServerPacket::MapData(remote, revision) => {
                 let map = Map::try_from(*remote).unwrap();
-                self.change_map(map);
+                self.awaiting_map = false;
+                self.change_map(map, revision);
             }
             ServerPacket::MapEditor {
                 id,
@@ -555,16 +1181,29 @@ impl State {
             ServerPacket::Flags(client_id, flags) => {
                 self.players.get_mut(&client_id).unwrap().flags = flags;
             }
+            ServerPacket::Shutdown { reason } | ServerPacket::Kicked { reason } => {
+                self.disconnected = Some(reason);
+            }
+            ServerPacket::Snapshot(snapshot) => {
+                self.apply_snapshot(snapshot);
+            }
         }
     }
 }
 
-pub async fn run(network: Network, client_id: ClientId, assets: Rc<Assets>) {
+/// Runs the game until we're disconnected, returning the reason
+pub async fn run(network: Network, client_id: ClientId, assets: Rc<Assets>) -> String {
     let mut state = State::new(network, client_id, assets);
 
     loop {
         state.update();
 
+        if let Some(reason) = state.disconnected.take() {
+            state.network.stop();
+            state.assets.toggle_music(None);
+            return reason;
+        }
+
         set_camera(&state.camera);
         state.draw();
         set_default_camera();
@@ -585,3 +1224,18 @@ pub async fn run(network: Network, client_id: ClientId, assets: Rc<Assets>) {
         next_frame().await;
     }
 }
+
+/// Outlines the tiles from `from` to `to` inclusive
+fn draw_tile_rect(from: IVec2, to: IVec2, color: Color) {
+    let position = (from * TILE_SIZE).as_f32();
+    let size = ((to - from + IVec2::ONE) * TILE_SIZE).as_f32();
+    draw_rectangle_lines(position.x, position.y, size.x, size.y, 2.0, color);
+}
+
+/// Converts a tile position that's already known to be on the map
+fn tile_point(position: IVec2) -> Point2<u32> {
+    Point2 {
+        x: position.x as u32,
+        y: position.y as u32,
+    }
+}
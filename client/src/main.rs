@@ -39,6 +39,11 @@ async fn main() {
     let assets = Assets::load().await.expect("Could not load assets");
     let assets = Rc::new(assets);
 
-    let (client_id, network) = title_state::run(Rc::clone(&assets)).await;
-    game_state::run(network, client_id, Rc::clone(&assets)).await;
+    // whenever we get disconnected, go back to the title screen and tell the player why
+    let mut error = None;
+    loop {
+        let (client_id, network) = title_state::run(Rc::clone(&assets), error.take()).await;
+        let reason = game_state::run(network, client_id, Rc::clone(&assets)).await;
+        error = Some(reason);
+    }
 }
@@ -1,7 +1,7 @@
 use std::{path::PathBuf, rc::Rc};
 
 use anyhow::Result;
-use common::network::{client::Packet, ClientId};
+use common::network::{client::Packet, ClientId, PROTOCOL_VERSION};
 use egui::Color32;
 use macroquad::{color, prelude::*};
 use message_io::node::StoredNetEvent;
@@ -58,6 +58,8 @@ struct UiState {
     error: Option<String>,
     tab: UiTab,
     dialog: Option<String>,
+    /// Set when the server can't ever accept us, so we stop trying to reconnect
+    incompatible: bool,
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -198,12 +200,12 @@ fn draw_ui(ctx: &egui::Context, state: &mut UiState, assets: &Assets) {
     }
 }
 
-pub async fn run(assets: Rc<Assets>) -> (ClientId, Network) {
+pub async fn run(assets: Rc<Assets>, error: Option<String>) -> (ClientId, Network) {
     let settings = Settings::load().unwrap_or_default();
 
     let mut state = UiState {
         network: Network::connect(&settings.address),
-        error: None,
+        error,
         tab: UiTab::Login,
         loading: true,
         username: settings.username,
@@ -211,15 +213,23 @@ pub async fn run(assets: Rc<Assets>) -> (ClientId, Network) {
         password: settings.password.unwrap_or_default(),
         character_name: String::new(),
         dialog: Some(String::from("Do you like weeD????????????????")),
+        incompatible: false,
     };
 
     loop {
         if let Some(event) = state.network.try_receive() {
-            use common::network::server::Packet;
+            use common::network::{
+                client::Packet as ClientPacket,
+                server::{FailJoinReason, Packet},
+            };
             match event.network() {
                 StoredNetEvent::Connected(_, ok) => {
                     if ok {
-                        state.loading = false;
+                        // we stay loading until the server says hello back
+                        state.network.send(&ClientPacket::Hello {
+                            protocol_version: PROTOCOL_VERSION,
+                            client_version: env!("CARGO_PKG_VERSION").to_owned(),
+                        });
                     } else {
                         state.error = Some(String::from("could not connect"));
                         state.network.stop();
@@ -228,10 +238,33 @@ pub async fn run(assets: Rc<Assets>) -> (ClientId, Network) {
                 }
                 StoredNetEvent::Accepted(_, _) => unreachable!(),
                 StoredNetEvent::Message(_, bytes) => {
-                    let message = rmp_serde::from_slice(&bytes).unwrap();
+                    let message = match common::network::decode(&bytes) {
+                        Ok(message) => message,
+                        Err(e) => {
+                            log::error!("Couldn't decode packet from the server: {e}");
+                            state.error = Some(String::from("received a malformed packet"));
+                            continue;
+                        }
+                    };
                     log::debug!("{message:?}");
 
                     match message {
+                        Packet::Hello {
+                            protocol_version,
+                            server_version,
+                        } => {
+                            if protocol_version == PROTOCOL_VERSION {
+                                log::info!("Connected to server {server_version}");
+                                state.loading = false;
+                            } else {
+                                let reason = FailJoinReason::VersionMismatch {
+                                    server: protocol_version,
+                                    client: PROTOCOL_VERSION,
+                                };
+                                state.error = Some(reason.to_string());
+                                state.incompatible = true;
+                            }
+                        }
                         Packet::JoinGame(client_id) => {
                             let settings = Settings {
                                 address: settings.address,
@@ -245,13 +278,25 @@ pub async fn run(assets: Rc<Assets>) -> (ClientId, Network) {
 
                             return (client_id, state.network);
                         }
+                        Packet::FailedJoin(reason @ FailJoinReason::VersionMismatch { .. }) => {
+                            state.error = Some(reason.to_string());
+                            state.incompatible = true;
+                        }
                         Packet::FailedJoin(reason) => {
                             state.error = Some(reason.to_string());
                             state.loading = false;
                         }
-                        _ => unreachable!(),
+                        Packet::Shutdown { reason } => {
+                            state.error = Some(reason);
+                            state.loading = false;
+                        }
+                        // announcements go out to everyone connected, even if they haven't logged in yet
+                        _ => (),
                     }
                 }
+                StoredNetEvent::Disconnected(_) if state.incompatible => {
+                    state.loading = true;
+                }
                 StoredNetEvent::Disconnected(_) => {
                     state.loading = true;
                     state.network.stop();
@@ -46,8 +46,8 @@ pub fn dialog<R>(ctx: &egui::Context, add_contents: impl FnOnce(&mut egui::Ui) -
     })
 }
 
-// TODO multiple tile selections
-pub fn tile_selector(ui: &mut Ui, texture: &TextureHandle, selected: &mut egui::Pos2, snap: egui::Vec2) {
+/// Picks tiles out of a tileset, click for a single tile or drag out a rectangle of them
+pub fn tile_selector(ui: &mut Ui, texture: &TextureHandle, selected: &mut Rect, snap: egui::Vec2) {
     ScrollArea::both().show_viewport(ui, |ui, viewport| {
         let clip_rect = ui.clip_rect();
 
@@ -55,22 +55,28 @@ pub fn tile_selector(ui: &mut Ui, texture: &TextureHandle, selected: &mut egui::
         let offset = (clip_rect.left_top() - viewport.left_top()) + egui::vec2(margin, margin);
         let texture_size = texture.size_vec2();
 
-        let response = ui.add(Image::new(texture, texture_size).sense(Sense::click()));
-        if response.clicked() {
-            let pointer = response.interact_pointer_pos().unwrap();
-            let position = pointer - offset;
-            if position.x >= 0.0 && position.y >= 0.0 && position.x < texture_size.x && position.y < texture_size.y {
-                *selected = (snap * (position.to_vec2() / snap).floor()).to_pos2();
+        let response = ui.add(Image::new(texture, texture_size).sense(Sense::click_and_drag()));
+        if response.clicked() || response.dragged() {
+            let snap_to_tile = |pointer: egui::Pos2| {
+                let position = (pointer - offset).clamp(egui::Pos2::ZERO, (texture_size - snap).to_pos2());
+                (snap * (position.to_vec2() / snap).floor()).to_pos2()
+            };
+
+            if let Some(pointer) = response.interact_pointer_pos() {
+                let start = ui.input().pointer.press_origin().unwrap_or(pointer);
+                let (start, end) = (snap_to_tile(start), snap_to_tile(pointer));
+
+                *selected = Rect::from_min_max(start.min(end), start.max(end) + snap);
             }
         }
 
         let painter = ui.painter();
-        let rect = Rect::from_min_size(*selected + offset, snap);
-        painter.rect_stroke(rect, 0.0, ui.visuals().window_stroke());
+        painter.rect_stroke(selected.translate(offset), 0.0, ui.visuals().window_stroke());
 
         response
     });
 }
+
 #[allow(dead_code)] // keeping it for a rainy day
 pub fn sprite_preview(ui: &mut Ui, texture: &TextureHandle, time: f64, sprite: u32) -> Response {
     let sprite_x = (sprite as f64 % 4.0) * 3.0;
@@ -5,8 +5,63 @@ use macroquad::window::screen_height;
 
 pub type ChatMessage = (ChatChannel, String);
 
+/// Which messages the chat window is showing
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum ChatTab {
+    All,
+    Say,
+    Global,
+    Whisper,
+    Party,
+    Guild,
+}
+
+impl ChatTab {
+    const ALL: [ChatTab; 6] = [
+        ChatTab::All,
+        ChatTab::Say,
+        ChatTab::Global,
+        ChatTab::Whisper,
+        ChatTab::Party,
+        ChatTab::Guild,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            ChatTab::All => "All",
+            ChatTab::Say => "Say",
+            ChatTab::Global => "Global",
+            ChatTab::Whisper => "Whisper",
+            ChatTab::Party => "Party",
+            ChatTab::Guild => "Guild",
+        }
+    }
+
+    /// The channel messages go to by default while the tab is open
+    fn channel(self) -> Option<ChatChannel> {
+        match self {
+            ChatTab::All | ChatTab::Whisper => None,
+            ChatTab::Say => Some(ChatChannel::Say),
+            ChatTab::Global => Some(ChatChannel::Global),
+            ChatTab::Party => Some(ChatChannel::Party),
+            ChatTab::Guild => Some(ChatChannel::Guild),
+        }
+    }
+
+    /// Checks if the tab shows messages from a channel, messages from the server show up everywhere
+    fn shows(self, channel: ChatChannel) -> bool {
+        match (self, channel) {
+            (ChatTab::All, _) => true,
+            (_, ChatChannel::Echo | ChatChannel::Server | ChatChannel::Error) => true,
+            (ChatTab::Whisper, ChatChannel::Whisper) => true,
+            (tab, channel) => tab.channel() == Some(channel),
+        }
+    }
+}
+
 pub struct ChatWindow {
     buffer: Vec<ChatMessage>,
+    tab: ChatTab,
     channel: ChatChannel,
     message: String,
     send_message: Option<ChatMessage>,
@@ -19,6 +74,9 @@ fn channel_info(channel: ChatChannel) -> (Color32, &'static str) {
         ChatChannel::Say => (Color32::WHITE, "Say"),
         ChatChannel::Global => (Color32::from_rgb(0x75, 0x6d, 0xd1), "Global"),
         ChatChannel::Error => (Color32::RED, "Error"),
+        ChatChannel::Whisper => (Color32::from_rgb(0xe0, 0x8c, 0xd8), "Whisper"),
+        ChatChannel::Party => (Color32::from_rgb(0x5c, 0xc8, 0xe6), "Party"),
+        ChatChannel::Guild => (Color32::from_rgb(0x6c, 0xd0, 0x6c), "Guild"),
     }
 }
 
@@ -26,6 +84,7 @@ impl ChatWindow {
     pub fn new() -> Self {
         Self {
             buffer: Vec::new(),
+            tab: ChatTab::All,
             channel: ChatChannel::Say,
             message: String::new(),
             send_message: None,
@@ -53,6 +112,17 @@ impl ChatWindow {
         let mut text: Option<Response> = None;
         let mut button: Option<Response> = None;
 
+        ui.horizontal(|ui| {
+            for tab in ChatTab::ALL {
+                if ui.selectable_value(&mut self.tab, tab, tab.name()).clicked() {
+                    if let Some(channel) = tab.channel() {
+                        self.channel = channel;
+                    }
+                }
+            }
+        });
+        ui.separator();
+
         let bottom_height = ui.spacing().interact_size.y;
         StripBuilder::new(ui)
             .size(Size::remainder().at_least(100.0))
@@ -64,7 +134,8 @@ impl ChatWindow {
                         .auto_shrink([false; 2])
                         .stick_to_bottom()
                         .show(ui, |ui| {
-                            for (channel, message) in &self.buffer {
+                            let messages = self.buffer.iter().filter(|(channel, _)| self.tab.shows(*channel));
+                            for (channel, message) in messages {
                                 self.message_ui(ui, *channel, message);
                             }
                         });
@@ -97,6 +168,16 @@ impl ChatWindow {
                                             ChatChannel::Global,
                                             channel_label(ChatChannel::Global),
                                         );
+                                        ui.selectable_value(
+                                            &mut self.channel,
+                                            ChatChannel::Party,
+                                            channel_label(ChatChannel::Party),
+                                        );
+                                        ui.selectable_value(
+                                            &mut self.channel,
+                                            ChatChannel::Guild,
+                                            channel_label(ChatChannel::Guild),
+                                        );
                                         ui.selectable_value(
                                             &mut self.channel,
                                             ChatChannel::Server,
@@ -129,7 +210,12 @@ impl ChatWindow {
             ChatChannel::Echo | ChatChannel::Error => {
                 ui.colored_label(color, message);
             }
-            ChatChannel::Server | ChatChannel::Say | ChatChannel::Global => {
+            ChatChannel::Server
+            | ChatChannel::Say
+            | ChatChannel::Global
+            | ChatChannel::Whisper
+            | ChatChannel::Party
+            | ChatChannel::Guild => {
                 ui.colored_label(color, format!("[{name}] {message}"));
             }
         };
@@ -1,13 +1,19 @@
 use std::collections::{BTreeMap, HashMap};
 
 use common::{
-    network::{MapLayer, MapSettings, TileAnimation, ZoneData},
+    network::{
+        edit::MAX_MAP_SIZE, BlockedDirections, Direction, MapLayer, MapSettings, TileAnimation, TileAttribute, ZoneData,
+    },
     TILE_SIZE,
 };
 use egui::{collapsing_header::CollapsingState, menu, Color32, DragValue, Grid, Response, TextEdit, Ui, Window};
+use ndarray::Array2;
 use strum::IntoEnumIterator;
 
-use crate::{assets::Assets, data::Tile};
+use crate::{
+    assets::Assets,
+    data::{Clipboard, CopyOptions, Tile},
+};
 
 use super::{auto_complete, tile_selector};
 
@@ -56,9 +62,24 @@ fn map_selector(ui: &mut Ui, id: &str, value: &mut String, maps: &BTreeMap<Strin
         });
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum Tool {
+    /// Places the selected tiles under the cursor
+    Pencil,
+    /// Fills a dragged out rectangle with the selected tiles
+    Rectangle,
+    /// Fills every matching tile connected to the one clicked
+    Bucket,
+    /// Selects the tile under the cursor
+    Eyedropper,
+    /// Selects part of the map to copy or cut
+    Select,
+}
+
 #[derive(Clone, Copy, PartialEq)]
 pub enum Tab {
     Tileset,
+    Attributes,
     Zones,
     Settings,
     Tools,
@@ -66,10 +87,20 @@ pub enum Tab {
 
 #[derive(Clone)]
 pub enum Wants {
-    /// Map editor wishes to exit *while* saving changes
+    /// Map editor wishes to exit *while* saving changes to the settings, everything else is saved as it's edited
     Save,
-    /// Map editor wishes to exit *without* saving changes
+    /// Map editor wishes to exit *without* saving changes to the settings
     Close,
+    /// Map editor wishes to undo the last change
+    Undo,
+    /// Map editor wishes to redo the last undone change
+    Redo,
+    /// Map editor wishes to copy the selection into the clipboard
+    Copy,
+    /// Map editor wishes to copy the selection into the clipboard and clear it
+    Cut,
+    /// Map editor wishes to start placing the clipboard
+    Paste,
     /// Map editor wishes to teleport the player to the supplied map
     Warp(String),
     /// Map editor wishes to resize the map
@@ -84,10 +115,17 @@ pub struct MapEditor {
 
     // map editor
     layer: MapLayer,
-    tile_picker: egui::Pos2,
+    tool: Tool,
+    /// Selected part of the tileset, in pixels
+    tile_picker: egui::Rect,
     is_autotile: bool,
     is_tile_animated: bool,
     tile_animation: TileAnimation,
+    copy_options: CopyOptions,
+    clipboard: Option<Clipboard>,
+
+    // attributes
+    attribute: TileAttribute,
 
     // zones
     zone_data: ZoneData,
@@ -111,7 +149,8 @@ impl MapEditor {
 
             // map editor
             layer: MapLayer::Ground,
-            tile_picker: egui::pos2(0.0, 0.0),
+            tool: Tool::Pencil,
+            tile_picker: single_tile(egui::Pos2::ZERO),
             is_autotile: false,
             is_tile_animated: false,
             tile_animation: TileAnimation {
@@ -119,6 +158,11 @@ impl MapEditor {
                 duration: 1.0,
                 bouncy: false,
             },
+            copy_options: CopyOptions::default(),
+            clipboard: None,
+
+            // attributes
+            attribute: TileAttribute::Blocked,
 
             // zones
             zone_data: ZoneData::Blocked,
@@ -172,6 +216,42 @@ impl MapEditor {
                 }
             });
             ui.menu_button("Edit", |ui| {
+                if ui.button("Undo").clicked() {
+                    self.wants = Some(Wants::Undo);
+                    ui.close_menu();
+                }
+                if ui.button("Redo").clicked() {
+                    self.wants = Some(Wants::Redo);
+                    ui.close_menu();
+                }
+                ui.separator();
+                if ui.button("Copy").clicked() {
+                    self.wants = Some(Wants::Copy);
+                    ui.close_menu();
+                }
+                if ui.button("Cut").clicked() {
+                    self.wants = Some(Wants::Cut);
+                    ui.close_menu();
+                }
+                ui.add_enabled_ui(self.clipboard.is_some(), |ui| {
+                    if ui.button("Paste").clicked() {
+                        self.wants = Some(Wants::Paste);
+                        ui.close_menu();
+                    }
+
+                    if let Some(clipboard) = &mut self.clipboard {
+                        if ui.button("Flip horizontally").clicked() {
+                            clipboard.flip_horizontal();
+                        }
+                        if ui.button("Flip vertically").clicked() {
+                            clipboard.flip_vertical();
+                        }
+                        if ui.button("Rotate clockwise").clicked() {
+                            clipboard.rotate();
+                        }
+                    }
+                });
+                ui.separator();
                 if ui.button("Fill layer").clicked() {
                     self.wants = Some(Wants::Fill(self.layer, Some(self.tile())));
                     ui.close_menu();
@@ -186,6 +266,7 @@ impl MapEditor {
             ui.separator();
 
             ui.selectable_value(&mut self.tab, Tab::Tileset, "Tileset");
+            ui.selectable_value(&mut self.tab, Tab::Attributes, "Attributes");
             ui.selectable_value(&mut self.tab, Tab::Zones, "Zones");
             ui.selectable_value(&mut self.tab, Tab::Settings, "Settings");
             ui.selectable_value(&mut self.tab, Tab::Tools, "Tools");
@@ -195,6 +276,7 @@ impl MapEditor {
 
         match self.tab {
             Tab::Tileset => self.show_tileset_tab(ui, assets),
+            Tab::Attributes => self.show_attribute_tab(ui),
             Tab::Zones => self.show_zone_tab(ui),
             Tab::Settings => self.show_settings_tab(ui, assets),
             Tab::Tools => self.show_tools_tab(ui),
@@ -202,6 +284,32 @@ impl MapEditor {
     }
 
     fn show_tileset_tab(&mut self, ui: &mut Ui, assets: &Assets) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.tool, Tool::Pencil, "✏ Pencil");
+            ui.selectable_value(&mut self.tool, Tool::Rectangle, "⬛ Rectangle");
+            ui.selectable_value(&mut self.tool, Tool::Bucket, "🌊 Bucket");
+            ui.selectable_value(&mut self.tool, Tool::Eyedropper, "💧 Eyedropper");
+            ui.selectable_value(&mut self.tool, Tool::Select, "⬚ Select");
+        });
+
+        if self.tool == Tool::Select {
+            ui.horizontal_wrapped(|ui| {
+                ui.label("Copy: ");
+                for layer in MapLayer::iter() {
+                    let mut checked = self.copy_options.layers.contains(&layer);
+                    if ui.checkbox(&mut checked, layer.to_string()).changed() {
+                        if checked {
+                            self.copy_options.layers.push(layer);
+                        } else {
+                            self.copy_options.layers.retain(|other| *other != layer);
+                        }
+                    }
+                }
+                ui.checkbox(&mut self.copy_options.attributes, "Attributes");
+                ui.checkbox(&mut self.copy_options.zones, "Zones");
+            });
+        }
+
         let id = ui.make_persistent_id("mapeditor_settings");
         CollapsingState::load_with_default_open(ui.ctx(), id, false)
             .show_header(ui, |ui| {
@@ -255,6 +363,88 @@ impl MapEditor {
         );
     }
 
+    fn show_attribute_tab(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.heading("Attribute type");
+                    let response = zone_radio(
+                        ui,
+                        matches!(self.attribute, TileAttribute::Blocked),
+                        "Blocked",
+                        "Entities are blocked from entering this tile.",
+                    );
+                    if response.clicked() {
+                        self.attribute = TileAttribute::Blocked;
+                    }
+
+                    let response = zone_radio(
+                        ui,
+                        matches!(self.attribute, TileAttribute::Directional(_)),
+                        "Directional",
+                        "Entities are blocked from entering this tile while moving in certain directions.",
+                    );
+                    if response.clicked() {
+                        self.attribute = TileAttribute::Directional(BlockedDirections::default());
+                    }
+
+                    let response = zone_radio(
+                        ui,
+                        matches!(self.attribute, TileAttribute::Water),
+                        "Water",
+                        "Deep water, entities can't walk into it.",
+                    );
+                    if response.clicked() {
+                        self.attribute = TileAttribute::Water;
+                    }
+
+                    let response = zone_radio(
+                        ui,
+                        matches!(self.attribute, TileAttribute::Ledge(_)),
+                        "Ledge",
+                        "Entities can only enter this tile while moving in one direction.",
+                    );
+                    if response.clicked() {
+                        self.attribute = TileAttribute::Ledge(Direction::South);
+                    }
+                });
+            });
+
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.heading("Attribute data");
+                    Grid::new("attribute data")
+                        .num_columns(2)
+                        .show(ui, |ui| match &mut self.attribute {
+                            TileAttribute::None | TileAttribute::Blocked | TileAttribute::Water => {
+                                ui.label("This attribute has no values");
+                            }
+                            TileAttribute::Directional(blocked) => {
+                                ui.label("Blocked moving:");
+                                ui.vertical(|ui| {
+                                    ui.checkbox(&mut blocked.north, "North");
+                                    ui.checkbox(&mut blocked.east, "East");
+                                    ui.checkbox(&mut blocked.south, "South");
+                                    ui.checkbox(&mut blocked.west, "West");
+                                });
+                            }
+                            TileAttribute::Ledge(direction) => {
+                                ui.label("Direction:");
+                                egui::ComboBox::from_id_source("ledge direction")
+                                    .selected_text(direction.to_string())
+                                    .show_ui(ui, |ui| {
+                                        ui.selectable_value(direction, Direction::North, "North");
+                                        ui.selectable_value(direction, Direction::East, "East");
+                                        ui.selectable_value(direction, Direction::South, "South");
+                                        ui.selectable_value(direction, Direction::West, "West");
+                                    });
+                            }
+                        });
+                });
+            });
+        });
+    }
+
     fn show_zone_tab(&mut self, ui: &mut Ui) {
         ui.horizontal(|ui| {
             ui.group(|ui| {
@@ -313,7 +503,6 @@ impl MapEditor {
                                         String::from("Don't change, keep movement")
                                     })
                                     .show_ui(ui, |ui| {
-                                        use common::network::Direction;
                                         ui.selectable_value(direction, None, "Don't change, keep movement");
                                         ui.selectable_value(direction, Some(Direction::North), "North");
                                         ui.selectable_value(direction, Some(Direction::East), "East");
@@ -346,7 +535,7 @@ impl MapEditor {
                         if ui.selectable_label(self.settings.tileset == tileset, tileset).clicked() {
                             self.settings.tileset = tileset.to_owned();
                             assets.set_tileset(tileset).unwrap();
-                            self.tile_picker = egui::Pos2::ZERO;
+                            self.tile_picker = single_tile(egui::Pos2::ZERO);
                             ui.close_menu();
                         }
                     }
@@ -452,7 +641,7 @@ impl MapEditor {
             ui.label("Width:");
             ui.add(
                 DragValue::new(&mut self.new_width)
-                    .clamp_range(0..=u32::MAX)
+                    .clamp_range(1..=MAX_MAP_SIZE)
                     .speed(0.05)
                     .suffix(" tiles"),
             );
@@ -461,7 +650,7 @@ impl MapEditor {
             ui.label("Height:");
             ui.add(
                 DragValue::new(&mut self.new_height)
-                    .clamp_range(0..=u32::MAX)
+                    .clamp_range(1..=MAX_MAP_SIZE)
                     .speed(0.05)
                     .suffix(" tiles"),
             );
@@ -471,7 +660,7 @@ impl MapEditor {
                 let button = ui.button("Save").on_disabled_hover_ui(|ui| {
                     ui.colored_label(
                         Color32::RED,
-                        "This will destroy tiles outside of the map and isn't reversable.",
+                        "This will destroy tiles outside of the map, undo to get them back.",
                     );
                     ui.label("Hold shift to enable the save button.");
                 });
@@ -492,11 +681,16 @@ impl MapEditor {
         self.layer
     }
 
+    pub fn tool(&self) -> Tool {
+        self.tool
+    }
+
+    /// The top left of the selected tiles
     pub fn tile(&self) -> Tile {
         Tile {
             texture: glam::ivec2(
-                self.tile_picker.x as i32 / TILE_SIZE,
-                self.tile_picker.y as i32 / TILE_SIZE,
+                self.tile_picker.min.x as i32 / TILE_SIZE,
+                self.tile_picker.min.y as i32 / TILE_SIZE,
             ),
             autotile: self.is_autotile,
             animation: if self.is_tile_animated {
@@ -507,6 +701,52 @@ impl MapEditor {
         }
     }
 
+    /// Every selected tile, laid out the same way as in the tileset
+    pub fn stamp(&self) -> Array2<Tile> {
+        let size = self.tile_picker.size() / TILE_SIZE as f32;
+        let dimensions = (size.x as usize, size.y as usize);
+        let tile = self.tile();
+
+        Array2::from_shape_fn(dimensions, |(x, y)| Tile {
+            texture: tile.texture + glam::ivec2(x as i32, y as i32),
+            ..tile
+        })
+    }
+
+    /// Selects a tile that's already on the map, along with how it's set up
+    pub fn pick(&mut self, tile: Tile) {
+        let position = egui::pos2((tile.texture.x * TILE_SIZE) as f32, (tile.texture.y * TILE_SIZE) as f32);
+
+        self.tile_picker = single_tile(position);
+        self.is_autotile = tile.autotile;
+        self.is_tile_animated = tile.animation.is_some();
+        if let Some(animation) = tile.animation {
+            self.tile_animation = animation;
+        }
+    }
+
+    /// Switches to a tool on the tileset tab
+    pub fn select_tool(&mut self, tool: Tool) {
+        self.tab = Tab::Tileset;
+        self.tool = tool;
+    }
+
+    pub fn copy_options(&self) -> &CopyOptions {
+        &self.copy_options
+    }
+
+    pub fn clipboard(&self) -> Option<&Clipboard> {
+        self.clipboard.as_ref()
+    }
+
+    pub fn set_clipboard(&mut self, clipboard: Clipboard) {
+        self.clipboard = Some(clipboard);
+    }
+
+    pub fn attribute(&self) -> TileAttribute {
+        self.attribute
+    }
+
     pub fn zone_data(&self) -> &ZoneData {
         &self.zone_data
     }
@@ -515,3 +755,7 @@ impl MapEditor {
         (&*self.id, &self.settings)
     }
 }
+
+fn single_tile(position: egui::Pos2) -> egui::Rect {
+    egui::Rect::from_min_size(position, egui::vec2(TILE_SIZE as f32, TILE_SIZE as f32))
+}
@@ -19,6 +19,54 @@ pub fn ping_pong(t: f64, frames: u16) -> u16 {
     }
 }
 
+/// Splits text into lines no wider than `max_width`, breaking between words where it can.
+///
+/// Anything past `max_lines` is cut off and replaced with "...".
+pub fn wrap_text(text: &str, max_width: f32, font: Font, font_size: u16, max_lines: usize) -> Vec<String> {
+    let fits = |line: &str| measure_text(line, Some(font), font_size, 1.0).width <= max_width;
+
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let joined = if line.is_empty() {
+            word.to_string()
+        } else {
+            format!("{line} {word}")
+        };
+
+        if fits(&joined) {
+            line = joined;
+            continue;
+        }
+
+        if !line.is_empty() {
+            lines.push(std::mem::take(&mut line));
+        }
+
+        // words too long for a line of their own are split wherever they run out of room
+        for c in word.chars() {
+            line.push(c);
+            if !fits(&line) && line.chars().count() > 1 {
+                line.pop();
+                lines.push(std::mem::replace(&mut line, c.to_string()));
+            }
+        }
+    }
+
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    if lines.len() > max_lines {
+        lines.truncate(max_lines);
+        if let Some(last) = lines.last_mut() {
+            last.push_str("...");
+        }
+    }
+
+    lines
+}
+
 pub fn draw_text_shadow(text: &str, position: Vec2, params: TextParams) {
     let shadow_position = position + glam::vec2(1.0, 1.0);
     draw_text_ex(
@@ -1,6 +1,7 @@
 use std::path::PathBuf;
 
 pub mod network;
+pub mod physics;
 
 pub const TILE_SIZE: i32 = 48;
 pub const SPRITE_SIZE: i32 = 48;
@@ -19,6 +20,24 @@ pub fn vector2(x: i32, y: i32) -> Vector2 {
     Vector2 { x, y }
 }
 
+/// Describes a length of time in seconds using its largest unit, like "3 days" or "1 minute"
+pub fn format_duration(seconds: i64) -> String {
+    const UNITS: &[(i64, &str)] = &[(7 * 86400, "week"), (86400, "day"), (3600, "hour"), (60, "minute")];
+
+    let seconds = seconds.max(1);
+    let (count, unit) = UNITS
+        .iter()
+        .find(|(length, _)| seconds >= *length)
+        .map(|(length, unit)| (seconds / length, *unit))
+        .unwrap_or((seconds, "second"));
+
+    if count == 1 {
+        format!("1 {unit}")
+    } else {
+        format!("{count} {unit}s")
+    }
+}
+
 /// Fetches the directory that should be used to store runtime data and assets
 pub fn runtime_path(manifest_dir: &str, subfolder: &str) -> PathBuf {
     if cfg!(debug_assertions) {
@@ -1,13 +1,81 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{collections::HashMap, error::Error, fmt::Display};
 
 use crc::{Crc, CRC_32_CKSUM};
 use mint::{Point2, Vector2};
 use ndarray::Array2;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use strum::{EnumCount, EnumIter, IntoEnumIterator};
 
 pub mod client;
+pub mod edit;
 pub mod server;
+pub mod snapshot;
+
+/// Version of the network protocol, bump this whenever a packet changes in any way.
+///
+/// Clients and servers only talk to each other if their versions match exactly.
+pub const PROTOCOL_VERSION: u32 = 14;
+
+/// Largest packet the server will decode from a client, in bytes. Nothing a client sends needs to be big,
+/// map edits are split across batches to stay under it.
+pub const MAX_CLIENT_PACKET_SIZE: usize = 64 * 1024;
+
+/// Largest packet a client will decode from the server, in bytes. Whole maps are sent as one packet so this is
+/// fairly generous.
+pub const MAX_SERVER_PACKET_SIZE: usize = 8 * 1024 * 1024;
+
+/// A packet that can be decoded from untrusted bytes.
+///
+/// ! message-io's FramedTcp buffers a whole frame before handing it over and has no setting to cap how big one can
+/// ! get, so the size can't be checked any earlier than this without replacing it with our own framing over plain TCP.
+pub trait Decode: DeserializeOwned {
+    /// Largest encoding that will be decoded, in bytes
+    const MAX_SIZE: usize;
+}
+
+impl Decode for client::Packet {
+    const MAX_SIZE: usize = MAX_CLIENT_PACKET_SIZE;
+}
+
+impl Decode for server::Packet {
+    const MAX_SIZE: usize = MAX_SERVER_PACKET_SIZE;
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    TooLarge { size: usize, max: usize },
+    Malformed(rmp_serde::decode::Error),
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::TooLarge { size, max } => write!(f, "packet is too large ({size} bytes, max {max})"),
+            DecodeError::Malformed(e) => write!(f, "packet is malformed: {e}"),
+        }
+    }
+}
+
+impl Error for DecodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DecodeError::TooLarge { .. } => None,
+            DecodeError::Malformed(e) => Some(e),
+        }
+    }
+}
+
+/// Decodes a packet from untrusted bytes, this never panics no matter what it's given
+pub fn decode<T: Decode>(bytes: &[u8]) -> Result<T, DecodeError> {
+    if bytes.len() > T::MAX_SIZE {
+        return Err(DecodeError::TooLarge {
+            size: bytes.len(),
+            max: T::MAX_SIZE,
+        });
+    }
+
+    rmp_serde::from_slice(bytes).map_err(DecodeError::Malformed)
+}
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Eq, Hash, Clone, Copy)]
 #[serde(transparent)]
@@ -19,7 +87,7 @@ impl From<u64> for ClientId {
     }
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Copy, Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
 pub enum Direction {
     South,
     West,
@@ -100,6 +168,12 @@ pub enum ChatChannel {
     Say,
     Global,
     Error,
+    /// Private messages between two players
+    Whisper,
+    /// Everyone in the speaker's party
+    Party,
+    /// Everyone in the speaker's guild
+    Guild,
 }
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
@@ -159,6 +233,9 @@ pub struct Map {
     pub height: u32,
     pub settings: MapSettings,
     pub layers: HashMap<MapLayer, Array2<Option<Tile>>>,
+    /// Collision attributes of every tile, maps saved before these existed have an empty grid
+    #[serde(default)]
+    pub attributes: Array2<TileAttribute>,
     pub zones: Vec<Zone>,
 }
 
@@ -180,6 +257,7 @@ impl Map {
             height,
             settings,
             layers,
+            attributes: Array2::default((width as usize, height as usize)),
             zones,
         }
     }
@@ -250,6 +328,68 @@ impl TileAnimation {
     }
 }
 
+/// What happens when something tries to walk into a tile
+#[derive(Copy, Clone, Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+pub enum TileAttribute {
+    #[default]
+    None,
+    /// Nothing can walk into this tile
+    Blocked,
+    /// Can't be walked into while moving in any of the marked directions
+    Directional(BlockedDirections),
+    /// Deep water, nothing can walk into it until there's a way to swim
+    Water,
+    /// Can only be walked into while moving in this direction, like jumping off of a ledge
+    Ledge(Direction),
+}
+
+impl TileAttribute {
+    /// Checks if this tile stops something moving in `direction` from walking into it
+    pub fn blocks(&self, direction: Direction) -> bool {
+        match self {
+            TileAttribute::None => false,
+            TileAttribute::Blocked | TileAttribute::Water => true,
+            TileAttribute::Directional(blocked) => blocked.contains(direction),
+            TileAttribute::Ledge(allowed) => *allowed != direction,
+        }
+    }
+
+    /// Checks if this tile stops movement in every direction
+    pub fn is_solid(&self) -> bool {
+        matches!(self, TileAttribute::Blocked | TileAttribute::Water)
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            TileAttribute::None => "None",
+            TileAttribute::Blocked => "Blocked",
+            TileAttribute::Directional(_) => "Directional",
+            TileAttribute::Water => "Water",
+            TileAttribute::Ledge(_) => "Ledge",
+        }
+    }
+}
+
+/// Directions a tile can't be walked into while moving in
+#[derive(Copy, Clone, Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+pub struct BlockedDirections {
+    pub north: bool,
+    pub east: bool,
+    pub south: bool,
+    pub west: bool,
+}
+
+impl BlockedDirections {
+    pub fn contains(&self, direction: Direction) -> bool {
+        match direction {
+            Direction::North => self.north,
+            Direction::East => self.east,
+            Direction::South => self.south,
+            Direction::West => self.west,
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
 pub enum ZoneData {
     Blocked,
@@ -271,3 +411,140 @@ pub struct Zone {
     pub size: Vector2<f32>,
     pub data: ZoneData,
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::{collection::vec, prelude::*, sample::Index};
+
+    use super::*;
+
+    /// A valid encoding of a few of each kind of packet, for the fuzz tests to mangle
+    fn samples() -> Vec<Vec<u8>> {
+        let client_packets = [
+            client::Packet::Hello {
+                protocol_version: PROTOCOL_VERSION,
+                client_version: String::from("0.1.0"),
+            },
+            client::Packet::Login {
+                username: String::from("username"),
+                password: String::from("password"),
+            },
+            client::Packet::Move {
+                sequence: 12,
+                position: Point2 { x: 96.0, y: 144.0 },
+                direction: Direction::East,
+                velocity: Some(Vector2 { x: 120.0, y: 0.0 }),
+            },
+            client::Packet::ChatMessage(ChatChannel::Say, String::from("hello")),
+            client::Packet::EditMap {
+                batch: 7,
+                revision: 3,
+                edits: vec![
+                    edit::MapEdit::FillRegion {
+                        layer: MapLayer::Ground,
+                        from: Point2 { x: 0, y: 0 },
+                        to: Point2 { x: 4, y: 4 },
+                        tile: None,
+                    },
+                    edit::MapEdit::SetAttribute {
+                        position: Point2 { x: 2, y: 3 },
+                        attribute: TileAttribute::Ledge(Direction::South),
+                    },
+                ],
+            },
+        ];
+
+        let server_packets = [
+            server::Packet::ChatLog(ChatChannel::Global, String::from("someone: hello")),
+            server::Packet::FailedJoin(server::FailJoinReason::Banned {
+                reason: Some(String::from("spamming")),
+                until: Some(1_700_000_000),
+            }),
+            server::Packet::PlayerData(
+                ClientId(4),
+                Player {
+                    name: String::from("Someone"),
+                    position: Point2 { x: 48.0, y: 48.0 },
+                    velocity: None,
+                    sprite: 3,
+                    direction: Direction::North,
+                    flags: PlayerFlags::default(),
+                },
+            ),
+        ];
+
+        let client_packets = client_packets.iter().map(|packet| rmp_serde::to_vec(packet).unwrap());
+        let server_packets = server_packets.iter().map(|packet| rmp_serde::to_vec(packet).unwrap());
+
+        client_packets.chain(server_packets).collect()
+    }
+
+    /// Decodes bytes as every kind of packet, the results don't matter as long as nothing panics
+    fn decode_all(bytes: &[u8]) {
+        let _ = decode::<client::Packet>(bytes);
+        let _ = decode::<server::Packet>(bytes);
+    }
+
+    proptest! {
+        #[test]
+        fn decoding_random_bytes_never_panics(bytes in vec(any::<u8>(), 0..1024)) {
+            decode_all(&bytes);
+        }
+
+        #[test]
+        fn decoding_corrupted_packets_never_panics(
+            sample in any::<Index>(),
+            changes in vec((any::<Index>(), any::<u8>()), 1..8),
+            length in any::<Index>(),
+        ) {
+            let samples = samples();
+            let mut bytes = sample.get(&samples).clone();
+
+            for (index, byte) in changes {
+                *index.get_mut(&mut bytes) = byte;
+            }
+            bytes.truncate(length.index(bytes.len() + 1));
+
+            decode_all(&bytes);
+        }
+
+        #[test]
+        fn chat_messages_round_trip(message in ".*") {
+            let packet = client::Packet::ChatMessage(ChatChannel::Say, message);
+            let bytes = rmp_serde::to_vec(&packet).unwrap();
+
+            prop_assert_eq!(decode::<client::Packet>(&bytes).unwrap(), packet);
+        }
+    }
+
+    #[test]
+    fn samples_decode() {
+        for bytes in samples() {
+            let client = decode::<client::Packet>(&bytes);
+            let server = decode::<server::Packet>(&bytes);
+            assert!(client.is_ok() || server.is_ok());
+        }
+    }
+
+    #[test]
+    fn oversized_packets_are_rejected() {
+        let bytes = vec![0; MAX_CLIENT_PACKET_SIZE + 1];
+        assert!(matches!(
+            decode::<client::Packet>(&bytes),
+            Err(DecodeError::TooLarge { size, max: MAX_CLIENT_PACKET_SIZE }) if size == MAX_CLIENT_PACKET_SIZE + 1
+        ));
+        assert!(matches!(
+            decode::<server::Packet>(&bytes),
+            Err(DecodeError::Malformed(_))
+        ));
+
+        let bytes = vec![0; MAX_SERVER_PACKET_SIZE + 1];
+        assert!(matches!(
+            decode::<server::Packet>(&bytes),
+            Err(DecodeError::TooLarge {
+                max: MAX_SERVER_PACKET_SIZE,
+                ..
+            })
+        ));
+    }
+}
@@ -1,10 +1,20 @@
 use mint::{Point2, Vector2};
 use serde::{Deserialize, Serialize};
 
-use super::{ChatChannel, Direction, Map};
+use super::{
+    edit::{EditorCursor, MapEdit},
+    ChatChannel, Direction,
+};
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
 pub enum Packet {
+    /// Always the first packet a client sends.
+    ///
+    /// ! This must stay the first variant and never change, or older clients can't be told to update.
+    Hello {
+        protocol_version: u32,
+        client_version: String,
+    },
     CreateAccount {
         username: String,
         password: String,
@@ -15,13 +25,26 @@ pub enum Packet {
         password: String,
     },
     Move {
+        /// Increases by one for every move sent, so the server can tell us which ones it's processed
+        sequence: u32,
         position: Point2<f32>,
         direction: Direction,
         velocity: Option<Vector2<f32>>,
     },
     ChatMessage(ChatChannel, String),
     RequestMap,
-    SaveMap(Box<Map>),
+    /// Changes made in the map editor, applied in order
+    EditMap {
+        /// Counts up by one for every batch sent, so the echo can be matched up with it
+        batch: u32,
+        /// The last revision of the map we'd heard about when making these
+        revision: u32,
+        edits: Vec<MapEdit>,
+    },
+    /// Where we are in the map editor, `None` once we've stopped editing
+    EditorCursor(Option<EditorCursor>),
     Warp(String, Option<Point2<f32>>),
     MapEditor(bool),
+    /// Lets the server know which snapshot it can encode the next ones against
+    AckSnapshot(u32),
 }
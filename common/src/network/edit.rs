@@ -0,0 +1,325 @@
+use std::{error::Error, fmt::Display};
+
+use mint::Point2;
+use serde::{Deserialize, Serialize};
+
+use super::{MapLayer, MapSettings, Tile, TileAttribute, Zone};
+use crate::{physics::Rect, TILE_SIZE};
+
+/// Largest width or height a map can be resized to, in tiles
+pub const MAX_MAP_SIZE: u32 = 256;
+
+/// A single change to a map, sent instead of the whole map so edits stay small
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+pub enum MapEdit {
+    SetTile {
+        layer: MapLayer,
+        position: Point2<u32>,
+        tile: Tile,
+    },
+    ClearTile {
+        layer: MapLayer,
+        position: Point2<u32>,
+    },
+    /// Sets a batch of tiles at once, clearing the ones that are `None`
+    SetTiles {
+        layer: MapLayer,
+        tiles: Vec<(Point2<u32>, Option<Tile>)>,
+    },
+    /// Sets every tile from `from` to `to` inclusive, or clears them if `tile` is `None`
+    FillRegion {
+        layer: MapLayer,
+        from: Point2<u32>,
+        to: Point2<u32>,
+        tile: Option<Tile>,
+    },
+    SetAttribute {
+        position: Point2<u32>,
+        attribute: TileAttribute,
+    },
+    AddZone(Zone),
+    /// Removes a zone by its index, every zone after it moves down by one
+    RemoveZone(usize),
+    /// Puts a zone at an index, every zone from there on moves up by one. Used to undo removing a zone.
+    InsertZone(usize, Zone),
+    ModifyZone(usize, Zone),
+    /// Replaces the map's settings, apart from the cache key which only the server sets
+    ChangeSettings(MapSettings),
+    /// Resizes the map, keeping whatever still fits and zones that still overlap it
+    Resize {
+        width: u32,
+        height: u32,
+    },
+}
+
+impl MapEdit {
+    /// Checks that the edit makes sense for a map of the given size with `zones` zones
+    pub fn validate(&self, width: u32, height: u32, zones: usize) -> Result<(), EditError> {
+        let in_bounds = |position: &Point2<u32>| position.x < width && position.y < height;
+
+        match self {
+            MapEdit::SetTile { position, .. }
+            | MapEdit::ClearTile { position, .. }
+            | MapEdit::SetAttribute { position, .. } => {
+                if !in_bounds(position) {
+                    return Err(EditError::OutOfBounds);
+                }
+            }
+            MapEdit::SetTiles { tiles, .. } => {
+                if !tiles.iter().all(|(position, _)| in_bounds(position)) {
+                    return Err(EditError::OutOfBounds);
+                }
+            }
+            MapEdit::FillRegion { from, to, .. } => {
+                if !in_bounds(from) || !in_bounds(to) || from.x > to.x || from.y > to.y {
+                    return Err(EditError::OutOfBounds);
+                }
+            }
+            MapEdit::AddZone(zone) => validate_zone(zone, width, height)?,
+            MapEdit::RemoveZone(index) => {
+                if *index >= zones {
+                    return Err(EditError::NoSuchZone(*index));
+                }
+            }
+            MapEdit::InsertZone(index, zone) => {
+                if *index > zones {
+                    return Err(EditError::NoSuchZone(*index));
+                }
+                validate_zone(zone, width, height)?;
+            }
+            MapEdit::ModifyZone(index, zone) => {
+                if *index >= zones {
+                    return Err(EditError::NoSuchZone(*index));
+                }
+                validate_zone(zone, width, height)?;
+            }
+            MapEdit::ChangeSettings(_) => (),
+            &MapEdit::Resize { width, height } => {
+                if !(1..=MAX_MAP_SIZE).contains(&width) || !(1..=MAX_MAP_SIZE).contains(&height) {
+                    return Err(EditError::InvalidSize { width, height });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adjusts an edit made without knowing about `earlier`, so it can be applied after it.
+    ///
+    /// Returns `None` if `earlier` got rid of whatever this edit was changing.
+    pub fn rebase(&self, earlier: &MapEdit) -> Option<MapEdit> {
+        let index = match self {
+            MapEdit::RemoveZone(index) | MapEdit::InsertZone(index, _) | MapEdit::ModifyZone(index, _) => *index,
+            // everything else is addressed by position, last one in wins
+            edit => return Some(edit.clone()),
+        };
+
+        let index = match earlier {
+            // putting a zone back where one was removed is still fine
+            MapEdit::RemoveZone(removed) if *removed == index && !matches!(self, MapEdit::InsertZone(..)) => {
+                return None
+            }
+            MapEdit::RemoveZone(removed) if *removed < index => index - 1,
+            MapEdit::InsertZone(inserted, _) if *inserted <= index => index + 1,
+            // there's no telling which zones a resize dropped without the map
+            MapEdit::Resize { .. } => return None,
+            _ => index,
+        };
+
+        match self {
+            MapEdit::RemoveZone(_) => Some(MapEdit::RemoveZone(index)),
+            MapEdit::InsertZone(_, zone) => Some(MapEdit::InsertZone(index, zone.clone())),
+            MapEdit::ModifyZone(_, zone) => Some(MapEdit::ModifyZone(index, zone.clone())),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Checks if the edit adds, removes or moves any zones
+    pub fn changes_zones(&self) -> bool {
+        matches!(
+            self,
+            MapEdit::AddZone(_)
+                | MapEdit::RemoveZone(_)
+                | MapEdit::InsertZone(_, _)
+                | MapEdit::ModifyZone(_, _)
+                | MapEdit::Resize { .. }
+        )
+    }
+
+    /// Checks if the edit changes any tiles, so autotiles need to be worked out again
+    pub fn changes_tiles(&self) -> bool {
+        matches!(
+            self,
+            MapEdit::SetTile { .. }
+                | MapEdit::ClearTile { .. }
+                | MapEdit::SetTiles { .. }
+                | MapEdit::FillRegion { .. }
+                | MapEdit::Resize { .. }
+        )
+    }
+}
+
+/// Where someone in the map editor is pointing and what they'd place there, shown to the other editors
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+pub struct EditorCursor {
+    pub position: Point2<f32>,
+    pub layer: MapLayer,
+    /// The selected tile, `None` when they aren't placing tiles
+    pub tile: Option<Tile>,
+}
+
+/// Checks that a zone has a real size and is entirely on a map of the given size
+fn validate_zone(zone: &Zone, width: u32, height: u32) -> Result<(), EditError> {
+    let finite = zone.position.x.is_finite()
+        && zone.position.y.is_finite()
+        && zone.size.x.is_finite()
+        && zone.size.y.is_finite();
+
+    let bounds = Rect::new(
+        0.0,
+        0.0,
+        width as f32 * TILE_SIZE as f32,
+        height as f32 * TILE_SIZE as f32,
+    );
+
+    if finite && zone.size.x > 0.0 && zone.size.y > 0.0 && bounds.contains(&Rect::from(zone)) {
+        Ok(())
+    } else {
+        Err(EditError::InvalidZone)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EditError {
+    OutOfBounds,
+    NoSuchZone(usize),
+    InvalidZone,
+    InvalidSize { width: u32, height: u32 },
+}
+
+impl Display for EditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EditError::OutOfBounds => write!(f, "edit is outside of the map"),
+            EditError::NoSuchZone(index) => write!(f, "there is no zone {index}"),
+            EditError::InvalidZone => write!(f, "zone has an invalid size or isn't on the map"),
+            EditError::InvalidSize { width, height } => write!(
+                f,
+                "maps must be between 1x1 and {MAX_MAP_SIZE}x{MAX_MAP_SIZE} tiles, not {width}x{height}"
+            ),
+        }
+    }
+}
+
+impl Error for EditError {}
+
+#[cfg(test)]
+mod tests {
+    use mint::Vector2;
+
+    use super::*;
+    use crate::network::ZoneData;
+
+    fn zone(x: f32, y: f32, w: f32, h: f32) -> Zone {
+        Zone {
+            position: Point2 { x, y },
+            size: Vector2 { x: w, y: h },
+            data: ZoneData::Blocked,
+        }
+    }
+
+    fn tile_edit() -> MapEdit {
+        MapEdit::ClearTile {
+            layer: MapLayer::Ground,
+            position: Point2 { x: 1, y: 2 },
+        }
+    }
+
+    #[test]
+    fn zones_have_to_be_on_the_map() {
+        let edge = 10.0 * TILE_SIZE as f32;
+        let validate = |zone| MapEdit::AddZone(zone).validate(10, 10, 0);
+
+        assert_eq!(validate(zone(0.0, 0.0, edge, edge)), Ok(()));
+        assert_eq!(validate(zone(edge - 6.0, edge - 6.0, 6.0, 6.0)), Ok(()));
+
+        assert_eq!(validate(zone(-1.0, 0.0, 6.0, 6.0)), Err(EditError::InvalidZone));
+        assert_eq!(validate(zone(edge - 5.0, 0.0, 6.0, 6.0)), Err(EditError::InvalidZone));
+        assert_eq!(validate(zone(0.0, 0.0, 1e30, 1e30)), Err(EditError::InvalidZone));
+        assert_eq!(validate(zone(0.0, 0.0, 0.0, 6.0)), Err(EditError::InvalidZone));
+        assert_eq!(validate(zone(f32::NAN, 0.0, 6.0, 6.0)), Err(EditError::InvalidZone));
+    }
+
+    #[test]
+    fn removing_a_zone_moves_later_indices_down() {
+        let earlier = MapEdit::RemoveZone(1);
+
+        assert_eq!(MapEdit::RemoveZone(3).rebase(&earlier), Some(MapEdit::RemoveZone(2)));
+        assert_eq!(
+            MapEdit::ModifyZone(2, zone(0.0, 0.0, 6.0, 6.0)).rebase(&earlier),
+            Some(MapEdit::ModifyZone(1, zone(0.0, 0.0, 6.0, 6.0)))
+        );
+        assert_eq!(MapEdit::RemoveZone(0).rebase(&earlier), Some(MapEdit::RemoveZone(0)));
+    }
+
+    #[test]
+    fn edits_to_a_removed_zone_are_dropped() {
+        let earlier = MapEdit::RemoveZone(1);
+
+        assert_eq!(MapEdit::RemoveZone(1).rebase(&earlier), None);
+        assert_eq!(MapEdit::ModifyZone(1, zone(0.0, 0.0, 6.0, 6.0)).rebase(&earlier), None);
+
+        // putting one back in the same place is still fine
+        let insert = MapEdit::InsertZone(1, zone(0.0, 0.0, 6.0, 6.0));
+        assert_eq!(insert.rebase(&earlier), Some(insert));
+    }
+
+    #[test]
+    fn inserting_a_zone_moves_indices_from_there_up() {
+        let earlier = MapEdit::InsertZone(1, zone(0.0, 0.0, 6.0, 6.0));
+
+        assert_eq!(MapEdit::RemoveZone(1).rebase(&earlier), Some(MapEdit::RemoveZone(2)));
+        assert_eq!(MapEdit::RemoveZone(4).rebase(&earlier), Some(MapEdit::RemoveZone(5)));
+        assert_eq!(MapEdit::RemoveZone(0).rebase(&earlier), Some(MapEdit::RemoveZone(0)));
+        assert_eq!(
+            MapEdit::InsertZone(1, zone(6.0, 0.0, 6.0, 6.0)).rebase(&earlier),
+            Some(MapEdit::InsertZone(2, zone(6.0, 0.0, 6.0, 6.0)))
+        );
+    }
+
+    #[test]
+    fn zone_edits_after_a_resize_are_dropped() {
+        let earlier = MapEdit::Resize { width: 5, height: 5 };
+
+        assert_eq!(MapEdit::RemoveZone(0).rebase(&earlier), None);
+        assert_eq!(MapEdit::ModifyZone(0, zone(0.0, 0.0, 6.0, 6.0)).rebase(&earlier), None);
+        assert_eq!(MapEdit::InsertZone(0, zone(0.0, 0.0, 6.0, 6.0)).rebase(&earlier), None);
+    }
+
+    #[test]
+    fn edits_by_position_are_never_adjusted() {
+        let earlier = [
+            MapEdit::RemoveZone(0),
+            MapEdit::InsertZone(0, zone(0.0, 0.0, 6.0, 6.0)),
+            MapEdit::Resize { width: 5, height: 5 },
+            tile_edit(),
+        ];
+
+        for earlier in &earlier {
+            assert_eq!(tile_edit().rebase(earlier), Some(tile_edit()));
+            assert_eq!(
+                MapEdit::AddZone(zone(0.0, 0.0, 6.0, 6.0)).rebase(earlier),
+                Some(MapEdit::AddZone(zone(0.0, 0.0, 6.0, 6.0)))
+            );
+        }
+    }
+
+    #[test]
+    fn modified_zones_have_to_be_on_the_map() {
+        let edit = MapEdit::ModifyZone(0, zone(0.0, 0.0, 1e30, 6.0));
+        assert_eq!(edit.validate(10, 10, 1), Err(EditError::InvalidZone));
+
+        let edit = MapEdit::InsertZone(0, zone(0.0, 0.0, 1e30, 6.0));
+        assert_eq!(edit.validate(10, 10, 0), Err(EditError::InvalidZone));
+    }
+}
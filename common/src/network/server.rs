@@ -1,12 +1,27 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use mint::{Point2, Vector2};
 use serde::{Deserialize, Serialize};
 
-use super::{ChatChannel, ClientId, Direction, Map, MapHash, MapSettings, Player, PlayerFlags};
+use super::{
+    edit::{EditorCursor, MapEdit},
+    snapshot::Snapshot,
+    ChatChannel, ClientId, Direction, Map, MapHash, MapSettings, Player, PlayerFlags,
+};
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
 pub enum Packet {
+    /// Reply to the client's hello, sent even if the versions don't match.
+    ///
+    /// ! This must stay the first variant and never change, or older clients can't be told to update.
+    Hello {
+        protocol_version: u32,
+        server_version: String,
+    },
     JoinGame(ClientId),
     FailedJoin(FailJoinReason),
     PlayerData(ClientId, Player),
@@ -16,10 +31,28 @@ pub enum Packet {
         position: Point2<f32>,
         direction: Direction,
         velocity: Option<Vector2<f32>>,
+        /// The last move the player sent that the server has processed
+        sequence: u32,
     },
     ChatLog(ChatChannel, String),
-    ChangeMap(MapHash, i64),
-    MapData(Box<Map>),
+    /// Something a player said in say chat, shown in a bubble over their head for a while
+    SpeechBubble(ClientId, String),
+    /// The map a player is now on, with its cache key and current revision
+    ChangeMap(MapHash, i64, u32),
+    MapData(Box<Map>, u32),
+    /// Changes made to the current map, applied in order.
+    ///
+    /// Edits are echoed back to whoever made them, possibly adjusted for what others did first, or empty if none of them applied.
+    MapEdits {
+        /// Revision of the map after these edits
+        revision: u32,
+        author: ClientId,
+        /// The author's number for the batch these came from
+        batch: u32,
+        edits: Vec<MapEdit>,
+    },
+    /// Where another player is in the map editor, `None` once they've stopped editing
+    EditorCursor(ClientId, Option<EditorCursor>),
     MapEditor {
         maps: HashMap<String, String>,
         id: String,
@@ -28,13 +61,42 @@ pub enum Packet {
         settings: MapSettings,
     },
     Flags(ClientId, PlayerFlags),
+    Shutdown {
+        reason: String,
+    },
+    /// A moderator removed the player from the game, sent right before they're disconnected
+    Kicked {
+        reason: String,
+    },
+    /// Movement of nearby players, only sent when the server has snapshots turned on
+    Snapshot(Snapshot),
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
 pub enum FailJoinReason {
     UsernameTaken,
     CharacterNameTaken,
     LoginIncorrect,
+    UsernameLength {
+        min: usize,
+        max: usize,
+    },
+    UsernameInvalid,
+    CharacterNameLength {
+        min: usize,
+        max: usize,
+    },
+    CharacterNameInvalid,
+    NameReserved,
+    VersionMismatch {
+        server: u32,
+        client: u32,
+    },
+    /// The account or address is banned, `until` is a unix timestamp or `None` if it's permanent
+    Banned {
+        reason: Option<String>,
+        until: Option<i64>,
+    },
 }
 
 impl Display for FailJoinReason {
@@ -43,6 +105,41 @@ impl Display for FailJoinReason {
             FailJoinReason::UsernameTaken => write!(f, "username is taken"),
             FailJoinReason::CharacterNameTaken => write!(f, "character name is taken"),
             FailJoinReason::LoginIncorrect => write!(f, "username/password is incorrect"),
+            FailJoinReason::UsernameLength { min, max } => {
+                write!(f, "username must be between {min} and {max} characters")
+            }
+            FailJoinReason::UsernameInvalid => {
+                write!(f, "username may only contain letters, numbers and underscores")
+            }
+            FailJoinReason::CharacterNameLength { min, max } => {
+                write!(f, "character name must be between {min} and {max} characters")
+            }
+            FailJoinReason::CharacterNameInvalid => {
+                write!(f, "character name may only contain letters, numbers and single spaces")
+            }
+            FailJoinReason::NameReserved => write!(f, "that name is reserved"),
+            FailJoinReason::VersionMismatch { server, client } => write!(
+                f,
+                "the server uses protocol version {server} but this client uses {client}, please update"
+            ),
+            FailJoinReason::Banned { reason, until } => {
+                match until {
+                    Some(until) => {
+                        let now = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .map(|now| now.as_secs() as i64)
+                            .unwrap_or_default();
+
+                        write!(f, "you are banned for {}", crate::format_duration(until - now))?;
+                    }
+                    None => write!(f, "you are banned")?,
+                }
+
+                match reason {
+                    Some(reason) => write!(f, ": {reason}"),
+                    None => Ok(()),
+                }
+            }
         }
     }
 }
@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+
+use mint::{Point2, Vector2};
+use serde::{Deserialize, Serialize};
+
+use super::{ClientId, Direction};
+
+/// Positions are sent in fractions of a pixel, this is how many make up one
+pub const POSITION_SCALE: f32 = 8.0;
+
+/// Quantized state of a single player, what snapshots are built from
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct PlayerState {
+    pub position: Point2<i32>,
+    pub direction: Direction,
+    /// Velocity in whole pixels per second
+    pub velocity: Option<Vector2<i16>>,
+}
+
+impl PlayerState {
+    pub fn new(position: Point2<f32>, direction: Direction, velocity: Option<Vector2<f32>>) -> Self {
+        Self {
+            position: Point2 {
+                x: (position.x * POSITION_SCALE).round() as i32,
+                y: (position.y * POSITION_SCALE).round() as i32,
+            },
+            direction,
+            velocity: velocity.map(|velocity| Vector2 {
+                x: velocity.x.round() as i16,
+                y: velocity.y.round() as i16,
+            }),
+        }
+    }
+
+    pub fn position(&self) -> Point2<f32> {
+        Point2 {
+            x: self.position.x as f32 / POSITION_SCALE,
+            y: self.position.y as f32 / POSITION_SCALE,
+        }
+    }
+
+    pub fn velocity(&self) -> Option<Vector2<f32>> {
+        self.velocity.map(|velocity| Vector2 {
+            x: velocity.x as f32,
+            y: velocity.y as f32,
+        })
+    }
+
+    /// Whether a client that last saw this needs to be told about `current`. Moving players are left for the
+    /// client to extrapolate until they change speed or direction, the same as with a packet per move.
+    pub fn needs_update(&self, current: &PlayerState) -> bool {
+        self.direction != current.direction
+            || self.velocity != current.velocity
+            || (self.velocity.is_none() && self.position != current.position)
+    }
+}
+
+/// Every player in a snapshot, by who they are
+pub type SnapshotState = HashMap<ClientId, PlayerState>;
+
+/// Whether anyone in `state` needs an update since `previous`, or has come or gone
+pub fn changed(previous: &SnapshotState, state: &SnapshotState) -> bool {
+    previous.len() != state.len()
+        || state.iter().any(|(client_id, current)| {
+            previous
+                .get(client_id)
+                .is_none_or(|previous| previous.needs_update(current))
+        })
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub enum SnapshotPosition {
+    Absolute(Point2<i32>),
+    /// Offset from the player's position in the baseline, used when it's small enough to fit
+    Delta(Vector2<i16>),
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct SnapshotPlayer {
+    pub client_id: ClientId,
+    pub position: SnapshotPosition,
+    pub direction: Direction,
+    pub velocity: Option<Vector2<i16>>,
+}
+
+/// Movement of every player a client can see, sent in one packet per update instead of one per change
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct Snapshot {
+    pub sequence: u32,
+    /// The acknowledged snapshot this one is relative to, players that don't need an update since it are left out
+    pub baseline: Option<u32>,
+    pub players: Vec<SnapshotPlayer>,
+    /// Players in the baseline that aren't in this snapshot anymore
+    pub removed: Vec<ClientId>,
+}
+
+impl Snapshot {
+    /// Encodes everything in `state` that's changed since `baseline`. Players that are left out keep their baseline
+    /// position when decoded, so the state to encode the next snapshot against is this one decoded, not `state`.
+    pub fn encode(sequence: u32, state: &SnapshotState, baseline: Option<(u32, &SnapshotState)>) -> Self {
+        let players = state
+            .iter()
+            .filter_map(|(&client_id, current)| {
+                let previous = baseline.and_then(|(_, baseline)| baseline.get(&client_id));
+
+                if previous.is_some_and(|previous| !previous.needs_update(current)) {
+                    return None;
+                }
+
+                let delta = previous.and_then(|previous| {
+                    let x = i16::try_from(current.position.x - previous.position.x).ok()?;
+                    let y = i16::try_from(current.position.y - previous.position.y).ok()?;
+                    Some(Vector2 { x, y })
+                });
+
+                Some(SnapshotPlayer {
+                    client_id,
+                    position: match delta {
+                        Some(delta) => SnapshotPosition::Delta(delta),
+                        None => SnapshotPosition::Absolute(current.position),
+                    },
+                    direction: current.direction,
+                    velocity: current.velocity,
+                })
+            })
+            .collect();
+
+        let removed = baseline
+            .map(|(_, baseline)| {
+                baseline
+                    .keys()
+                    .filter(|client_id| !state.contains_key(client_id))
+                    .copied()
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            sequence,
+            baseline: baseline.map(|(sequence, _)| sequence),
+            players,
+            removed,
+        }
+    }
+
+    /// Whether decoding this wouldn't change anything
+    pub fn is_empty(&self) -> bool {
+        self.players.is_empty() && self.removed.is_empty()
+    }
+
+    /// Rebuilds the full state from the baseline it was encoded against,
+    /// returns `None` if it refers to a player the baseline doesn't have.
+    pub fn decode(&self, baseline: Option<&SnapshotState>) -> Option<SnapshotState> {
+        let mut state = baseline.cloned().unwrap_or_default();
+        for client_id in &self.removed {
+            state.remove(client_id);
+        }
+
+        for player in &self.players {
+            let position = match player.position {
+                SnapshotPosition::Absolute(position) => position,
+                SnapshotPosition::Delta(delta) => {
+                    let previous = baseline?.get(&player.client_id)?.position;
+                    Point2 {
+                        x: previous.x + delta.x as i32,
+                        y: previous.y + delta.y as i32,
+                    }
+                }
+            };
+
+            state.insert(
+                player.client_id,
+                PlayerState {
+                    position,
+                    direction: player.direction,
+                    velocity: player.velocity,
+                },
+            );
+        }
+
+        Some(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::server::Packet;
+
+    fn player(x: f32, y: f32, velocity: Option<Vector2<f32>>) -> PlayerState {
+        PlayerState::new(Point2 { x, y }, Direction::South, velocity)
+    }
+
+    /// Everyone walking along their own row, turning around every `turn_every` updates
+    fn walking(players: u64, update: u32, turn_every: u32) -> SnapshotState {
+        let step = if update % (turn_every * 2) < turn_every {
+            6.0
+        } else {
+            -6.0
+        };
+        let offset = (update % turn_every) as f32 * step;
+
+        (0..players)
+            .map(|client_id| {
+                let state = player(
+                    100.0 + offset,
+                    client_id as f32 * 48.0,
+                    Some(Vector2 { x: step * 20.0, y: 0.0 }),
+                );
+                (ClientId(client_id), state)
+            })
+            .collect()
+    }
+
+    /// Bytes sent to one client over `updates` updates: a packet per changed player, or a snapshot per update
+    fn bytes_sent(players: u64, updates: u32, turn_every: u32) -> (usize, usize) {
+        let mut per_move = 0;
+        let mut snapshots = 0;
+        let mut last: Option<(u32, SnapshotState)> = None;
+
+        for update in 0..updates {
+            let state = walking(players, update, turn_every);
+
+            // either way the server only sends anything when someone starts, stops or turns
+            if update % turn_every == 0 {
+                for (client_id, player) in &state {
+                    let packet = Packet::PlayerMove {
+                        client_id: *client_id,
+                        position: player.position(),
+                        direction: player.direction,
+                        velocity: player.velocity(),
+                        sequence: update,
+                    };
+                    per_move += rmp_serde::to_vec(&packet).unwrap().len();
+                }
+            }
+
+            let baseline = last.as_ref().map(|(sequence, state)| (*sequence, state));
+            let snapshot = Snapshot::encode(update, &state, baseline);
+            if snapshot.is_empty() {
+                continue;
+            }
+
+            snapshots += rmp_serde::to_vec(&Packet::Snapshot(snapshot.clone())).unwrap().len();
+            last = Some((update, snapshot.decode(baseline.map(|(_, state)| state)).unwrap()));
+        }
+
+        (per_move, snapshots)
+    }
+
+    #[test]
+    fn decodes_against_baseline() {
+        let baseline: SnapshotState = [
+            (ClientId(1), player(10.0, 10.0, None)),
+            (ClientId(2), player(20.0, 20.0, None)),
+        ]
+        .into();
+
+        let mut state = baseline.clone();
+        state.insert(ClientId(1), player(16.0, 10.0, Some(Vector2 { x: 120.0, y: 0.0 })));
+        state.insert(ClientId(3), player(30.0, 30.0, None));
+
+        let snapshot = Snapshot::encode(1, &state, Some((0, &baseline)));
+        assert_eq!(snapshot.players.len(), 2, "the unchanged player should be left out");
+        assert_eq!(snapshot.decode(Some(&baseline)), Some(state));
+    }
+
+    #[test]
+    fn removes_players_missing_from_baseline() {
+        let baseline: SnapshotState = [
+            (ClientId(1), player(10.0, 10.0, None)),
+            (ClientId(2), player(20.0, 20.0, None)),
+        ]
+        .into();
+
+        let mut state = baseline.clone();
+        state.remove(&ClientId(2));
+
+        let snapshot = Snapshot::encode(1, &state, Some((0, &baseline)));
+        assert_eq!(snapshot.removed, vec![ClientId(2)]);
+        assert_eq!(snapshot.decode(Some(&baseline)), Some(state));
+
+        let empty = Snapshot::encode(2, &SnapshotState::new(), Some((0, &baseline)));
+        assert_eq!(empty.decode(Some(&baseline)), Some(SnapshotState::new()));
+    }
+
+    #[test]
+    fn full_snapshots_have_no_removals() {
+        let state: SnapshotState = [(ClientId(1), player(10.0, 10.0, None))].into();
+
+        let snapshot = Snapshot::encode(0, &state, None);
+        assert!(snapshot.removed.is_empty());
+        assert_eq!(snapshot.decode(None), Some(state));
+    }
+
+    #[test]
+    fn delta_needs_its_baseline() {
+        let baseline: SnapshotState = [(ClientId(1), player(10.0, 10.0, None))].into();
+        let state: SnapshotState = [(ClientId(1), player(16.0, 10.0, None))].into();
+
+        let snapshot = Snapshot::encode(1, &state, Some((0, &baseline)));
+        assert_eq!(snapshot.decode(None), None);
+    }
+
+    #[test]
+    fn moving_players_are_extrapolated() {
+        let walking = Some(Vector2 { x: 120.0, y: 0.0 });
+        let baseline: SnapshotState = [(ClientId(1), player(10.0, 10.0, walking))].into();
+
+        let state: SnapshotState = [(ClientId(1), player(16.0, 10.0, walking))].into();
+        let snapshot = Snapshot::encode(1, &state, Some((0, &baseline)));
+        assert!(
+            snapshot.is_empty(),
+            "a player keeping the same velocity should be left out"
+        );
+        assert_eq!(snapshot.decode(Some(&baseline)), Some(baseline.clone()));
+
+        let state: SnapshotState = [(ClientId(1), player(22.0, 10.0, None))].into();
+        let snapshot = Snapshot::encode(2, &state, Some((0, &baseline)));
+        assert_eq!(snapshot.decode(Some(&baseline)), Some(state));
+    }
+
+    #[test]
+    fn snapshots_are_no_bigger_than_a_packet_per_move() {
+        for players in [10, 50, 100] {
+            for turn_every in [1, 5, 20] {
+                let (per_move, snapshots) = bytes_sent(players, 100, turn_every);
+                assert!(
+                    snapshots <= per_move,
+                    "{players} players turning every {turn_every} updates: {snapshots} bytes in snapshots, \
+                     {per_move} bytes per move"
+                );
+            }
+        }
+    }
+}
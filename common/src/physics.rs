@@ -0,0 +1,488 @@
+use mint::{Point2, Vector2};
+use ndarray::Array2;
+
+use crate::{
+    network::{Direction, TileAttribute, Zone},
+    SPRITE_SIZE, TILE_SIZE,
+};
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+impl Rect {
+    pub fn new(x: f32, y: f32, w: f32, h: f32) -> Self {
+        Self { x, y, w, h }
+    }
+
+    pub fn left(&self) -> f32 {
+        self.x
+    }
+    pub fn right(&self) -> f32 {
+        self.x + self.w
+    }
+    pub fn top(&self) -> f32 {
+        self.y
+    }
+    pub fn bottom(&self) -> f32 {
+        self.y + self.h
+    }
+
+    /// Checks if two rectangles overlap, rectangles that only touch edges don't count
+    pub fn overlaps(&self, other: &Rect) -> bool {
+        self.left() < other.right()
+            && self.right() > other.left()
+            && self.top() < other.bottom()
+            && self.bottom() > other.top()
+    }
+
+    /// Returns the smallest rectangle containing both of them
+    pub fn union(&self, other: &Rect) -> Rect {
+        let left = self.left().min(other.left());
+        let top = self.top().min(other.top());
+        let right = self.right().max(other.right());
+        let bottom = self.bottom().max(other.bottom());
+
+        Rect::new(left, top, right - left, bottom - top)
+    }
+
+    /// Checks if another rectangle is entirely inside of this one
+    pub fn contains(&self, other: &Rect) -> bool {
+        other.left() >= self.left()
+            && other.right() <= self.right()
+            && other.top() >= self.top()
+            && other.bottom() <= self.bottom()
+    }
+}
+
+impl From<&Zone> for Rect {
+    fn from(zone: &Zone) -> Self {
+        Self::new(zone.position.x, zone.position.y, zone.size.x, zone.size.y)
+    }
+}
+
+/// The part of a sprite that collides with things, only the bottom half so you can walk up to walls
+pub fn sprite_hitbox(position: Point2<f32>) -> Rect {
+    Rect::new(
+        position.x,
+        position.y + SPRITE_SIZE as f32 / 2.0,
+        SPRITE_SIZE as f32,
+        SPRITE_SIZE as f32 / 2.0,
+    )
+}
+
+/// Everything a moving sprite can collide with
+#[derive(Copy, Clone, Debug)]
+pub struct Obstacles<'a> {
+    /// Edges of the map, sprites can't leave it
+    pub bounds: Rect,
+    /// Attribute of every tile on the map, if tiles should block at all
+    pub tiles: Option<&'a Array2<TileAttribute>>,
+    /// Anything else in the way, like zones and other players
+    pub blockers: &'a [Rect],
+}
+
+/// Returns the area covered by a tile
+pub fn tile_rect(x: usize, y: usize) -> Rect {
+    Rect::new(
+        (x as i32 * TILE_SIZE) as f32,
+        (y as i32 * TILE_SIZE) as f32,
+        TILE_SIZE as f32,
+        TILE_SIZE as f32,
+    )
+}
+
+/// Finds every tile overlapping `area` with an attribute matching `filter`
+pub fn tiles_in<'a>(
+    tiles: &'a Array2<TileAttribute>,
+    area: Rect,
+    filter: impl Fn(&TileAttribute) -> bool + 'a,
+) -> impl Iterator<Item = Rect> + 'a {
+    overlapping_tiles(tiles, area)
+        .filter(move |&position| filter(&tiles[position]))
+        .map(|(x, y)| tile_rect(x, y))
+}
+
+/// Positions of every tile on the map overlapping `area`
+fn overlapping_tiles(tiles: &Array2<TileAttribute>, area: Rect) -> impl Iterator<Item = (usize, usize)> {
+    let (width, height) = tiles.dim();
+    let to_tile = |value: f32, max: usize| ((value / TILE_SIZE as f32).floor().max(0.0) as usize).min(max);
+
+    let (max_x, max_y) = (width.saturating_sub(1), height.saturating_sub(1));
+    let (min_x, max_x) = (to_tile(area.left(), max_x), to_tile(area.right(), max_x));
+    let (min_y, max_y) = (to_tile(area.top(), max_y), to_tile(area.bottom(), max_y));
+
+    // an empty map has no tiles at all, even though the range above always has one
+    let empty = width == 0 || height == 0;
+
+    (min_y..=max_y)
+        .flat_map(move |y| (min_x..=max_x).map(move |x| (x, y)))
+        .filter(move |&(x, y)| !empty && tile_rect(x, y).overlaps(&area))
+}
+
+/// Directions something is moving in along each axis, `None` for axes it isn't moving along
+fn directions(delta: Vector2<f32>) -> (Option<Direction>, Option<Direction>) {
+    let horizontal = if delta.x > 0.0 {
+        Some(Direction::East)
+    } else if delta.x < 0.0 {
+        Some(Direction::West)
+    } else {
+        None
+    };
+
+    let vertical = if delta.y > 0.0 {
+        Some(Direction::South)
+    } else if delta.y < 0.0 {
+        Some(Direction::North)
+    } else {
+        None
+    };
+
+    (horizontal, vertical)
+}
+
+/// Gap left between a sprite and whatever it stops against, so floating point error never pushes it inside
+const SKIN: f32 = 0.01;
+
+/// Where a sweep ran into something, as a fraction of the movement and the axis it hit on
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct Hit {
+    time: f32,
+    horizontal: bool,
+}
+
+/// Moves a sprite by it's velocity for `dt` seconds, staying inside of the map and out of every obstacle.
+///
+/// Movement is swept, so it can't tunnel through thin blockers at high speeds, and running into something
+/// keeps whatever movement is left along the other axis so sprites slide along walls instead of sticking.
+/// Blockers the sprite is already stuck inside of are ignored so it can always walk back out.
+///
+/// Both the client and server move things with this, so that they always agree on where something ends up.
+pub fn step(position: Point2<f32>, velocity: Vector2<f32>, dt: f32, obstacles: &Obstacles) -> Point2<f32> {
+    let mut position = position;
+    let mut delta = Vector2 {
+        x: velocity.x * dt,
+        y: velocity.y * dt,
+    };
+
+    // tiles only block from certain directions, so gather the ones in the way up front along with which axes
+    // they block. Sliding only ever stops movement along an axis, so these stay right for every pass.
+    let start = sprite_hitbox(position);
+    let end = sprite_hitbox(Point2 {
+        x: position.x + delta.x,
+        y: position.y + delta.y,
+    });
+    let (horizontal, vertical) = directions(delta);
+    let blocks = |tile: &TileAttribute, direction: Option<Direction>| direction.is_some_and(|d| tile.blocks(d));
+    let tiles = obstacles
+        .tiles
+        .map(|tiles| {
+            overlapping_tiles(tiles, start.union(&end))
+                .map(|(x, y)| {
+                    let tile = &tiles[(x, y)];
+                    (tile_rect(x, y), blocks(tile, horizontal), blocks(tile, vertical))
+                })
+                .filter(|(_, horizontal, vertical)| *horizontal || *vertical)
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    // each hit removes one axis from the movement, so two passes is always enough
+    for _ in 0..2 {
+        if delta.x == 0.0 && delta.y == 0.0 {
+            break;
+        }
+
+        let hitbox = sprite_hitbox(position);
+        let tile_hits = tiles.iter().filter_map(|(tile, horizontal, vertical)| {
+            // running into the side of a tile that doesn't block that way just walks onto it
+            sweep(&hitbox, delta, tile).filter(|hit| if hit.horizontal { *horizontal } else { *vertical })
+        });
+        let hit = obstacles
+            .blockers
+            .iter()
+            .filter_map(|blocker| sweep(&hitbox, delta, blocker))
+            .chain(tile_hits)
+            .chain(sweep_bounds(&hitbox, delta, &obstacles.bounds))
+            .min_by(|a, b| a.time.total_cmp(&b.time));
+
+        match hit {
+            Some(hit) => {
+                position.x += delta.x * hit.time;
+                position.y += delta.y * hit.time;
+
+                // slide along whatever we hit with what's left of the movement
+                let remaining = 1.0 - hit.time;
+                delta = if hit.horizontal {
+                    Vector2 {
+                        x: 0.0,
+                        y: delta.y * remaining,
+                    }
+                } else {
+                    Vector2 {
+                        x: delta.x * remaining,
+                        y: 0.0,
+                    }
+                };
+            }
+            None => {
+                position.x += delta.x;
+                position.y += delta.y;
+                break;
+            }
+        }
+    }
+
+    position
+}
+
+/// Sweeps `hitbox` by `delta` against a single blocker
+fn sweep(hitbox: &Rect, delta: Vector2<f32>, other: &Rect) -> Option<Hit> {
+    if hitbox.overlaps(other) {
+        return None;
+    }
+
+    let (entry_x, exit_x) = sweep_axis(hitbox.left(), hitbox.right(), other.left(), other.right(), delta.x);
+    let (entry_y, exit_y) = sweep_axis(hitbox.top(), hitbox.bottom(), other.top(), other.bottom(), delta.y);
+
+    let entry = entry_x.max(entry_y);
+    let exit = exit_x.min(exit_y);
+
+    if entry >= exit || entry > 1.0 || exit <= 0.0 {
+        return None;
+    }
+
+    let horizontal = entry_x > entry_y;
+    let distance = if horizontal { delta.x.abs() } else { delta.y.abs() };
+
+    Some(Hit {
+        time: (entry - SKIN / distance).max(0.0),
+        horizontal,
+    })
+}
+
+/// Returns the fraction of `delta` where two ranges start and stop overlapping on one axis
+fn sweep_axis(min: f32, max: f32, other_min: f32, other_max: f32, delta: f32) -> (f32, f32) {
+    if delta > 0.0 {
+        ((other_min - max) / delta, (other_max - min) / delta)
+    } else if delta < 0.0 {
+        ((other_max - min) / delta, (other_min - max) / delta)
+    } else if max > other_min && min < other_max {
+        (f32::NEG_INFINITY, f32::INFINITY)
+    } else {
+        (f32::INFINITY, f32::NEG_INFINITY)
+    }
+}
+
+/// Sweeps `hitbox` by `delta` against the inside edges of `bounds`
+fn sweep_bounds(hitbox: &Rect, delta: Vector2<f32>, bounds: &Rect) -> Option<Hit> {
+    let time_x = if delta.x > 0.0 {
+        (bounds.right() - hitbox.right()) / delta.x
+    } else if delta.x < 0.0 {
+        (bounds.left() - hitbox.left()) / delta.x
+    } else {
+        f32::INFINITY
+    };
+
+    let time_y = if delta.y > 0.0 {
+        (bounds.bottom() - hitbox.bottom()) / delta.y
+    } else if delta.y < 0.0 {
+        (bounds.top() - hitbox.top()) / delta.y
+    } else {
+        f32::INFINITY
+    };
+
+    let (time, horizontal) = if time_x < time_y {
+        (time_x, true)
+    } else {
+        (time_y, false)
+    };
+
+    // already past the edge counts as touching it, so you can't get further out
+    (time <= 1.0).then(|| Hit {
+        time: time.max(0.0),
+        horizontal,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::network::BlockedDirections;
+
+    use super::*;
+
+    const SIZE: f32 = SPRITE_SIZE as f32;
+
+    fn point(x: f32, y: f32) -> Point2<f32> {
+        Point2 { x, y }
+    }
+
+    fn vector(x: f32, y: f32) -> Vector2<f32> {
+        Vector2 { x, y }
+    }
+
+    /// A 10x10 tile map with nothing on it apart from `blockers`
+    fn open_map(blockers: &[Rect]) -> Obstacles<'_> {
+        let size = 10.0 * TILE_SIZE as f32;
+        Obstacles {
+            bounds: Rect::new(0.0, 0.0, size, size),
+            tiles: None,
+            blockers,
+        }
+    }
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 0.1, "expected {expected}, got {actual}");
+    }
+
+    #[test]
+    fn moves_freely_when_nothing_is_in_the_way() {
+        let position = step(point(10.0, 10.0), vector(100.0, -5.0), 0.5, &open_map(&[]));
+        assert_near(position.x, 60.0);
+        assert_near(position.y, 7.5);
+    }
+
+    #[test]
+    fn fast_movement_doesnt_tunnel_through_thin_blockers() {
+        let wall = [Rect::new(200.0, 0.0, 1.0, 480.0)];
+        let position = step(point(0.0, 0.0), vector(100_000.0, 0.0), 1.0, &open_map(&wall));
+
+        assert!(position.x + SIZE <= 200.0);
+        assert_near(position.x + SIZE, 200.0);
+    }
+
+    #[test]
+    fn slides_along_walls() {
+        let wall = [Rect::new(100.0, 0.0, 10.0, 480.0)];
+        let position = step(point(0.0, 0.0), vector(100.0, 100.0), 1.0, &open_map(&wall));
+
+        assert_near(position.x + SIZE, 100.0);
+        assert_near(position.y, 100.0);
+    }
+
+    #[test]
+    fn stays_inside_the_map() {
+        let obstacles = open_map(&[]);
+
+        let position = step(point(10.0, 10.0), vector(-1000.0, -1000.0), 1.0, &obstacles);
+        assert_eq!(position.x, 0.0);
+        assert_eq!(position.y, -SIZE / 2.0);
+
+        let position = step(point(400.0, 400.0), vector(1000.0, 1000.0), 1.0, &obstacles);
+        assert_eq!(position.x + SIZE, 480.0);
+        assert_eq!(position.y + SIZE, 480.0);
+    }
+
+    #[test]
+    fn can_walk_out_of_a_blocker_it_started_inside() {
+        let blocker = [sprite_hitbox(point(100.0, 100.0))];
+        let position = step(point(110.0, 100.0), vector(50.0, 0.0), 1.0, &open_map(&blocker));
+
+        assert_near(position.x, 160.0);
+        assert_near(position.y, 100.0);
+    }
+
+    #[test]
+    fn directional_tiles_only_block_their_directions() {
+        let mut tiles = Array2::from_elem((10, 10), TileAttribute::None);
+        tiles[(3, 0)] = TileAttribute::Directional(BlockedDirections {
+            east: true,
+            ..Default::default()
+        });
+
+        let obstacles = Obstacles {
+            tiles: Some(&tiles),
+            ..open_map(&[])
+        };
+
+        // walking east into it stops at its left edge
+        let position = step(point(0.0, 0.0), vector(200.0, 0.0), 1.0, &obstacles);
+        assert_near(position.x + SIZE, 3.0 * TILE_SIZE as f32);
+
+        // walking west goes right through it
+        let position = step(point(300.0, 0.0), vector(-200.0, 0.0), 1.0, &obstacles);
+        assert_near(position.x, 100.0);
+    }
+
+    #[test]
+    fn diagonal_moves_only_stop_on_the_axes_a_tile_blocks() {
+        let mut tiles = Array2::from_elem((10, 10), TileAttribute::None);
+        tiles[(3, 3)] = TileAttribute::Directional(BlockedDirections {
+            east: true,
+            ..Default::default()
+        });
+        tiles[(6, 3)] = TileAttribute::Ledge(Direction::South);
+
+        let obstacles = Obstacles {
+            tiles: Some(&tiles),
+            ..open_map(&[])
+        };
+
+        // coming down onto it from above isn't moving east into it, so it doesn't stop anything
+        let position = step(point(150.0, 0.0), vector(20.0, 200.0), 1.0, &obstacles);
+        assert_near(position.x, 170.0);
+        assert_near(position.y, 200.0);
+
+        // running into its side stops the eastward part, but still slides south
+        let position = step(point(50.0, 110.0), vector(100.0, 30.0), 1.0, &obstacles);
+        assert_near(position.x + SIZE, 3.0 * TILE_SIZE as f32);
+        assert_near(position.y, 140.0);
+
+        // jumping off a ledge diagonally
+        let position = step(point(294.0, 0.0), vector(20.0, 200.0), 1.0, &obstacles);
+        assert_near(position.x, 314.0);
+        assert_near(position.y, 200.0);
+    }
+
+    #[test]
+    fn ledges_can_only_be_jumped_off_one_way() {
+        let mut tiles = Array2::from_elem((10, 10), TileAttribute::None);
+        tiles[(0, 3)] = TileAttribute::Ledge(Direction::South);
+
+        let obstacles = Obstacles {
+            tiles: Some(&tiles),
+            ..open_map(&[])
+        };
+
+        let position = step(point(0.0, 0.0), vector(0.0, 200.0), 1.0, &obstacles);
+        assert_near(position.y, 200.0);
+
+        let position = step(point(0.0, 250.0), vector(0.0, -200.0), 1.0, &obstacles);
+        assert_near(position.y + SIZE / 2.0, 4.0 * TILE_SIZE as f32);
+    }
+
+    #[test]
+    fn tiles_in_only_finds_overlapping_matches() {
+        let mut tiles = Array2::from_elem((10, 10), TileAttribute::None);
+        tiles[(1, 1)] = TileAttribute::Blocked;
+        tiles[(2, 1)] = TileAttribute::Water;
+        tiles[(5, 5)] = TileAttribute::Blocked;
+
+        // touching the edge of (2, 1) doesn't count
+        let area = Rect::new(50.0, 50.0, 46.0, 10.0);
+        let found = tiles_in(&tiles, area, TileAttribute::is_solid).collect::<Vec<_>>();
+        assert_eq!(found, vec![tile_rect(1, 1)]);
+
+        // areas hanging off of the map are clamped to it
+        let area = Rect::new(-100.0, -100.0, 1000.0, 1000.0);
+        assert_eq!(tiles_in(&tiles, area, TileAttribute::is_solid).count(), 3);
+    }
+
+    #[test]
+    fn sweep_axis_finds_entry_and_exit() {
+        assert_eq!(sweep_axis(0.0, 10.0, 20.0, 30.0, 20.0), (0.5, 1.5));
+        assert_eq!(sweep_axis(20.0, 30.0, 0.0, 10.0, -20.0), (0.5, 1.5));
+        assert_eq!(
+            sweep_axis(0.0, 10.0, 5.0, 15.0, 0.0),
+            (f32::NEG_INFINITY, f32::INFINITY)
+        );
+        assert_eq!(
+            sweep_axis(0.0, 10.0, 20.0, 30.0, 0.0),
+            (f32::INFINITY, f32::NEG_INFINITY)
+        );
+    }
+}
@@ -0,0 +1,1301 @@
+use chrono::Utc;
+use common::{
+    format_duration,
+    network::{server::Packet, ChatChannel, ClientId, Direction, MapHash},
+};
+use euclid::default::Point2D;
+
+use crate::{
+    data::AccessLevel,
+    guild::{validate_guild_name, Guild},
+    mail::Letter,
+    moderation::{Ban, BanTarget, Moderation, Mute},
+    GameServer, WarpParams,
+};
+
+/// Longest a ban or mute can last, about ten years. Anything longer should be permanent.
+const MAX_DURATION: i64 = 520 * 7 * 24 * 60 * 60;
+
+/// What kind of value an argument is, and how it's parsed
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ArgKind {
+    /// Name of a player that's online
+    Player,
+    /// Username of an account, online or not
+    Account,
+    /// Id of a map, it doesn't have to exist yet
+    Map,
+    /// A whole number
+    Number,
+    /// An x and y position in pixels, as two arguments
+    Coordinates,
+    /// A length of time like `30m`, in seconds, `h`ours, `d`ays or `w`eeks
+    Duration,
+    /// A single word
+    Word,
+    /// Everything left on the line
+    Text,
+}
+
+/// One argument a command takes
+pub struct Param {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    /// Optional arguments can only come after every required one
+    pub optional: bool,
+}
+
+impl Param {
+    const fn required(name: &'static str, kind: ArgKind) -> Self {
+        Self {
+            name,
+            kind,
+            optional: false,
+        }
+    }
+
+    const fn optional(name: &'static str, kind: ArgKind) -> Self {
+        Self {
+            name,
+            kind,
+            optional: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Arg {
+    Player(ClientId),
+    Account(String),
+    Map(String),
+    Number(i64),
+    Coordinates(Point2D<f32>),
+    /// Length of time in seconds
+    Duration(i64),
+    Text(String),
+}
+
+/// Parsed arguments, one for each of the command's params.
+///
+/// Required arguments are always there, the getters only return `None` for optional ones that were left out.
+#[derive(Debug)]
+pub struct Args(Vec<Option<Arg>>);
+
+impl Args {
+    fn get(&self, index: usize) -> Option<&Arg> {
+        self.0.get(index)?.as_ref()
+    }
+
+    pub fn player(&self, index: usize) -> Option<ClientId> {
+        match self.get(index) {
+            Some(Arg::Player(client_id)) => Some(*client_id),
+            _ => None,
+        }
+    }
+
+    pub fn account(&self, index: usize) -> Option<&str> {
+        match self.get(index) {
+            Some(Arg::Account(username)) => Some(username),
+            _ => None,
+        }
+    }
+
+    pub fn map(&self, index: usize) -> Option<&str> {
+        match self.get(index) {
+            Some(Arg::Map(map_id)) => Some(map_id),
+            _ => None,
+        }
+    }
+
+    pub fn number(&self, index: usize) -> Option<i64> {
+        match self.get(index) {
+            Some(Arg::Number(number)) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn coordinates(&self, index: usize) -> Option<Point2D<f32>> {
+        match self.get(index) {
+            Some(Arg::Coordinates(position)) => Some(*position),
+            _ => None,
+        }
+    }
+
+    pub fn duration(&self, index: usize) -> Option<i64> {
+        match self.get(index) {
+            Some(Arg::Duration(seconds)) => Some(*seconds),
+            _ => None,
+        }
+    }
+
+    pub fn text(&self, index: usize) -> Option<&str> {
+        match self.get(index) {
+            Some(Arg::Text(text)) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+/// Finds the players and accounts that arguments name
+pub trait Lookup {
+    /// Finds an online player by their character name
+    fn find_player(&self, name: &str) -> Option<ClientId>;
+    /// Finds an account by its username, returning the username as it was saved
+    fn find_account(&self, username: &str) -> anyhow::Result<Option<String>>;
+}
+
+impl Lookup for GameServer {
+    fn find_player(&self, name: &str) -> Option<ClientId> {
+        GameServer::find_player(self, name)
+    }
+
+    fn find_account(&self, username: &str) -> anyhow::Result<Option<String>> {
+        Ok(self.storage.load_player(username)?.map(|player| player.username))
+    }
+}
+
+/// Runs a command once its arguments have been parsed, errors are shown to whoever used it along with the usage
+type Handler = fn(&mut GameServer, ClientId, &Args) -> Result<(), String>;
+
+pub struct Command {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub params: &'static [Param],
+    /// Lowest access level that can use the command
+    pub access: AccessLevel,
+    pub help: &'static str,
+    handler: Handler,
+}
+
+/// Every chat command, in the order `/help` lists them
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "help",
+        aliases: &["?", "commands"],
+        params: &[Param::optional("command", ArgKind::Word)],
+        access: AccessLevel::Player,
+        help: "Lists the commands you can use, or explains one of them",
+        handler: help,
+    },
+    Command {
+        name: "whisper",
+        aliases: &["w", "msg", "tell"],
+        params: &[
+            Param::required("player name", ArgKind::Word),
+            Param::required("message", ArgKind::Text),
+        ],
+        access: AccessLevel::Player,
+        help: "Sends a private message, it's saved for later if they're offline",
+        handler: whisper,
+    },
+    Command {
+        name: "reply",
+        aliases: &["r"],
+        params: &[Param::required("message", ArgKind::Text)],
+        access: AccessLevel::Player,
+        help: "Whispers back to whoever last whispered to you",
+        handler: reply,
+    },
+    Command {
+        name: "party",
+        aliases: &["p"],
+        params: &[
+            Param::required("invite|accept|leave|kick|list", ArgKind::Word),
+            Param::optional("player name", ArgKind::Player),
+        ],
+        access: AccessLevel::Player,
+        help: "Invites players to your party, or accepts, leaves, kicks from or lists it",
+        handler: party,
+    },
+    Command {
+        name: "guild",
+        aliases: &["g"],
+        params: &[
+            Param::required("create|invite|accept|leave|kick|list|disband", ArgKind::Word),
+            Param::optional("name", ArgKind::Text),
+        ],
+        access: AccessLevel::Player,
+        help: "Creates a guild or manages yours, kicking takes a character name so it works on offline members",
+        handler: guild,
+    },
+    Command {
+        name: "warp",
+        aliases: &["tp"],
+        params: &[
+            Param::required("map id", ArgKind::Map),
+            Param::optional("x y", ArgKind::Coordinates),
+        ],
+        access: AccessLevel::Moderator,
+        help: "Teleports you to a map, creating it if it doesn't exist",
+        handler: warp,
+    },
+    Command {
+        name: "announce",
+        aliases: &[],
+        params: &[Param::required("message", ArgKind::Text)],
+        access: AccessLevel::Moderator,
+        help: "Sends a message to everyone as the server",
+        handler: announce,
+    },
+    Command {
+        name: "sprite",
+        aliases: &[],
+        params: &[
+            Param::required("player name", ArgKind::Player),
+            Param::required("sprite #", ArgKind::Number),
+        ],
+        access: AccessLevel::Player,
+        help: "Changes a player's sprite, only moderators can change anyone else's",
+        handler: sprite,
+    },
+    Command {
+        name: "access",
+        aliases: &[],
+        params: &[
+            Param::required("player name", ArgKind::Player),
+            Param::required("access level", ArgKind::Word),
+        ],
+        access: AccessLevel::Owner,
+        help: "Sets a player's access level, one of player, moderator, mapper, developer or owner",
+        handler: access,
+    },
+    Command {
+        name: "kick",
+        aliases: &[],
+        params: &[
+            Param::required("player name", ArgKind::Player),
+            Param::optional("reason", ArgKind::Text),
+        ],
+        access: AccessLevel::Moderator,
+        help: "Removes a player from the game, they can come straight back",
+        handler: kick,
+    },
+    Command {
+        name: "ban",
+        aliases: &[],
+        params: &[
+            Param::required("username", ArgKind::Account),
+            Param::optional("duration", ArgKind::Duration),
+            Param::optional("reason", ArgKind::Text),
+        ],
+        access: AccessLevel::Moderator,
+        help: "Bans an account, forever if there's no duration",
+        handler: ban,
+    },
+    Command {
+        name: "banip",
+        aliases: &[],
+        params: &[
+            Param::required("player name", ArgKind::Player),
+            Param::optional("duration", ArgKind::Duration),
+            Param::optional("reason", ArgKind::Text),
+        ],
+        access: AccessLevel::Moderator,
+        help: "Bans the address a player is connecting from, forever if there's no duration",
+        handler: ban_address,
+    },
+    Command {
+        name: "unban",
+        aliases: &[],
+        params: &[Param::required("username or address", ArgKind::Word)],
+        access: AccessLevel::Moderator,
+        help: "Lifts every ban on an account or address",
+        handler: unban,
+    },
+    Command {
+        name: "mute",
+        aliases: &[],
+        params: &[
+            Param::required("player name", ArgKind::Player),
+            Param::required("duration", ArgKind::Duration),
+            Param::optional("reason", ArgKind::Text),
+        ],
+        access: AccessLevel::Moderator,
+        help: "Stops a player from chatting for a while",
+        handler: mute,
+    },
+    Command {
+        name: "unmute",
+        aliases: &[],
+        params: &[Param::required("username", ArgKind::Account)],
+        access: AccessLevel::Moderator,
+        help: "Lets a muted account chat again, online or not",
+        handler: unmute,
+    },
+    Command {
+        name: "jail",
+        aliases: &[],
+        params: &[
+            Param::required("player name", ArgKind::Player),
+            Param::required("map id", ArgKind::Map),
+        ],
+        access: AccessLevel::Moderator,
+        help: "Keeps a player on a map until they're let out",
+        handler: jail,
+    },
+    Command {
+        name: "unjail",
+        aliases: &[],
+        params: &[Param::required("username", ArgKind::Account)],
+        access: AccessLevel::Moderator,
+        help: "Lets a jailed account out, back to the start",
+        handler: unjail,
+    },
+];
+
+impl Command {
+    /// Looks up a command by its name or any of its aliases
+    pub fn find(name: &str) -> Option<&'static Command> {
+        COMMANDS.iter().find(|command| {
+            command.name.eq_ignore_ascii_case(name)
+                || command.aliases.iter().any(|alias| alias.eq_ignore_ascii_case(name))
+        })
+    }
+
+    pub fn usage(&self) -> String {
+        let mut usage = format!("/{}", self.name);
+        for param in self.params {
+            if param.optional {
+                usage.push_str(&format!(" [{}]", param.name));
+            } else {
+                usage.push_str(&format!(" <{}>", param.name));
+            }
+        }
+
+        usage
+    }
+
+    /// Parses the rest of the line after the command's name. Arguments are split by spaces,
+    /// unless they're in quotes like `"player name"`.
+    ///
+    /// An optional argument that doesn't fit is skipped if there's more after it, so `/ban name being rude`
+    /// doesn't need a duration.
+    pub fn parse(&self, lookup: &impl Lookup, line: &str) -> Result<Args, String> {
+        let mut tokens = Tokens(line);
+        let mut args = Vec::with_capacity(self.params.len());
+
+        for (index, param) in self.params.iter().enumerate() {
+            let before = tokens.0;
+            let token = match param.kind {
+                ArgKind::Text => tokens.rest(),
+                _ => tokens.next(),
+            };
+
+            let token = match token {
+                Some(token) => token,
+                // the rest are optional too
+                None if param.optional => break,
+                None => return Err(format!("Missing the {}", param.name)),
+            };
+
+            match parse_arg(lookup, param, token, &mut tokens) {
+                Ok(arg) => args.push(Some(arg)),
+                Err(_) if param.optional && index + 1 < self.params.len() => {
+                    // keep the later arguments at their own index
+                    args.push(None);
+                    tokens.0 = before;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        if tokens.next().is_some() {
+            return Err(String::from("Too many arguments"));
+        }
+
+        Ok(Args(args))
+    }
+
+    pub fn run(&self, server: &mut GameServer, client_id: ClientId, args: &Args) -> Result<(), String> {
+        (self.handler)(server, client_id, args)
+    }
+}
+
+fn parse_arg(lookup: &impl Lookup, param: &Param, token: &str, tokens: &mut Tokens) -> Result<Arg, String> {
+    let arg = match param.kind {
+        ArgKind::Player => {
+            let client_id = lookup
+                .find_player(token)
+                .ok_or_else(|| format!("Could not find {token}, are they online?"))?;
+            Arg::Player(client_id)
+        }
+        ArgKind::Account => match lookup.find_account(token) {
+            Ok(Some(username)) => Arg::Account(username),
+            Ok(None) => return Err(format!("There is no account named {token}")),
+            Err(e) => {
+                log::error!("Couldn't load player: {e}");
+                return Err(format!("Couldn't look up the account {token}"));
+            }
+        },
+        ArgKind::Map => Arg::Map(token.to_string()),
+        ArgKind::Number => {
+            let number = token
+                .parse()
+                .map_err(|_| format!("The {} must be a whole number", param.name))?;
+            Arg::Number(number)
+        }
+        ArgKind::Coordinates => {
+            let invalid = || format!("The {} must be two numbers", param.name);
+            let x = token.parse().map_err(|_| invalid())?;
+            let y = tokens.next().ok_or_else(invalid)?.parse().map_err(|_| invalid())?;
+            Arg::Coordinates(Point2D::new(x, y))
+        }
+        ArgKind::Duration => {
+            let seconds = parse_duration(token)
+                .ok_or_else(|| format!("The {} must be a length of time like 30m, 12h or 7d", param.name))?;
+            Arg::Duration(seconds)
+        }
+        ArgKind::Word | ArgKind::Text => Arg::Text(token.to_string()),
+    };
+
+    Ok(arg)
+}
+
+/// Parses a length of time like `90s`, `30m`, `12h`, `7d` or `2w` into seconds
+fn parse_duration(token: &str) -> Option<i64> {
+    let split = token.find(|c: char| !c.is_ascii_digit())?;
+    let (count, unit) = token.split_at(split);
+
+    let length = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+
+    count
+        .parse::<i64>()
+        .ok()?
+        .checked_mul(length)
+        .filter(|seconds| *seconds > 0)
+}
+
+/// Splits a command's arguments apart as they're needed
+struct Tokens<'a>(&'a str);
+
+impl<'a> Tokens<'a> {
+    fn next(&mut self) -> Option<&'a str> {
+        let line = self.0.trim_start();
+        if line.is_empty() {
+            self.0 = line;
+            return None;
+        }
+
+        let (token, rest) = match line.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => line.split_once(char::is_whitespace).unwrap_or((line, "")),
+        };
+
+        self.0 = rest;
+        Some(token)
+    }
+
+    fn rest(&mut self) -> Option<&'a str> {
+        let rest = std::mem::take(&mut self.0).trim();
+        (!rest.is_empty()).then_some(rest)
+    }
+}
+
+fn help(server: &mut GameServer, client_id: ClientId, args: &Args) -> Result<(), String> {
+    let access = server.players[&client_id].access;
+
+    let message = match args.text(0) {
+        Some(name) => {
+            let command = Command::find(name.trim_start_matches('/'))
+                .filter(|command| access >= command.access)
+                .ok_or_else(|| format!("There is no /{name} command"))?;
+
+            let mut message = format!("{}\n{}", command.usage(), command.help);
+            if !command.aliases.is_empty() {
+                let aliases = command
+                    .aliases
+                    .iter()
+                    .map(|alias| format!("/{alias}"))
+                    .collect::<Vec<_>>();
+                message.push_str(&format!("\nAlso: {}", aliases.join(", ")));
+            }
+            message
+        }
+        None => {
+            let lines = COMMANDS
+                .iter()
+                .filter(|command| access >= command.access)
+                .map(|command| format!("{} - {}", command.usage(), command.help))
+                .collect::<Vec<_>>();
+
+            format!("Commands you can use:\n{}", lines.join("\n"))
+        }
+    };
+
+    server.send(client_id, &Packet::ChatLog(ChatChannel::Echo, message));
+    Ok(())
+}
+
+fn warp(server: &mut GameServer, client_id: ClientId, args: &Args) -> Result<(), String> {
+    let map_hash = server.validate_map(args.map(0).unwrap());
+    server.warp_player(
+        client_id,
+        map_hash,
+        WarpParams {
+            position: args.coordinates(1),
+            direction: Some(Direction::South),
+            ..Default::default()
+        },
+    );
+
+    Ok(())
+}
+
+fn announce(server: &mut GameServer, _client_id: ClientId, args: &Args) -> Result<(), String> {
+    let message = args.text(0).unwrap().to_string();
+    server.send_all(&Packet::ChatLog(ChatChannel::Server, message));
+
+    Ok(())
+}
+
+fn sprite(server: &mut GameServer, client_id: ClientId, args: &Args) -> Result<(), String> {
+    let other_id = args.player(0).unwrap();
+    let sprite = args
+        .number(1)
+        .and_then(|sprite| u32::try_from(sprite).ok())
+        .ok_or("Invalid sprite, it can't be negative")?;
+
+    if other_id != client_id && !server.require(client_id, AccessLevel::Moderator) {
+        return Ok(());
+    }
+
+    let player = server.players.get_mut(&other_id).unwrap();
+    player.sprite = sprite;
+    match server.storage.save_player(player) {
+        Ok(()) => player.dirty = false,
+        Err(e) => {
+            player.dirty = true;
+            log::error!("Couldn't save player: {e}");
+        }
+    };
+
+    let data = player.clone();
+    server.send_to_observers(other_id, &Packet::PlayerData(other_id, data.into()));
+
+    Ok(())
+}
+
+fn access(server: &mut GameServer, _client_id: ClientId, args: &Args) -> Result<(), String> {
+    let other_id = args.player(0).unwrap();
+    let level = args
+        .text(1)
+        .unwrap()
+        .parse::<AccessLevel>()
+        .map_err(|_| "Invalid access level")?;
+
+    let player = server.players.get_mut(&other_id).unwrap();
+    player.access = level;
+    player.dirty = true;
+
+    let message = format!("Your access level is now {level}.");
+    server.send(other_id, &Packet::ChatLog(ChatChannel::Server, message));
+
+    Ok(())
+}
+
+/// Makes sure a moderator is allowed to act on someone with `target` access, only owners can act on their equals
+fn check_rank(server: &GameServer, client_id: ClientId, target: AccessLevel) -> Result<(), String> {
+    let access = server.players[&client_id].access;
+    if access > target || access == AccessLevel::Owner {
+        Ok(())
+    } else {
+        Err(format!("You can't do that to someone with {target} access"))
+    }
+}
+
+/// Same as `check_rank`, for a player that's online
+fn check_target(server: &GameServer, client_id: ClientId, other_id: ClientId) -> Result<(), String> {
+    if other_id == client_id {
+        return Err(String::from("You can't do that to yourself"));
+    }
+
+    check_rank(server, client_id, server.players[&other_id].access)
+}
+
+/// Same as `check_rank`, for an account that might not be online. Returns who's playing on it if anyone is.
+fn check_account(server: &GameServer, client_id: ClientId, username: &str) -> Result<Option<ClientId>, String> {
+    let online = server.find_account(username);
+
+    match online {
+        Some(other_id) => check_target(server, client_id, other_id)?,
+        None => {
+            let access = match server.storage.load_player(username) {
+                Ok(player) => player.map(|player| player.access).unwrap_or_default(),
+                Err(e) => {
+                    log::error!("Couldn't load player: {e}");
+                    return Err(String::from("Couldn't look up that account"));
+                }
+            };
+            check_rank(server, client_id, access)?;
+        }
+    }
+
+    Ok(online)
+}
+
+/// When a punishment lasting `seconds` from now runs out, as a unix timestamp
+fn expiry(seconds: i64) -> Result<i64, String> {
+    if seconds > MAX_DURATION {
+        return Err(format!(
+            "That's too long, it can be at most {}",
+            format_duration(MAX_DURATION)
+        ));
+    }
+
+    Utc::now()
+        .timestamp()
+        .checked_add(seconds)
+        .ok_or_else(|| String::from("That's too long"))
+}
+
+/// Describes a punishment for the audit log and whoever it's for, like "for 2 days: spamming"
+fn describe(until: Option<i64>, reason: Option<&str>) -> String {
+    let mut description = match until {
+        Some(until) => format!("for {}", format_duration(until - Utc::now().timestamp())),
+        None => String::from("permanently"),
+    };
+
+    if let Some(reason) = reason {
+        description.push_str(&format!(": {reason}"));
+    }
+
+    description
+}
+
+/// Writes a moderator's action to the audit log and lets them know it worked
+fn audit(server: &GameServer, client_id: ClientId, action: String) {
+    let moderator = &server.players[&client_id];
+    Moderation::audit(&format!("{} ({})", moderator.name, moderator.username), &action);
+
+    let message = format!("Done, {action}.");
+    server.send(client_id, &Packet::ChatLog(ChatChannel::Echo, message));
+}
+
+fn kick(server: &mut GameServer, client_id: ClientId, args: &Args) -> Result<(), String> {
+    let other_id = args.player(0).unwrap();
+    check_target(server, client_id, other_id)?;
+
+    let name = server.players[&other_id].name.clone();
+    let reason = match args.text(1) {
+        Some(reason) => format!("You were kicked by a moderator: {reason}"),
+        None => String::from("You were kicked by a moderator."),
+    };
+
+    audit(server, client_id, format!("kicked {name}"));
+    server.kick(other_id, reason);
+
+    Ok(())
+}
+
+/// Adds a ban and kicks whoever it's for
+fn add_ban(server: &mut GameServer, client_id: ClientId, ban: Ban, kick: Option<ClientId>, description: String) {
+    let reason = format!("You have been banned {}", describe(ban.until, ban.reason.as_deref()));
+
+    server.moderation.bans.push(ban);
+    server.save_moderation();
+
+    audit(server, client_id, description);
+    if let Some(other_id) = kick {
+        server.kick(other_id, reason);
+    }
+}
+
+fn ban(server: &mut GameServer, client_id: ClientId, args: &Args) -> Result<(), String> {
+    let username = args.account(0).unwrap().to_string();
+    let online = check_account(server, client_id, &username)?;
+
+    let until = args.duration(1).map(expiry).transpose()?;
+    let reason = args.text(2);
+    let description = format!("banned the account {username} {}", describe(until, reason));
+
+    let ban = Ban {
+        reason: reason.map(str::to_string),
+        until,
+        target: BanTarget::Account(username.to_lowercase()),
+    };
+
+    add_ban(server, client_id, ban, online, description);
+    Ok(())
+}
+
+fn ban_address(server: &mut GameServer, client_id: ClientId, args: &Args) -> Result<(), String> {
+    let other_id = args.player(0).unwrap();
+    check_target(server, client_id, other_id)?;
+
+    let address = server.address(other_id).ok_or("Couldn't find their address")?;
+    let until = args.duration(1).map(expiry).transpose()?;
+    let reason = args.text(2);
+
+    let name = &server.players[&other_id].name;
+    let description = format!("banned {name}'s address {address} {}", describe(until, reason));
+
+    let ban = Ban {
+        reason: reason.map(str::to_string),
+        until,
+        target: BanTarget::Address(address),
+    };
+
+    add_ban(server, client_id, ban, Some(other_id), description);
+    Ok(())
+}
+
+fn unban(server: &mut GameServer, client_id: ClientId, args: &Args) -> Result<(), String> {
+    let target = match args.text(0).unwrap().parse() {
+        Ok(address) => BanTarget::Address(address),
+        Err(_) => BanTarget::Account(args.text(0).unwrap().to_lowercase()),
+    };
+
+    let count = server.moderation.bans.len();
+    server.moderation.bans.retain(|ban| ban.target != target);
+    if server.moderation.bans.len() == count {
+        return Err(String::from("That account or address isn't banned"));
+    }
+
+    server.save_moderation();
+
+    let action = match target {
+        BanTarget::Account(username) => format!("unbanned the account {username}"),
+        BanTarget::Address(address) => format!("unbanned the address {address}"),
+    };
+    audit(server, client_id, action);
+
+    Ok(())
+}
+
+fn mute(server: &mut GameServer, client_id: ClientId, args: &Args) -> Result<(), String> {
+    let other_id = args.player(0).unwrap();
+    check_target(server, client_id, other_id)?;
+
+    let until = expiry(args.duration(1).unwrap())?;
+    let reason = args.text(2);
+    let description = describe(Some(until), reason);
+
+    let player = &server.players[&other_id];
+    let (name, username) = (player.name.clone(), player.username.to_lowercase());
+
+    let mute = Mute {
+        reason: reason.map(str::to_string),
+        until,
+    };
+    server.moderation.mutes.insert(username, mute);
+    server.save_moderation();
+
+    let message = format!("You have been muted {description}");
+    server.send(other_id, &Packet::ChatLog(ChatChannel::Server, message));
+    audit(server, client_id, format!("muted {name} {description}"));
+
+    Ok(())
+}
+
+fn unmute(server: &mut GameServer, client_id: ClientId, args: &Args) -> Result<(), String> {
+    let username = args.account(0).unwrap().to_string();
+    let online = check_account(server, client_id, &username)?;
+
+    if server.moderation.mutes.remove(&username.to_lowercase()).is_none() {
+        return Err(format!("The account {username} isn't muted"));
+    }
+
+    server.save_moderation();
+
+    if let Some(other_id) = online {
+        let message = String::from("You can chat again.");
+        server.send(other_id, &Packet::ChatLog(ChatChannel::Server, message));
+    }
+    audit(server, client_id, format!("unmuted the account {username}"));
+
+    Ok(())
+}
+
+fn jail(server: &mut GameServer, client_id: ClientId, args: &Args) -> Result<(), String> {
+    let other_id = args.player(0).unwrap();
+    check_target(server, client_id, other_id)?;
+
+    let map_id = args.map(1).unwrap();
+    let map_hash = MapHash::from(map_id);
+    if !server.maps.contains_key(&map_hash) {
+        return Err(format!("There is no map {map_id}"));
+    }
+
+    let player = &server.players[&other_id];
+    let (name, username) = (player.name.clone(), player.username.to_lowercase());
+
+    server.moderation.jails.insert(username, map_id.to_string());
+    server.save_moderation();
+
+    let position = server.map_center(map_hash);
+    server.warp_player(
+        other_id,
+        map_hash,
+        WarpParams {
+            position: Some(position),
+            direction: Some(Direction::South),
+            ..Default::default()
+        },
+    );
+
+    let message = String::from("You have been jailed by a moderator.");
+    server.send(other_id, &Packet::ChatLog(ChatChannel::Server, message));
+    audit(server, client_id, format!("jailed {name} on {map_id}"));
+
+    Ok(())
+}
+
+fn unjail(server: &mut GameServer, client_id: ClientId, args: &Args) -> Result<(), String> {
+    let username = args.account(0).unwrap().to_string();
+    let online = check_account(server, client_id, &username)?;
+
+    if server.moderation.jails.remove(&username.to_lowercase()).is_none() {
+        return Err(format!("The account {username} isn't jailed"));
+    }
+
+    server.save_moderation();
+
+    let position = server.config.start.position();
+    match online {
+        Some(other_id) => {
+            server.warp_player(
+                other_id,
+                MapHash::start(),
+                WarpParams {
+                    position: Some(position),
+                    direction: Some(Direction::South),
+                    ..Default::default()
+                },
+            );
+
+            let message = String::from("You have been let out of jail.");
+            server.send(other_id, &Packet::ChatLog(ChatChannel::Server, message));
+        }
+        // move them out of the jail themselves, so they don't log back in on it
+        None => match server.storage.load_player(&username) {
+            Ok(Some(mut player)) => {
+                player.map = MapHash::start();
+                player.position = position;
+                player.direction = Direction::South;
+
+                if let Err(e) = server.storage.save_player(&player) {
+                    log::error!("Couldn't save player: {e}");
+                }
+            }
+            Ok(None) => (),
+            Err(e) => log::error!("Couldn't load player: {e}"),
+        },
+    }
+    audit(server, client_id, format!("let the account {username} out of jail"));
+
+    Ok(())
+}
+
+/// Whispers to a player by their character name, leaving it for them to read later if they're offline
+fn send_whisper(server: &mut GameServer, client_id: ClientId, name: &str, message: &str) -> Result<(), String> {
+    if server.muted(client_id) {
+        return Ok(());
+    }
+
+    let sender = server.players[&client_id].name.clone();
+
+    if let Some(other_id) = server.find_player(name) {
+        if other_id == client_id {
+            return Err(String::from("You can't whisper to yourself"));
+        }
+
+        let recipient = &server.players[&other_id].name;
+        let echo = format!("To {recipient}: {message}");
+
+        server.send(
+            other_id,
+            &Packet::ChatLog(ChatChannel::Whisper, format!("From {sender}: {message}")),
+        );
+        server.send(client_id, &Packet::ChatLog(ChatChannel::Whisper, echo));
+        server.reply_to.insert(other_id, sender);
+
+        return Ok(());
+    }
+
+    let recipient = match server.storage.find_player(name) {
+        Ok(Some(player)) => player,
+        Ok(None) => return Err(format!("There is no one named {name}")),
+        Err(e) => {
+            log::error!("Couldn't load player: {e}");
+            return Err(format!("Couldn't look up {name}"));
+        }
+    };
+
+    let letter = Letter {
+        from: sender,
+        sent: Utc::now().timestamp(),
+        message: message.to_string(),
+    };
+
+    if !server.mailbox.send(&recipient.username, letter) {
+        return Err(format!(
+            "{} has too many messages waiting for them already",
+            recipient.name
+        ));
+    }
+
+    server.save_mail();
+
+    let echo = format!(
+        "To {} (offline, they'll get it when they're back): {message}",
+        recipient.name
+    );
+    server.send(client_id, &Packet::ChatLog(ChatChannel::Whisper, echo));
+
+    Ok(())
+}
+
+fn whisper(server: &mut GameServer, client_id: ClientId, args: &Args) -> Result<(), String> {
+    send_whisper(server, client_id, args.text(0).unwrap(), args.text(1).unwrap())
+}
+
+fn reply(server: &mut GameServer, client_id: ClientId, args: &Args) -> Result<(), String> {
+    let name = server
+        .reply_to
+        .get(&client_id)
+        .cloned()
+        .ok_or("Nobody has whispered to you yet")?;
+
+    send_whisper(server, client_id, &name, args.text(0).unwrap())
+}
+
+/// Sends a message to everyone in a party
+fn tell_party(server: &GameServer, members: &[ClientId], message: String) {
+    server.send_list(members, &Packet::ChatLog(ChatChannel::Party, message));
+}
+
+fn party(server: &mut GameServer, client_id: ClientId, args: &Args) -> Result<(), String> {
+    let name = server.players[&client_id].name.clone();
+
+    match args.text(0).unwrap().to_lowercase().as_str() {
+        "invite" => {
+            let other_id = args.player(1).ok_or("Who do you want to invite?")?;
+            if other_id == client_id {
+                return Err(String::from("You can't invite yourself"));
+            }
+
+            server.parties.invite(client_id, other_id)?;
+
+            let other_name = &server.players[&other_id].name;
+            let message = format!("{name} invited you to their party, type /party accept to join.");
+            server.send(other_id, &Packet::ChatLog(ChatChannel::Party, message));
+
+            let echo = format!("Invited {other_name} to your party.");
+            server.send(client_id, &Packet::ChatLog(ChatChannel::Echo, echo));
+        }
+        "accept" => {
+            let members = server.parties.accept(client_id)?.members.clone();
+            tell_party(server, &members, format!("{name} joined the party."));
+        }
+        "leave" => {
+            if !server.leave_party(client_id, "left the party") {
+                return Err(String::from("You aren't in a party"));
+            }
+
+            let message = String::from("You left the party.");
+            server.send(client_id, &Packet::ChatLog(ChatChannel::Party, message));
+        }
+        "kick" => {
+            let other_id = args.player(1).ok_or("Who do you want to kick?")?;
+            let party = server.parties.get(client_id).ok_or("You aren't in a party")?;
+
+            if party.leader != client_id {
+                return Err(String::from("Only the party leader can kick people"));
+            }
+
+            if other_id == client_id || !party.members.contains(&other_id) {
+                return Err(String::from("They aren't in your party"));
+            }
+
+            server.leave_party(other_id, "was kicked from the party");
+
+            let message = String::from("You were kicked from the party.");
+            server.send(other_id, &Packet::ChatLog(ChatChannel::Party, message));
+        }
+        "list" => {
+            let party = server.parties.get(client_id).ok_or("You aren't in a party")?;
+            let names = party
+                .members
+                .iter()
+                .map(|member| {
+                    let name = &server.players[member].name;
+                    if *member == party.leader {
+                        format!("{name} (leader)")
+                    } else {
+                        name.clone()
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            let message = format!("Your party: {}", names.join(", "));
+            server.send(client_id, &Packet::ChatLog(ChatChannel::Echo, message));
+        }
+        action => return Err(format!("There is no {action} action")),
+    }
+
+    Ok(())
+}
+
+/// Sends a message to every member of a guild that's online
+fn tell_guild(server: &GameServer, guild: &Guild, message: String) {
+    let members = server.guild_members(guild);
+    server.send_list(&members, &Packet::ChatLog(ChatChannel::Guild, message));
+}
+
+fn guild(server: &mut GameServer, client_id: ClientId, args: &Args) -> Result<(), String> {
+    let player = &server.players[&client_id];
+    let (name, username) = (player.name.clone(), player.username.to_lowercase());
+
+    match args.text(0).unwrap().to_lowercase().as_str() {
+        "create" => {
+            let guild_name = args.text(1).ok_or("What do you want to call your guild?")?;
+            validate_guild_name(guild_name)?;
+
+            if server.guilds.of(&username).is_some() {
+                return Err(String::from("You're already in a guild, leave it first"));
+            }
+
+            if server.guilds.find(guild_name).is_some() {
+                return Err(format!("There's already a guild called {guild_name}"));
+            }
+
+            server.guilds.guilds.push(Guild {
+                name: guild_name.to_string(),
+                leader: username.clone(),
+                members: [(username, name)].into_iter().collect(),
+            });
+            server.save_guilds();
+
+            let message = format!("You founded {guild_name}, invite people with /guild invite <player name>.");
+            server.send(client_id, &Packet::ChatLog(ChatChannel::Guild, message));
+        }
+        "invite" => {
+            let other_name = args.text(1).ok_or("Who do you want to invite?")?;
+            let other_id = server
+                .find_player(other_name)
+                .ok_or_else(|| format!("Could not find {other_name}, are they online?"))?;
+
+            let guild = server.guilds.of(&username).ok_or("You aren't in a guild")?;
+            if guild.leader != username {
+                return Err(String::from("Only the guild leader can invite people"));
+            }
+
+            let other = &server.players[&other_id];
+            if server.guilds.of(&other.username).is_some() {
+                return Err(format!("{} is already in a guild", other.name));
+            }
+
+            let guild_name = guild.name.clone();
+            let echo = format!("Invited {} to {guild_name}.", other.name);
+            let message = format!("{name} invited you to join {guild_name}, type /guild accept to join.");
+
+            server.guilds.invites.insert(other_id, guild_name);
+            server.send(other_id, &Packet::ChatLog(ChatChannel::Guild, message));
+            server.send(client_id, &Packet::ChatLog(ChatChannel::Echo, echo));
+        }
+        "accept" => {
+            let guild_name = server
+                .guilds
+                .invites
+                .remove(&client_id)
+                .ok_or("You haven't been invited to a guild")?;
+
+            if server.guilds.of(&username).is_some() {
+                return Err(String::from("You're already in a guild, leave it first"));
+            }
+
+            let guild = server
+                .guilds
+                .guilds
+                .iter_mut()
+                .find(|guild| guild.name == guild_name)
+                .ok_or("That guild doesn't exist anymore")?;
+
+            guild.members.insert(username, name.clone());
+            let guild = guild.clone();
+            server.save_guilds();
+
+            tell_guild(server, &guild, format!("{name} joined {}.", guild.name));
+        }
+        "leave" => {
+            let guild = server.guilds.of(&username).ok_or("You aren't in a guild")?;
+            if guild.leader == username && guild.members.len() > 1 {
+                return Err(String::from(
+                    "You lead the guild, kick everyone else or disband it before leaving",
+                ));
+            }
+
+            let guild = server.guilds.of_mut(&username).unwrap();
+            guild.members.remove(&username);
+            let guild = guild.clone();
+
+            server.guilds.guilds.retain(|guild| !guild.members.is_empty());
+            server.save_guilds();
+
+            let message = format!("You left {}.", guild.name);
+            server.send(client_id, &Packet::ChatLog(ChatChannel::Guild, message));
+            tell_guild(server, &guild, format!("{name} left the guild."));
+        }
+        "kick" => {
+            let other_name = args.text(1).ok_or("Who do you want to kick?")?;
+
+            let guild = server.guilds.of_mut(&username).ok_or("You aren't in a guild")?;
+            if guild.leader != username {
+                return Err(String::from("Only the guild leader can kick people"));
+            }
+
+            let other_username = guild
+                .members
+                .iter()
+                .find(|(member, name)| **member != username && name.eq_ignore_ascii_case(other_name))
+                .map(|(member, _)| member.clone())
+                .ok_or_else(|| format!("{other_name} isn't in your guild"))?;
+
+            let other_name = guild.members.remove(&other_username).unwrap();
+            let guild = guild.clone();
+            server.save_guilds();
+
+            if let Some(other_id) = server.find_account(&other_username) {
+                let message = format!("You were kicked from {}.", guild.name);
+                server.send(other_id, &Packet::ChatLog(ChatChannel::Guild, message));
+            }
+
+            tell_guild(server, &guild, format!("{other_name} was kicked from the guild."));
+        }
+        "list" => {
+            let guild = server.guilds.of(&username).ok_or("You aren't in a guild")?;
+            let online = server
+                .guild_members(guild)
+                .into_iter()
+                .map(|member| server.players[&member].username.to_lowercase())
+                .collect::<Vec<_>>();
+
+            let mut names = guild
+                .members
+                .iter()
+                .map(|(member, name)| {
+                    let mut name = name.clone();
+                    if *member == guild.leader {
+                        name.push_str(" (leader)");
+                    }
+                    if online.contains(member) {
+                        name.push_str(" (online)");
+                    }
+                    name
+                })
+                .collect::<Vec<_>>();
+            names.sort();
+
+            let message = format!("{}: {}", guild.name, names.join(", "));
+            server.send(client_id, &Packet::ChatLog(ChatChannel::Echo, message));
+        }
+        "disband" => {
+            let guild = server.guilds.of(&username).ok_or("You aren't in a guild")?;
+            if guild.leader != username {
+                return Err(String::from("Only the guild leader can disband it"));
+            }
+
+            let guild = guild.clone();
+            tell_guild(server, &guild, format!("{name} disbanded {}.", guild.name));
+
+            server.guilds.guilds.retain(|other| other.name != guild.name);
+            server.guilds.invites.retain(|_, invite| *invite != guild.name);
+            server.save_guilds();
+        }
+        action => return Err(format!("There is no {action} action")),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bob is online, and there's an account for him
+    struct Bob;
+
+    impl Lookup for Bob {
+        fn find_player(&self, name: &str) -> Option<ClientId> {
+            name.eq_ignore_ascii_case("bob").then_some(ClientId(1))
+        }
+
+        fn find_account(&self, username: &str) -> anyhow::Result<Option<String>> {
+            Ok(username.eq_ignore_ascii_case("bob").then(|| String::from("bob")))
+        }
+    }
+
+    fn parse(name: &str, line: &str) -> Result<Args, String> {
+        Command::find(name).unwrap().parse(&Bob, line)
+    }
+
+    #[test]
+    fn skipped_optional_arguments_keep_their_place() {
+        let args = parse("ban", "Bob being rude").unwrap();
+        assert_eq!(args.account(0), Some("bob"));
+        assert_eq!(args.duration(1), None);
+        assert_eq!(args.text(2), Some("being rude"));
+
+        let args = parse("banip", "bob being rude").unwrap();
+        assert_eq!(args.player(0), Some(ClientId(1)));
+        assert_eq!(args.duration(1), None);
+        assert_eq!(args.text(2), Some("being rude"));
+    }
+
+    #[test]
+    fn parses_every_optional_argument() {
+        let args = parse("ban", "bob 7d spamming again").unwrap();
+        assert_eq!(args.duration(1), Some(7 * 24 * 60 * 60));
+        assert_eq!(args.text(2), Some("spamming again"));
+
+        let args = parse("ban", "bob").unwrap();
+        assert_eq!(args.duration(1), None);
+        assert_eq!(args.text(2), None);
+
+        let args = parse("warp", "start 96 144.5").unwrap();
+        assert_eq!(args.map(0), Some("start"));
+        assert_eq!(args.coordinates(1), Some(Point2D::new(96.0, 144.5)));
+    }
+
+    #[test]
+    fn quotes_keep_spaces_in_an_argument() {
+        let args = parse("whisper", r#""Some One" hello there "#).unwrap();
+        assert_eq!(args.text(0), Some("Some One"));
+        assert_eq!(args.text(1), Some("hello there"));
+    }
+
+    #[test]
+    fn rejects_missing_invalid_and_extra_arguments() {
+        assert_eq!(parse("mute", "bob").unwrap_err(), "Missing the duration");
+        assert!(parse("mute", "bob soon").is_err());
+        assert!(parse("ban", "alice").is_err());
+        assert!(parse("kick", "alice").is_err());
+        assert!(parse("sprite", "bob three").is_err());
+        assert_eq!(parse("sprite", "bob 3 4").unwrap_err(), "Too many arguments");
+        assert!(parse("warp", "start 96").is_err());
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90s"), Some(90));
+        assert_eq!(parse_duration("30m"), Some(30 * 60));
+        assert_eq!(parse_duration("12h"), Some(12 * 60 * 60));
+        assert_eq!(parse_duration("7d"), Some(7 * 24 * 60 * 60));
+        assert_eq!(parse_duration("2w"), Some(2 * 7 * 24 * 60 * 60));
+
+        assert_eq!(parse_duration("0m"), None);
+        assert_eq!(parse_duration("30"), None);
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration("-5m"), None);
+        assert_eq!(parse_duration("5y"), None);
+        assert_eq!(parse_duration("5mm"), None);
+        assert_eq!(parse_duration("99999999999999999w"), None);
+    }
+}
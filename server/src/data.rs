@@ -1,19 +1,49 @@
 mod map;
 mod player;
+mod storage;
 
-use std::{collections::HashSet, path::PathBuf};
+use std::path::PathBuf;
 
-use anyhow::Result;
+use anyhow::{ensure, Result};
 use euclid::default::Point2D;
 use serde::{Deserialize, Serialize};
 
+use crate::{rate_limit::RateLimitConfig, snapshot::SnapshotConfig};
+
 pub use self::map::*;
 pub use self::player::*;
+pub use self::storage::*;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     pub listen: String,
     pub start: Start,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    /// How often changed players and maps are saved, in seconds
+    #[serde(default = "Config::default_autosave_interval")]
+    pub autosave_interval: u64,
+    /// How long players are warned before the server shuts down, in seconds
+    #[serde(default = "Config::default_shutdown_countdown")]
+    pub shutdown_countdown: u64,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    /// How many times per second the game is updated
+    #[serde(default = "Config::default_tick_rate")]
+    pub tick_rate: u32,
+    #[serde(default)]
+    pub movement: MovementConfig,
+    /// How far away players can see each other, in tiles
+    #[serde(default = "Config::default_view_radius")]
+    pub view_radius: f32,
+    /// How far away players can hear each other in say chat, in tiles
+    #[serde(default = "Config::default_say_radius")]
+    pub say_radius: f32,
+    #[serde(default)]
+    pub snapshots: SnapshotConfig,
+    /// Usernames that are always made owners when they log in, so there's someone to hand out access levels
+    #[serde(default)]
+    pub owners: Vec<String>,
 }
 
 impl Config {
@@ -25,7 +55,49 @@ impl Config {
     }
     pub fn load() -> Result<Self> {
         let contents = std::fs::read_to_string(Self::path())?;
-        Ok(toml::from_str(&contents)?)
+        let config: Self = toml::from_str(&contents)?;
+        config.validate()?;
+
+        Ok(config)
+    }
+    /// Checks for values that would break the server instead of letting it start with them
+    fn validate(&self) -> Result<()> {
+        ensure!(self.tick_rate > 0, "tick_rate must be more than 0");
+        ensure!(self.snapshots.rate > 0, "snapshots.rate must be more than 0");
+        self.rate_limit.validate()
+    }
+    fn default_autosave_interval() -> u64 {
+        300
+    }
+    fn default_shutdown_countdown() -> u64 {
+        10
+    }
+    fn default_tick_rate() -> u32 {
+        60
+    }
+    fn default_view_radius() -> f32 {
+        20.0
+    }
+    fn default_say_radius() -> f32 {
+        12.0
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MovementConfig {
+    /// How far behind the server a client is allowed to be, in seconds of movement
+    pub max_latency: f32,
+    /// How much faster than their allowed speed a client may move, to allow for rounding errors
+    pub speed_tolerance: f32,
+}
+
+impl Default for MovementConfig {
+    fn default() -> Self {
+        Self {
+            max_latency: 0.5,
+            speed_tolerance: 0.01,
+        }
     }
 }
 
@@ -40,43 +112,3 @@ impl Start {
         Point2D::new(self.x, self.y)
     }
 }
-
-#[derive(Debug, Default, Serialize, Deserialize)]
-#[serde(transparent)]
-pub struct NameCache {
-    names: HashSet<String>,
-}
-
-impl NameCache {
-    pub fn path() -> PathBuf {
-        let mut path = common::server_runtime!();
-        path.push("names.cache");
-
-        path
-    }
-    pub fn load() -> Result<Self> {
-        use std::io::{ErrorKind, Result};
-        let contents = match std::fs::read_to_string(Self::path()) {
-            Ok(contents) => Result::Ok(contents),
-            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(NameCache::default()),
-            Err(e) => Result::Err(e),
-        }?;
-
-        Ok(Self {
-            names: contents.lines().map(ToString::to_string).collect(),
-        })
-    }
-    pub fn save(&self) -> Result<()> {
-        let contents = self.names.iter().cloned().collect::<Vec<_>>().join("\n");
-
-        std::fs::write(Self::path(), &contents)?;
-        Ok(())
-    }
-
-    pub fn contains(&self, name: &str) -> bool {
-        self.names.contains(name)
-    }
-    pub fn insert(&mut self, name: String) {
-        self.names.insert(name);
-    }
-}
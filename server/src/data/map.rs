@@ -1,15 +1,14 @@
-use std::{
-    collections::HashMap,
-    path::{Path, PathBuf},
-};
+use std::collections::HashMap;
 
-use anyhow::Result;
 use common::{
-    network::{Map as NetworkMap, MapHash, MapLayer, MapSettings, Tile, Zone},
+    network::{
+        edit::{EditError, MapEdit},
+        Map as NetworkMap, MapHash, MapLayer, MapSettings, Tile, TileAttribute, Zone,
+    },
+    physics::Rect,
     TILE_SIZE,
 };
-use euclid::default::Box2D;
-use ndarray::Array2;
+use ndarray::{indices, s, Array2, Zip};
 use serde::{Deserialize, Serialize};
 use strum::IntoEnumIterator;
 
@@ -23,62 +22,15 @@ pub struct Map {
     pub height: u32,
     pub settings: MapSettings,
     pub layers: HashMap<MapLayer, Array2<Option<Tile>>>,
+    #[serde(default)]
+    pub attributes: Array2<TileAttribute>,
     pub zones: Vec<Zone>,
+    /// Set when the map has changed since it was last saved
+    #[serde(skip)]
+    pub dirty: bool,
 }
 
 impl Map {
-    pub fn path(id: &str) -> PathBuf {
-        let mut path = common::server_runtime!();
-        path.push("maps");
-        path.push(format!("{}.bin", id));
-        path
-    }
-
-    pub fn load(id: &str) -> Result<Self> {
-        let path = Self::path(id);
-        Self::load_path(path)
-    }
-
-    pub fn load_all() -> Result<HashMap<MapHash, Self>> {
-        let mut path = common::server_runtime!();
-        path.push("maps");
-
-        let mut maps = HashMap::new();
-        for entry in std::fs::read_dir(path)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_file() {
-                let mut map = Self::load_path(&path)?;
-                let id = path.file_stem().unwrap().to_string_lossy();
-                let hash = MapHash::from(&*id);
-
-                if map.hash != hash {
-                    log::warn!(
-                        "Map loaded but the file name didn't match it's hash: {:#x} {:#x}",
-                        map.hash.0,
-                        hash.0
-                    );
-
-                    if cfg!(debug_assertions) {
-                        log::debug!("Updating the map's hash to match the file path, this may break warps.");
-                        map.hash = hash;
-                    }
-                }
-
-                maps.insert(map.hash, map);
-            }
-        }
-
-        Ok(maps)
-    }
-
-    fn load_path(path: impl AsRef<Path>) -> Result<Self> {
-        let file = std::fs::File::open(path)?;
-        let map = rmp_serde::from_read(file)?;
-
-        Ok(map)
-    }
-
     pub fn new(id: &str, width: u32, height: u32) -> Self {
         let settings = MapSettings::default();
         let mut layers = HashMap::new();
@@ -96,34 +48,97 @@ impl Map {
             height,
             settings,
             layers,
+            attributes: Array2::default((width as usize, height as usize)),
             zones,
+            dirty: false,
         }
     }
 
-    pub fn save(&self) -> Result<()> {
-        let path = Self::path(&self.id);
+    pub fn bounds(&self) -> Rect {
+        Rect::new(
+            0.0,
+            0.0,
+            self.width as f32 * TILE_SIZE as f32,
+            self.height as f32 * TILE_SIZE as f32,
+        )
+    }
+
+    /// Applies an edit from the map editor, leaving the map untouched if it isn't valid
+    pub fn apply(&mut self, edit: &MapEdit) -> Result<(), EditError> {
+        edit.validate(self.width, self.height, self.zones.len())?;
+
+        let dimensions = (self.width as usize, self.height as usize);
 
-        let map = self.clone();
-        let contents = rmp_serde::to_vec_named(&map)?;
-        std::fs::write(path, contents)?;
+        // maps from before tile attributes existed won't have any
+        if self.attributes.dim() != dimensions {
+            self.attributes = Array2::default(dimensions);
+        }
+
+        match edit {
+            MapEdit::SetTile { layer, position, tile } => {
+                self.layer_mut(*layer)[(position.x as usize, position.y as usize)] = Some(*tile);
+            }
+            MapEdit::ClearTile { layer, position } => {
+                self.layer_mut(*layer)[(position.x as usize, position.y as usize)] = None;
+            }
+            MapEdit::SetTiles { layer, tiles } => {
+                let layer = self.layer_mut(*layer);
+                for (position, tile) in tiles {
+                    layer[(position.x as usize, position.y as usize)] = *tile;
+                }
+            }
+            MapEdit::FillRegion { layer, from, to, tile } => {
+                let (from, to) = ((from.x as usize, from.y as usize), (to.x as usize, to.y as usize));
+                self.layer_mut(*layer)
+                    .slice_mut(s![from.0..=to.0, from.1..=to.1])
+                    .fill(*tile);
+            }
+            MapEdit::SetAttribute { position, attribute } => {
+                self.attributes[(position.x as usize, position.y as usize)] = *attribute;
+            }
+            MapEdit::AddZone(zone) => self.zones.push(zone.clone()),
+            MapEdit::RemoveZone(index) => {
+                self.zones.remove(*index);
+            }
+            MapEdit::InsertZone(index, zone) => self.zones.insert(*index, zone.clone()),
+            MapEdit::ModifyZone(index, zone) => self.zones[*index] = zone.clone(),
+            MapEdit::ChangeSettings(settings) => {
+                let cache_key = self.settings.cache_key;
+                self.settings = settings.clone();
+                self.settings.cache_key = cache_key;
+            }
+            &MapEdit::Resize { width, height } => {
+                let dimensions = (width as usize, height as usize);
+
+                for layer in MapLayer::iter() {
+                    let tiles = resized(self.layer_mut(layer), dimensions);
+                    self.layers.insert(layer, tiles);
+                }
+                self.attributes = resized(&self.attributes, dimensions);
+
+                self.width = width;
+                self.height = height;
+
+                let bounds = self.bounds();
+                self.zones.retain(|zone| bounds.overlaps(&Rect::from(zone)));
+            }
+        }
 
         Ok(())
     }
 
-    pub fn to_box2d(&self) -> Box2D<f32> {
-        use euclid::default::{Point2D, Rect, Size2D};
-
-        Rect::new(
-            Point2D::zero(),
-            Size2D::new(
-                self.width as f32 * TILE_SIZE as f32,
-                self.height as f32 * TILE_SIZE as f32,
-            ),
-        )
-        .to_box2d()
+    /// Gets a layer's tiles, filling it in if the map is somehow missing it
+    fn layer_mut(&mut self, layer: MapLayer) -> &mut Array2<Option<Tile>> {
+        let dimensions = (self.width as usize, self.height as usize);
+        self.layers.entry(layer).or_insert_with(|| Array2::default(dimensions))
     }
 }
 
+/// Copies as much of `array` as fits into a new one of a different size
+fn resized<T: Clone + Default>(array: &Array2<T>, dimensions: (usize, usize)) -> Array2<T> {
+    Zip::from(indices(dimensions)).map_collect(|index| array.get(index).cloned().unwrap_or_default())
+}
+
 impl From<NetworkMap> for Map {
     fn from(other: NetworkMap) -> Self {
         Self {
@@ -133,7 +148,9 @@ impl From<NetworkMap> for Map {
             height: other.height,
             settings: other.settings,
             layers: other.layers,
+            attributes: other.attributes,
             zones: other.zones,
+            dirty: false,
         }
     }
 }
@@ -147,6 +164,7 @@ impl From<Map> for NetworkMap {
             height: other.height,
             settings: other.settings,
             layers: other.layers,
+            attributes: other.attributes,
             zones: other.zones,
         }
     }
@@ -1,10 +1,56 @@
-use std::path::PathBuf;
+use common::{
+    network::{Direction, MapHash, Player as NetworkPlayer, PlayerFlags},
+    RUN_SPEED,
+};
+use std::{fmt::Display, str::FromStr, time::Instant};
 
-use anyhow::Result;
-use common::network::{Direction, MapHash, Player as NetworkPlayer, PlayerFlags};
 use euclid::default::{Point2D, Vector2D};
 use serde::{Deserialize, Serialize};
 
+/// What an account is allowed to do, every level can do everything the ones below it can
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLevel {
+    #[default]
+    Player,
+    /// Can warp around and look after other players
+    Moderator,
+    /// Can use the map editor
+    Mapper,
+    Developer,
+    /// Can change everyone else's access level
+    Owner,
+}
+
+impl Display for AccessLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            AccessLevel::Player => "player",
+            AccessLevel::Moderator => "moderator",
+            AccessLevel::Mapper => "mapper",
+            AccessLevel::Developer => "developer",
+            AccessLevel::Owner => "owner",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for AccessLevel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "player" => Ok(AccessLevel::Player),
+            "moderator" => Ok(AccessLevel::Moderator),
+            "mapper" => Ok(AccessLevel::Mapper),
+            "developer" => Ok(AccessLevel::Developer),
+            "owner" => Ok(AccessLevel::Owner),
+            _ => Err(()),
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Player {
     pub username: String,
@@ -14,11 +60,26 @@ pub struct Player {
     pub map: MapHash,
     pub position: Point2D<f32>,
     pub direction: Direction,
+    #[serde(default)]
+    pub access: AccessLevel,
     #[serde(skip)]
     pub velocity: Option<Vector2D<f32>>,
 
     #[serde(skip)]
     pub flags: PlayerFlags,
+    /// Set when the player has changed since it was last saved
+    #[serde(skip)]
+    pub dirty: bool,
+    /// Sequence number of the last move the client sent us
+    #[serde(skip)]
+    pub last_sequence: u32,
+    /// When and where the last move the client sent us was, the next one is measured from here
+    #[serde(skip)]
+    pub last_move: Option<(Instant, Point2D<f32>)>,
+    /// How much further the player can move right now, in pixels. It fills back up at the player's top speed,
+    /// so moves can arrive bunched together but never add up to more than that speed.
+    #[serde(skip)]
+    pub move_budget: f32,
 }
 
 impl Default for Player {
@@ -30,9 +91,14 @@ impl Default for Player {
             sprite: 0,
             position: Point2D::new(0.0, 0.0),
             direction: Direction::South,
+            access: AccessLevel::default(),
             map: MapHash::start(),
             flags: PlayerFlags::default(),
             velocity: None,
+            dirty: false,
+            last_sequence: 0,
+            last_move: None,
+            move_budget: 0.0,
         }
     }
 }
@@ -51,32 +117,11 @@ impl From<Player> for NetworkPlayer {
 }
 
 impl Player {
-    pub fn path(name: &str) -> PathBuf {
-        let mut path = common::server_runtime!();
-        path.push("players");
-        path.push(format!("{name}.toml"));
-        path
-    }
-    pub fn load(name: &str) -> Result<Self> {
-        let path = Self::path(name);
-
-        let contents = std::fs::read_to_string(path)?;
-        let player = toml::from_str(&contents)?;
-
-        Ok(player)
+    /// Fastest the player is allowed to move, in pixels per second
+    pub fn max_speed(&self) -> f32 {
+        RUN_SPEED as f32
     }
-    pub fn save(&self) -> Result<()> {
-        let path = Self::path(&self.username);
 
-        let player = self.clone();
-        let contents = toml::to_string_pretty(&player)?;
-        std::fs::write(path, contents)?;
-
-        Ok(())
-    }
-}
-
-impl Player {
     pub fn new(username: &str, password: &str, name: &str, map: MapHash, position: Point2D<f32>) -> Self {
         Self {
             username: username.into(),
@@ -85,9 +130,14 @@ impl Player {
             sprite: 0,
             position,
             direction: Direction::South,
+            access: AccessLevel::default(),
             map,
             velocity: None,
             flags: PlayerFlags::default(),
+            dirty: false,
+            last_sequence: 0,
+            last_move: None,
+            move_budget: 0.0,
         }
     }
 }
@@ -0,0 +1,84 @@
+mod file;
+mod sqlite;
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use common::network::MapHash;
+use serde::{Deserialize, Serialize};
+
+use super::{Map, Player};
+
+pub use self::file::*;
+pub use self::sqlite::*;
+
+/// Outcome of trying to create a new account
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CreatePlayer {
+    Created,
+    UsernameTaken,
+    NameTaken,
+}
+
+/// Persistence backend for everything the server saves
+pub trait Storage {
+    /// Loads a player by their username ignoring case, returning `None` if the account doesn't exist
+    fn load_player(&self, username: &str) -> Result<Option<Player>>;
+    /// Loads a player by their character name, ignoring case, returning `None` if nobody has it
+    fn find_player(&self, name: &str) -> Result<Option<Player>>;
+    /// Saves an existing player, overwriting what was there. It's an error if the account doesn't exist
+    fn save_player(&self, player: &Player) -> Result<()>;
+    /// Creates a new player, reserving their username and character name.
+    ///
+    /// Both names are compared case-insensitively, and nothing is written unless both are free.
+    fn create_player(&self, player: &Player) -> Result<CreatePlayer>;
+
+    fn load_maps(&self) -> Result<HashMap<MapHash, Map>>;
+    fn save_map(&self, map: &Map) -> Result<()>;
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StorageConfig {
+    /// Players as toml files, maps as MessagePack files, all in the runtime folder
+    #[default]
+    File,
+    /// Everything in a single SQLite database, relative to the runtime folder
+    Sqlite { path: PathBuf },
+}
+
+impl StorageConfig {
+    pub fn open(&self) -> Result<Box<dyn Storage>> {
+        Ok(match self {
+            StorageConfig::File => Box::new(FileStorage::open()?),
+            StorageConfig::Sqlite { path } => {
+                let mut full_path = common::server_runtime!();
+                full_path.push(path);
+
+                Box::new(SqliteStorage::open(full_path)?)
+            }
+        })
+    }
+}
+
+/// Writes a file by writing a temporary file next to it and renaming it over the target.
+///
+/// The rename is atomic, so a crash part way through leaves either the old or new contents, never half of each.
+pub fn write_atomic(path: impl AsRef<Path>, contents: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+
+    let mut file = File::create(&temp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+
+    std::fs::rename(&temp_path, path)?;
+
+    Ok(())
+}
@@ -0,0 +1,329 @@
+use std::{
+    cell::RefCell,
+    collections::{hash_map::Entry, HashMap},
+    fs::{File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use common::network::MapHash;
+
+use super::{write_atomic, CreatePlayer, Storage};
+use crate::data::{Map, Player};
+
+/// How many old copies of each map are kept in `maps/backups`
+const MAP_BACKUPS: usize = 3;
+
+/// The original storage layout, one file per player and map inside the runtime folder.
+///
+/// Player files are named after their username in lowercase, so usernames ignore case like they do in SQLite.
+pub struct FileStorage {
+    root: PathBuf,
+    names: RefCell<NameCache>,
+}
+
+impl FileStorage {
+    pub fn open() -> Result<Self> {
+        let root = common::server_runtime!();
+        let storage = Self {
+            names: RefCell::new(NameCache::load(root.join("names.cache"))?),
+            root,
+        };
+        storage.lowercase_player_files()?;
+
+        if storage.names.borrow().outdated {
+            storage.rebuild_name_cache()?;
+        }
+
+        Ok(storage)
+    }
+
+    fn player_path(&self, username: &str) -> PathBuf {
+        let mut path = self.root.join("players");
+        path.push(format!("{}.toml", username.to_lowercase()));
+        path
+    }
+
+    /// Renames player files saved before usernames ignored case, so they can still be found
+    fn lowercase_player_files(&self) -> Result<()> {
+        let entries = match std::fs::read_dir(self.root.join("players")) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+            let username = match path.file_stem() {
+                Some(stem) if path.extension().is_some_and(|ext| ext == "toml") => stem.to_string_lossy(),
+                _ => continue,
+            };
+
+            let lowercase = self.player_path(&username);
+            if lowercase == path {
+                continue;
+            }
+
+            if lowercase.exists() {
+                log::warn!(
+                    "Couldn't rename {}, another account has the same username in a different case",
+                    path.display()
+                );
+                continue;
+            }
+
+            log::info!("Renaming {} to {}", path.display(), lowercase.display());
+            std::fs::rename(&path, &lowercase)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads every player file to work out who has each character name, for caches from before it kept usernames
+    fn rebuild_name_cache(&self) -> Result<()> {
+        log::info!("Rebuilding the character name cache, this may take a while");
+
+        let mut names = self.names.borrow_mut();
+        names.names.clear();
+
+        let entries = match std::fs::read_dir(self.root.join("players")) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return names.save(),
+            Err(e) => return Err(e.into()),
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+            let is_player = path.is_file() && path.extension().is_some_and(|ext| ext == "toml");
+            if !is_player {
+                continue;
+            }
+
+            let player: Player = toml::from_str(&std::fs::read_to_string(&path)?)?;
+            if !names.reserve(&player.name, &player.username) {
+                log::warn!("{} has the same character name as someone else", player.username);
+            }
+        }
+
+        names.save()
+    }
+
+    fn map_path(&self, id: &str) -> PathBuf {
+        let mut path = self.root.join("maps");
+        path.push(format!("{}.bin", id));
+        path
+    }
+
+    fn map_backup_path(&self, id: &str, generation: usize) -> PathBuf {
+        let mut path = self.root.join("maps");
+        path.push("backups");
+        path.push(format!("{}.{}.bin", id, generation));
+        path
+    }
+
+    /// Shifts every backup of a map down by one, then copies the current file in as the newest backup
+    fn rotate_map_backups(&self, id: &str) -> Result<()> {
+        let current = self.map_path(id);
+        if !current.exists() {
+            return Ok(());
+        }
+
+        std::fs::create_dir_all(self.root.join("maps").join("backups"))?;
+
+        for generation in (1..MAP_BACKUPS).rev() {
+            let from = self.map_backup_path(id, generation);
+            if from.exists() {
+                std::fs::rename(from, self.map_backup_path(id, generation + 1))?;
+            }
+        }
+
+        std::fs::copy(current, self.map_backup_path(id, 1))?;
+
+        Ok(())
+    }
+
+    fn load_map(path: impl AsRef<Path>) -> Result<Map> {
+        let file = File::open(path)?;
+        let map = rmp_serde::from_read(file)?;
+
+        Ok(map)
+    }
+}
+
+impl Storage for FileStorage {
+    fn load_player(&self, username: &str) -> Result<Option<Player>> {
+        let contents = match std::fs::read_to_string(self.player_path(username)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Some(toml::from_str(&contents)?))
+    }
+
+    fn find_player(&self, name: &str) -> Result<Option<Player>> {
+        let username = self.names.borrow().username(name).map(str::to_string);
+
+        match username {
+            Some(username) => self.load_player(&username),
+            None => Ok(None),
+        }
+    }
+
+    fn save_player(&self, player: &Player) -> Result<()> {
+        let path = self.player_path(&player.username);
+        if !path.exists() {
+            bail!("There is no account named {}", player.username);
+        }
+
+        let contents = toml::to_string_pretty(player)?;
+        write_atomic(path, contents.as_bytes())?;
+
+        Ok(())
+    }
+
+    fn create_player(&self, player: &Player) -> Result<CreatePlayer> {
+        if self.player_path(&player.username).exists() {
+            return Ok(CreatePlayer::UsernameTaken);
+        }
+
+        if self.names.borrow().username(&player.name).is_some() {
+            return Ok(CreatePlayer::NameTaken);
+        }
+
+        // create_new so that we never overwrite an account, even if the check above raced something
+        let contents = toml::to_string_pretty(player)?;
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(self.player_path(&player.username));
+
+        let mut file = match file {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => return Ok(CreatePlayer::UsernameTaken),
+            Err(e) => return Err(e.into()),
+        };
+        file.write_all(contents.as_bytes())?;
+
+        let mut names = self.names.borrow_mut();
+        names.reserve(&player.name, &player.username);
+        names.save()?;
+
+        Ok(CreatePlayer::Created)
+    }
+
+    fn load_maps(&self) -> Result<HashMap<MapHash, Map>> {
+        let mut maps = HashMap::new();
+        for entry in std::fs::read_dir(self.root.join("maps"))? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "bin") {
+                let mut map = Self::load_map(&path)?;
+                let id = path.file_stem().unwrap().to_string_lossy();
+                let hash = MapHash::from(&*id);
+
+                if map.hash != hash {
+                    log::warn!(
+                        "Map loaded but the file name didn't match it's hash: {:#x} {:#x}",
+                        map.hash.0,
+                        hash.0
+                    );
+
+                    if cfg!(debug_assertions) {
+                        log::debug!("Updating the map's hash to match the file path, this may break warps.");
+                        map.hash = hash;
+                    }
+                }
+
+                map.id = id.to_string();
+                maps.insert(map.hash, map);
+            }
+        }
+
+        Ok(maps)
+    }
+
+    fn save_map(&self, map: &Map) -> Result<()> {
+        let contents = rmp_serde::to_vec_named(map)?;
+
+        if let Err(e) = self.rotate_map_backups(&map.id) {
+            log::warn!("Couldn't back up map {}: {e}", map.id);
+        }
+
+        write_atomic(self.map_path(&map.id), &contents)?;
+
+        Ok(())
+    }
+}
+
+/// Username of whoever has each character name, by the name in lowercase so lookups ignore case.
+///
+/// Saved as a line per name, with the name and username separated by a tab.
+#[derive(Debug, Default)]
+struct NameCache {
+    path: PathBuf,
+    names: HashMap<String, String>,
+    /// The file is missing or only has names, so it needs rebuilding from the player files
+    outdated: bool,
+}
+
+impl NameCache {
+    fn load(path: PathBuf) -> Result<Self> {
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Ok(Self {
+                    path,
+                    outdated: true,
+                    ..Default::default()
+                })
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut cache = Self {
+            path,
+            ..Default::default()
+        };
+
+        for line in contents.lines().filter(|line| !line.is_empty()) {
+            match line.split_once('\t') {
+                Some((name, username)) => {
+                    cache.reserve(name, username);
+                }
+                None => cache.outdated = true,
+            }
+        }
+
+        Ok(cache)
+    }
+
+    fn save(&mut self) -> Result<()> {
+        let contents = self
+            .names
+            .iter()
+            .map(|(name, username)| format!("{name}\t{username}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        write_atomic(&self.path, contents.as_bytes())?;
+        self.outdated = false;
+        Ok(())
+    }
+
+    fn username(&self, name: &str) -> Option<&str> {
+        self.names.get(&name.to_lowercase()).map(String::as_str)
+    }
+
+    /// Reserves a name for an account, returning false if it was already taken
+    fn reserve(&mut self, name: &str, username: &str) -> bool {
+        match self.names.entry(name.to_lowercase()) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(username.to_string());
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{bail, Result};
+use common::network::MapHash;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::{CreatePlayer, Storage};
+use crate::data::{Map, Player};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS players (
+        username TEXT NOT NULL PRIMARY KEY COLLATE NOCASE,
+        name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+        data BLOB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS maps (
+        hash INTEGER NOT NULL PRIMARY KEY,
+        id TEXT NOT NULL,
+        data BLOB NOT NULL
+    );
+";
+
+/// Stores everything in a single SQLite database.
+///
+/// Rows keep their lookup keys in columns, and the rest of the data as MessagePack so new
+/// fields don't need a migration.
+pub struct SqliteStorage {
+    connection: Connection,
+}
+
+impl SqliteStorage {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(Self { connection })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load_player(&self, username: &str) -> Result<Option<Player>> {
+        let data: Option<Vec<u8>> = self
+            .connection
+            .query_row("SELECT data FROM players WHERE username = ?1", [username], |row| {
+                row.get(0)
+            })
+            .optional()?;
+
+        match data {
+            Some(data) => Ok(Some(rmp_serde::from_slice(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn find_player(&self, name: &str) -> Result<Option<Player>> {
+        let data: Option<Vec<u8>> = self
+            .connection
+            .query_row("SELECT data FROM players WHERE name = ?1", [name], |row| row.get(0))
+            .optional()?;
+
+        match data {
+            Some(data) => Ok(Some(rmp_serde::from_slice(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save_player(&self, player: &Player) -> Result<()> {
+        let data = rmp_serde::to_vec_named(player)?;
+        let updated = self.connection.execute(
+            "UPDATE players SET name = ?2, data = ?3 WHERE username = ?1",
+            params![player.username, player.name, data],
+        )?;
+
+        if updated == 0 {
+            bail!("There is no account named {}", player.username);
+        }
+
+        Ok(())
+    }
+
+    fn create_player(&self, player: &Player) -> Result<CreatePlayer> {
+        let transaction = self.connection.unchecked_transaction()?;
+
+        let username_taken = transaction
+            .query_row("SELECT 1 FROM players WHERE username = ?1", [&player.username], |_| {
+                Ok(())
+            })
+            .optional()?
+            .is_some();
+
+        if username_taken {
+            return Ok(CreatePlayer::UsernameTaken);
+        }
+
+        let name_taken = transaction
+            .query_row("SELECT 1 FROM players WHERE name = ?1", [&player.name], |_| Ok(()))
+            .optional()?
+            .is_some();
+
+        if name_taken {
+            return Ok(CreatePlayer::NameTaken);
+        }
+
+        let data = rmp_serde::to_vec_named(player)?;
+        transaction.execute(
+            "INSERT INTO players (username, name, data) VALUES (?1, ?2, ?3)",
+            params![player.username, player.name, data],
+        )?;
+        transaction.commit()?;
+
+        Ok(CreatePlayer::Created)
+    }
+
+    fn load_maps(&self) -> Result<HashMap<MapHash, Map>> {
+        let mut statement = self.connection.prepare("SELECT id, data FROM maps")?;
+        let rows = statement.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?)))?;
+
+        let mut maps = HashMap::new();
+        for row in rows {
+            let (id, data) = row?;
+            let mut map: Map = rmp_serde::from_slice(&data)?;
+            map.id = id;
+
+            maps.insert(map.hash, map);
+        }
+
+        Ok(maps)
+    }
+
+    fn save_map(&self, map: &Map) -> Result<()> {
+        let data = rmp_serde::to_vec_named(map)?;
+        self.connection.execute(
+            "INSERT INTO maps (hash, id, data) VALUES (?1, ?2, ?3)
+                ON CONFLICT (hash) DO UPDATE SET id = excluded.id, data = excluded.data",
+            params![map.hash.0, map.id, data],
+        )?;
+
+        Ok(())
+    }
+}
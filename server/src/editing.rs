@@ -0,0 +1,147 @@
+use std::collections::VecDeque;
+
+use common::network::{edit::MapEdit, ClientId};
+
+/// Most batches of edits kept around per map to rebase late edits against
+const LOG_LENGTH: usize = 256;
+
+/// Recent edits to a single map, in the order they were applied.
+///
+/// Editors keep working while their edits are on the way here, so each batch says which revision it was made
+/// against and gets adjusted for everything other editors did since.
+pub struct EditLog {
+    revision: u32,
+    /// Batches newer than the oldest revision that can still be rebased against, oldest first
+    recent: VecDeque<(u32, ClientId, Vec<MapEdit>)>,
+}
+
+impl EditLog {
+    pub fn new() -> Self {
+        Self {
+            revision: 0,
+            recent: VecDeque::new(),
+        }
+    }
+
+    pub fn revision(&self) -> u32 {
+        self.revision
+    }
+
+    /// Checks if edits made against `base` can still be brought up to date
+    pub fn covers(&self, base: u32) -> bool {
+        if base > self.revision {
+            return false;
+        }
+
+        // every batch after `base` needs to still be here
+        base == self.revision || matches!(self.recent.front(), Some((oldest, _, _)) if *oldest <= base + 1)
+    }
+
+    /// Adjusts an edit made against `base` for everything other clients have done since,
+    /// returns `None` if it no longer applies to anything.
+    pub fn rebase(&self, base: u32, author: ClientId, edit: &MapEdit) -> Option<MapEdit> {
+        let mut edit = edit.clone();
+
+        // the author already had their own edits when making this one
+        let concurrent = self
+            .recent
+            .iter()
+            .filter(|(revision, other, _)| *revision > base && *other != author)
+            .flat_map(|(_, _, edits)| edits);
+
+        for earlier in concurrent {
+            edit = edit.rebase(earlier)?;
+        }
+
+        Some(edit)
+    }
+
+    /// Records a batch of edits that's been applied, returning the map's new revision
+    pub fn push(&mut self, author: ClientId, edits: Vec<MapEdit>) -> u32 {
+        self.revision += 1;
+
+        self.recent.push_back((self.revision, author, edits));
+        if self.recent.len() > LOG_LENGTH {
+            self.recent.pop_front();
+        }
+
+        self.revision
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::network::{Zone, ZoneData};
+    use mint::{Point2, Vector2};
+
+    use super::*;
+
+    const ALICE: ClientId = ClientId(1);
+    const BOB: ClientId = ClientId(2);
+
+    fn zone() -> Zone {
+        Zone {
+            position: Point2 { x: 0.0, y: 0.0 },
+            size: Vector2 { x: 48.0, y: 48.0 },
+            data: ZoneData::Blocked,
+        }
+    }
+
+    #[test]
+    fn covers_every_revision_still_in_the_log() {
+        let mut log = EditLog::new();
+        assert!(log.covers(0));
+        assert!(!log.covers(1));
+
+        for _ in 0..LOG_LENGTH + 1 {
+            log.push(ALICE, vec![MapEdit::RemoveZone(0)]);
+        }
+
+        // the first batch has been forgotten, so anything made before it can't be brought up to date
+        let revision = log.revision();
+        assert_eq!(revision, LOG_LENGTH as u32 + 1);
+        assert!(!log.covers(0));
+        assert!(log.covers(1));
+        assert!(log.covers(revision));
+        assert!(!log.covers(revision + 1));
+    }
+
+    #[test]
+    fn rebases_over_everyone_elses_edits_since_the_base() {
+        let mut log = EditLog::new();
+        log.push(ALICE, vec![MapEdit::RemoveZone(0)]);
+        log.push(ALICE, vec![MapEdit::InsertZone(3, zone())]);
+        log.push(ALICE, vec![MapEdit::RemoveZone(1)]);
+
+        // made without knowing about any of alice's edits
+        assert_eq!(
+            log.rebase(0, BOB, &MapEdit::RemoveZone(4)),
+            Some(MapEdit::RemoveZone(3))
+        );
+        assert_eq!(log.rebase(0, BOB, &MapEdit::ModifyZone(0, zone())), None);
+
+        // made after the first one
+        assert_eq!(
+            log.rebase(1, BOB, &MapEdit::RemoveZone(2)),
+            Some(MapEdit::RemoveZone(1))
+        );
+
+        // alice already knew about her own edits
+        assert_eq!(
+            log.rebase(0, ALICE, &MapEdit::RemoveZone(4)),
+            Some(MapEdit::RemoveZone(4))
+        );
+    }
+
+    #[test]
+    fn resizing_drops_concurrent_zone_edits() {
+        let mut log = EditLog::new();
+        log.push(ALICE, vec![MapEdit::Resize { width: 5, height: 5 }]);
+
+        assert_eq!(log.rebase(0, BOB, &MapEdit::RemoveZone(0)), None);
+        assert_eq!(
+            log.rebase(1, BOB, &MapEdit::RemoveZone(0)),
+            Some(MapEdit::RemoveZone(0))
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use std::{collections::HashMap, ops::RangeInclusive, path::PathBuf};
+
+use anyhow::Result;
+use common::network::ClientId;
+use serde::{Deserialize, Serialize};
+
+use crate::data::write_atomic;
+
+const GUILD_NAME_LENGTH: RangeInclusive<usize> = 3..=24;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Guild {
+    pub name: String,
+    /// Username of whoever runs the guild, in lowercase
+    pub leader: String,
+    /// Character name of everyone in the guild including the leader, by their username in lowercase
+    pub members: HashMap<String, String>,
+}
+
+/// Every guild on the server, kept in the runtime folder so they last between restarts
+#[derive(Default, Serialize, Deserialize)]
+pub struct Guilds {
+    #[serde(default)]
+    pub guilds: Vec<Guild>,
+    /// Which guild each online player has been invited to, by the guild's name
+    #[serde(skip)]
+    pub invites: HashMap<ClientId, String>,
+}
+
+impl Guilds {
+    pub fn path() -> PathBuf {
+        let mut path = common::server_runtime!();
+        path.push("guilds.toml");
+
+        path
+    }
+
+    /// Loads every guild, starting out with none if nothing's been saved yet
+    pub fn load() -> Result<Self> {
+        match std::fs::read_to_string(Self::path()) {
+            Ok(contents) => Ok(toml::from_str(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        write_atomic(Self::path(), toml::to_string_pretty(self)?.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Looks up a guild by its name, ignoring case
+    pub fn find(&self, name: &str) -> Option<&Guild> {
+        self.guilds.iter().find(|guild| guild.name.eq_ignore_ascii_case(name))
+    }
+
+    /// The guild an account is in, if it's in one
+    pub fn of(&self, username: &str) -> Option<&Guild> {
+        let username = username.to_lowercase();
+        self.guilds.iter().find(|guild| guild.members.contains_key(&username))
+    }
+
+    pub fn of_mut(&mut self, username: &str) -> Option<&mut Guild> {
+        let username = username.to_lowercase();
+        self.guilds
+            .iter_mut()
+            .find(|guild| guild.members.contains_key(&username))
+    }
+}
+
+/// Checks a guild name follows the same rules as character names
+pub fn validate_guild_name(name: &str) -> Result<(), String> {
+    if !GUILD_NAME_LENGTH.contains(&name.chars().count()) {
+        return Err(format!(
+            "Guild names have to be between {} and {} characters long",
+            GUILD_NAME_LENGTH.start(),
+            GUILD_NAME_LENGTH.end()
+        ));
+    }
+
+    // words of letters and numbers, separated by single spaces
+    let valid = name
+        .split(' ')
+        .all(|word| !word.is_empty() && word.chars().all(|c| c.is_ascii_alphanumeric()));
+
+    if !valid {
+        return Err(String::from(
+            "Guild names can only have letters and numbers, with single spaces between words",
+        ));
+    }
+
+    Ok(())
+}
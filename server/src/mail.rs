@@ -0,0 +1,68 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::data::write_atomic;
+
+/// Most messages that can be waiting for one player, anything past this is turned away
+pub const MAX_MAIL: usize = 20;
+
+/// A whisper sent to someone who wasn't online
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Letter {
+    /// Character name of whoever sent it
+    pub from: String,
+    /// When it was sent as a unix timestamp
+    pub sent: i64,
+    pub message: String,
+}
+
+/// Whispers waiting to be delivered the next time their player logs in, kept in the runtime folder
+///
+/// Players are looked up by their username in lowercase.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Mailbox {
+    #[serde(default)]
+    pub mail: HashMap<String, Vec<Letter>>,
+}
+
+impl Mailbox {
+    pub fn path() -> PathBuf {
+        let mut path = common::server_runtime!();
+        path.push("mail.toml");
+
+        path
+    }
+
+    /// Loads all of the waiting mail, starting out empty if nothing's been saved yet
+    pub fn load() -> Result<Self> {
+        match std::fs::read_to_string(Self::path()) {
+            Ok(contents) => Ok(toml::from_str(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        write_atomic(Self::path(), toml::to_string_pretty(self)?.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Leaves a letter for an account, returning false if their mailbox is full
+    pub fn send(&mut self, username: &str, letter: Letter) -> bool {
+        let letters = self.mail.entry(username.to_lowercase()).or_default();
+        if letters.len() >= MAX_MAIL {
+            return false;
+        }
+
+        letters.push(letter);
+        true
+    }
+
+    /// Takes every letter waiting for an account
+    pub fn take(&mut self, username: &str) -> Vec<Letter> {
+        self.mail.remove(&username.to_lowercase()).unwrap_or_default()
+    }
+}
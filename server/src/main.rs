@@ -1,33 +1,64 @@
+mod command;
 mod data;
+mod editing;
+mod guild;
+mod mail;
+mod moderation;
+mod party;
+mod password;
 mod player;
+mod rate_limit;
+mod registration;
+mod snapshot;
+mod spatial;
 
 use std::{
-    collections::{hash_map::Entry, HashMap},
+    collections::{hash_map::Entry, HashMap, HashSet},
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 
 use anyhow::{bail, Context, Result};
-use base64ct::{Base64, Encoding};
 use chrono::Utc;
 use common::{
+    format_duration,
     network::{
+        self,
         client::Packet as ClientPacket,
+        edit::MapEdit,
         server::{FailJoinReason, Packet},
-        ChatChannel, ClientId, Direction, MapHash, Zone, ZoneData,
+        snapshot::PlayerState,
+        ChatChannel, ClientId, Direction, MapHash, TileAttribute, Zone, ZoneData, PROTOCOL_VERSION,
     },
-    SPRITE_SIZE,
+    physics, SPRITE_SIZE, TILE_SIZE,
 };
 use env_logger::WriteStyle;
-use euclid::default::{Box2D, Point2D, Size2D, Vector2D};
+use euclid::default::{Point2D, Vector2D};
 use log::LevelFilter;
 use message_io::{
     network::{Endpoint, NetworkController, Transport},
-    node::{self, NodeHandler, StoredNetEvent},
+    node::{self, NodeHandler, StoredNetEvent, StoredNodeEvent},
 };
 use rand::prelude::*;
-use sha2::{Digest, Sha256};
 
-use crate::data::{Config, Map, NameCache, Player};
+use crate::{
+    command::Command,
+    data::{AccessLevel, Config, Map, Player, Storage},
+    editing::EditLog,
+    guild::{Guild, Guilds},
+    mail::Mailbox,
+    moderation::Moderation,
+    party::Parties,
+    password::{Job, Outcome, Verified, Worker},
+    rate_limit::{Limit, RateLimiter},
+    registration::Registration,
+    snapshot::SnapshotHistory,
+    spatial::MapIndex,
+};
 
 fn main() -> Result<()> {
     #[cfg(debug_assertions)]
@@ -36,11 +67,51 @@ fn main() -> Result<()> {
         .write_style(WriteStyle::Always)
         .init();
 
-    let game_server = GameServer::new()?;
+    // counts how many times we've been asked to stop, so a second ctrl+c can skip the countdown
+    let shutdown_requests = Arc::new(AtomicUsize::new(0));
+    let requests = Arc::clone(&shutdown_requests);
+    ctrlc::set_handler(move || {
+        requests.fetch_add(1, Ordering::SeqCst);
+    })
+    .context("set signal handler")?;
+
+    let game_server = GameServer::new(shutdown_requests)?;
     game_server.run();
     Ok(())
 }
 
+/// Most ticks we'll run to catch up before giving up on the lost time
+const MAX_TICKS_PER_UPDATE: u32 = 5;
+
+/// How much further than the view radius a player has to go before they're hidden again,
+/// so someone standing right on the edge doesn't flicker in and out
+const VIEW_LEAVE_MARGIN: f32 = 1.2;
+
+/// How far a move can end up from where the server's sweep of it stops before it counts as blocked, in pixels
+const PATH_TOLERANCE: f32 = 1.0;
+
+struct Shutdown {
+    reason: String,
+    /// When the server will actually stop
+    at: Instant,
+    /// The last number of seconds that players were warned about
+    announced: Option<u64>,
+}
+
+/// Events the server sends itself from other threads
+enum Signal {
+    /// A password job for a client that's logging in or registering has finished
+    Password(ClientId, Outcome),
+}
+
+/// A client that's logging in or registering, waiting on the password thread
+enum PendingLogin {
+    /// Registering a new account, the player's password is filled in once it's been hashed
+    Register(Player),
+    /// Logging in to an existing account
+    Login(Player),
+}
+
 #[derive(Copy, Clone, Default, Debug)]
 struct WarpParams {
     initial: bool,
@@ -50,201 +121,438 @@ struct WarpParams {
 
 struct GameServer {
     config: Config,
+    storage: Box<dyn Storage>,
     players: HashMap<ClientId, Player>,
     peer_map: HashMap<ClientId, Endpoint>,
+    endpoints: HashMap<Endpoint, ClientId>,
+    next_client_id: u64,
+    /// Clients that have finished the version handshake
+    greeted: HashSet<ClientId>,
+    pending_logins: HashMap<ClientId, PendingLogin>,
+    rate_limits: HashMap<ClientId, RateLimiter>,
     maps: HashMap<MapHash, Map>,
+    /// Zones and players of every map, indexed by where they are
+    spatial: HashMap<MapHash, MapIndex>,
+    /// Recent edits to every map that's been edited since the server started
+    edit_logs: HashMap<MapHash, EditLog>,
+    moderation: Moderation,
+    parties: Parties,
+    guilds: Guilds,
+    mailbox: Mailbox,
+    /// Character name of whoever last whispered each player, for `/reply`
+    reply_to: HashMap<ClientId, String>,
+    /// Other players each client knows about, always mutual since it's based on distance
+    visible: HashMap<ClientId, HashSet<ClientId>>,
+    snapshots: HashMap<ClientId, SnapshotHistory>,
+    last_snapshot: Instant,
     time: Instant,
-    /// Time since last update
+    /// Length of a single tick, the time each update steps the game by
     dt: Duration,
-    handler: Option<NodeHandler<()>>,
+    last_autosave: Instant,
+    shutdown_requests: Arc<AtomicUsize>,
+    shutdown: Option<Shutdown>,
+    handler: Option<NodeHandler<Signal>>,
+    passwords: Option<Worker>,
     rng: ThreadRng,
 }
 
 impl GameServer {
-    pub fn new() -> Result<Self> {
+    pub fn new(shutdown_requests: Arc<AtomicUsize>) -> Result<Self> {
         let config = Config::load().context("load config")?;
-        let mut maps = Map::load_all().context("load maps")?;
+        let storage = config.storage.open().context("open storage")?;
+        let mut maps = storage.load_maps().context("load maps")?;
 
         if let Entry::Vacant(e) = maps.entry(MapHash::start()) {
-            e.insert(create_map("start"));
+            e.insert(create_map(&*storage, "start"));
         }
 
+        let spatial = maps.iter().map(|(hash, map)| (*hash, MapIndex::new(map))).collect();
+        let moderation = Moderation::load().context("load moderation")?;
+        let guilds = Guilds::load().context("load guilds")?;
+        let mailbox = Mailbox::load().context("load mail")?;
+
         Ok(Self {
             config,
+            storage,
             players: HashMap::new(),
             peer_map: HashMap::new(),
+            endpoints: HashMap::new(),
+            next_client_id: 0,
+            greeted: HashSet::new(),
+            pending_logins: HashMap::new(),
+            rate_limits: HashMap::new(),
             time: Instant::now(),
             dt: Duration::ZERO,
+            last_autosave: Instant::now(),
+            shutdown_requests,
+            shutdown: None,
             handler: None,
+            passwords: None,
             maps,
+            spatial,
+            edit_logs: HashMap::new(),
+            moderation,
+            parties: Parties::default(),
+            guilds,
+            mailbox,
+            reply_to: HashMap::new(),
+            visible: HashMap::new(),
+            snapshots: HashMap::new(),
+            last_snapshot: Instant::now(),
             rng: rand::thread_rng(),
         })
     }
 
     fn run(mut self) {
-        let (handler, listener) = node::split::<()>();
+        let (handler, listener) = node::split::<Signal>();
+        // frames can't be capped as they're read, packets that are too big are turned away once decoded, see `Decode`
         handler
             .network()
             .listen(Transport::FramedTcp, self.config.listen.clone())
             .unwrap();
 
+        let signals = handler.clone();
+        self.passwords = Some(Worker::spawn(move |client_id, outcome| {
+            signals.signals().send(Signal::Password(client_id, outcome));
+        }));
         self.handler = Some(handler);
 
         log::info!("Listening on {}", self.config.listen);
 
         let (_task, mut receive) = listener.enqueue();
 
-        let mut peer_map: HashMap<Endpoint, ClientId> = HashMap::new();
-        let mut idx = 0u64;
+        let tick_length = Duration::from_secs_f64(1.0 / self.config.tick_rate as f64);
+        let mut accumulator = Duration::ZERO;
 
         loop {
             let now = Instant::now();
-            let dt = now - self.time;
+            accumulator += now - self.time;
             self.time = now;
-            self.dt = dt;
-
-            if let Some(event) = receive.try_receive() {
-                match event.network() {
-                    StoredNetEvent::Connected(_, _) => unreachable!(),
-                    StoredNetEvent::Accepted(endpoint, _listener) => {
-                        let client_id = ClientId(idx);
-                        peer_map.insert(endpoint, client_id);
-                        self.peer_map.insert(client_id, endpoint);
-                        idx += 1;
-
-                        log::info!(
-                            "Client ({}) connected (total clients: {})",
-                            endpoint.addr(),
-                            peer_map.len()
-                        );
-                    }
-                    StoredNetEvent::Message(endpoint, bytes) => {
-                        let message = rmp_serde::from_slice(&bytes).unwrap();
-                        let client_id = peer_map[&endpoint];
-
-                        if let Err(e) = self.handle_message(client_id, message) {
-                            if let Some(client_id) = peer_map.remove(&endpoint) {
-                                self.peer_map.remove(&client_id);
-                                self.handle_disconnect(client_id);
-                            }
-
-                            log::warn!(
-                                "Disconnecting client ({}), message handler returned an error: {e}",
-                                endpoint.addr(),
-                            );
-
-                            log::info!(
-                                "Client ({}) disconnected (total clients: {})",
-                                endpoint.addr(),
-                                peer_map.len()
-                            );
-                        }
-                    }
-                    StoredNetEvent::Disconnected(endpoint) => {
-                        if let Some(client_id) = peer_map.remove(&endpoint) {
-                            self.peer_map.remove(&client_id);
-                            self.handle_disconnect(client_id);
-                        }
 
-                        log::info!(
-                            "Client ({}) disconnected (total clients: {})",
-                            endpoint.addr(),
-                            peer_map.len()
-                        );
-                    }
-                }
+            // handle everything that's waiting, so the queue never backs up
+            while let Some(event) = receive.try_receive() {
+                self.handle_event(event);
             }
 
-            // game loop
-            self.tick();
+            // game loop, always stepped by the same amount no matter how late we are
+            let mut ticks = 0;
+            while accumulator >= tick_length {
+                if ticks == MAX_TICKS_PER_UPDATE {
+                    log::warn!("Server is falling behind, skipping {:?} worth of ticks", accumulator);
+                    accumulator = Duration::ZERO;
+                    break;
+                }
+
+                self.dt = tick_length;
+
+                let start = Instant::now();
+                self.tick();
+                let elapsed = start.elapsed();
+
+                if elapsed > tick_length {
+                    log::warn!(
+                        "Tick overran its budget, took {:?} out of {:?} ({} players, {} maps)",
+                        elapsed,
+                        tick_length,
+                        self.players.len(),
+                        self.maps.len()
+                    );
+                }
+
+                accumulator -= tick_length;
+                ticks += 1;
+            }
 
             // finalizing
             self.maintain();
-            std::thread::sleep(Duration::from_secs_f64(1.0 / 60.0));
+            if self.update_shutdown() {
+                break;
+            }
+
+            // sleep until the next tick, waking up early if something comes in over the network
+            let timeout = tick_length.saturating_sub(accumulator + now.elapsed());
+            if let Some(event) = receive.receive_timeout(timeout) {
+                self.handle_event(event);
+            }
+        }
+
+        log::info!("Server stopped");
+    }
+
+    fn handle_event(&mut self, event: StoredNodeEvent<Signal>) {
+        match event {
+            StoredNodeEvent::Network(event) => self.handle_network_event(event),
+            StoredNodeEvent::Signal(Signal::Password(client_id, outcome)) => {
+                if let Err(e) = self.finish_login(client_id, outcome) {
+                    if let Some(&endpoint) = self.peer_map.get(&client_id) {
+                        self.disconnect(endpoint, e);
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle_network_event(&mut self, event: StoredNetEvent) {
+        match event {
+            StoredNetEvent::Connected(_, _) => unreachable!(),
+            StoredNetEvent::Accepted(endpoint, _listener) => {
+                let client_id = ClientId(self.next_client_id);
+                self.endpoints.insert(endpoint, client_id);
+                self.peer_map.insert(client_id, endpoint);
+                self.rate_limits
+                    .insert(client_id, RateLimiter::new(&self.config.rate_limit, self.time));
+                self.next_client_id += 1;
+
+                log::info!(
+                    "Client ({}) connected (total clients: {})",
+                    endpoint.addr(),
+                    self.endpoints.len()
+                );
+            }
+            StoredNetEvent::Message(endpoint, bytes) => {
+                // messages can still be queued from a client that was just disconnected
+                let Some(&client_id) = self.endpoints.get(&endpoint) else {
+                    return;
+                };
+
+                if let Err(e) = self.receive(client_id, &bytes) {
+                    self.disconnect(endpoint, e);
+                }
+            }
+            StoredNetEvent::Disconnected(endpoint) => {
+                if let Some(client_id) = self.endpoints.remove(&endpoint) {
+                    self.peer_map.remove(&client_id);
+                    self.handle_disconnect(client_id);
+                }
+
+                log::info!(
+                    "Client ({}) disconnected (total clients: {})",
+                    endpoint.addr(),
+                    self.endpoints.len()
+                );
+            }
+        }
+    }
+
+    /// Disconnects a client after something went wrong handling them
+    fn disconnect(&mut self, endpoint: Endpoint, error: anyhow::Error) {
+        self.network().remove(endpoint.resource_id());
+        if let Some(client_id) = self.endpoints.remove(&endpoint) {
+            self.peer_map.remove(&client_id);
+            self.handle_disconnect(client_id);
+        }
+
+        log::warn!(
+            "Disconnecting client ({}), message handler returned an error: {error}",
+            endpoint.addr(),
+        );
+
+        log::info!(
+            "Client ({}) disconnected (total clients: {})",
+            endpoint.addr(),
+            self.endpoints.len()
+        );
+    }
+
+    /// Counts down to a requested shutdown, returning true once the server has stopped
+    fn update_shutdown(&mut self) -> bool {
+        let requests = self.shutdown_requests.load(Ordering::SeqCst);
+        if requests == 0 {
+            return false;
+        }
+
+        if self.shutdown.is_none() {
+            log::info!(
+                "Shutting down in {} seconds, interrupt again to stop immediately",
+                self.config.shutdown_countdown
+            );
+
+            self.shutdown = Some(Shutdown {
+                reason: String::from("The server is shutting down."),
+                at: self.time + Duration::from_secs(self.config.shutdown_countdown),
+                announced: None,
+            });
         }
+
+        let shutdown = self.shutdown.as_mut().unwrap();
+        let remaining = shutdown.at.saturating_duration_since(self.time);
+
+        if requests > 1 || remaining.is_zero() {
+            let packet = Packet::Shutdown {
+                reason: shutdown.reason.clone(),
+            };
+
+            self.send_all(&packet);
+            self.save_all();
+            self.handler.as_ref().unwrap().stop();
+
+            return true;
+        }
+
+        let seconds = remaining.as_secs_f64().ceil() as u64;
+        let announce = shutdown.announced.is_none() || seconds <= 5 || seconds.is_multiple_of(10);
+
+        if announce && shutdown.announced != Some(seconds) {
+            shutdown.announced = Some(seconds);
+
+            let plural = if seconds == 1 { "" } else { "s" };
+            let message = format!("The server will shut down in {seconds} second{plural}.");
+            self.send_all(&Packet::ChatLog(ChatChannel::Server, message));
+        }
+
+        false
     }
 
     fn handle_disconnect(&mut self, client_id: ClientId) {
+        self.greeted.remove(&client_id);
+        self.pending_logins.remove(&client_id);
+        self.rate_limits.remove(&client_id);
+        self.snapshots.remove(&client_id);
+        self.reply_to.remove(&client_id);
+        self.guilds.invites.remove(&client_id);
+        self.leave_party(client_id, "left the game");
+        self.hide_cursor(client_id);
+
         if let Some(player) = self.players.remove(&client_id) {
-            self.send_list(
-                &self
-                    .players
-                    .iter()
-                    .filter(|(_, data)| data.map == player.map)
-                    .map(|(&cid, _)| cid)
-                    .collect::<Vec<_>>(),
-                &Packet::RemoveData(client_id),
-            );
+            if let Some(index) = self.spatial.get_mut(&player.map) {
+                index.players.remove(client_id);
+            }
+
+            self.hide_player(client_id);
+            self.visible.remove(&client_id);
 
             let goodbye = Packet::ChatLog(ChatChannel::Server, format!("{} has left the game.", &player.name));
             self.send_exclude(client_id, &goodbye);
 
-            player.save().unwrap();
+            if let Err(e) = self.storage.save_player(&player) {
+                log::error!("Couldn't save player: {e}");
+            }
         }
     }
 
+    /// Checks and decodes raw bytes from a client, then handles the packet
+    fn receive(&mut self, client_id: ClientId, bytes: &[u8]) -> Result<()> {
+        let limiter = self.rate_limits.get_mut(&client_id).unwrap();
+        match limiter.check(&self.config.rate_limit, self.time) {
+            Limit::Allow => (),
+            Limit::Drop => {
+                log::warn!("{client_id:?}: sending packets too quickly, dropping one");
+                return Ok(());
+            }
+            Limit::Disconnect => bail!("Client kept sending packets too quickly"),
+        }
+
+        let message = network::decode(bytes)?;
+        self.handle_message(client_id, message)
+    }
+
     fn handle_message(&mut self, client_id: ClientId, message: ClientPacket) -> Result<()> {
         use common::network::client::Packet::*;
 
         match &message {
-            SaveMap(_) => log::debug!("SaveMap(..)"),
+            EditMap { batch, revision, edits } => {
+                log::debug!(
+                    "{client_id:?}: EditMap {{ batch: {batch}, revision: {revision}, {} edits }}",
+                    edits.len()
+                )
+            }
             message => {
                 log::debug!("{client_id:?}: {message:?}");
             }
         }
 
-        if self.players.contains_key(&client_id) {
+        if !self.greeted.contains(&client_id) {
+            self.handle_hello(client_id, message)
+        } else if self.players.contains_key(&client_id) {
             self.handle_game_message(client_id, message)
         } else {
             self.handle_login_message(client_id, message)
         }
     }
 
+    fn handle_hello(&mut self, client_id: ClientId, message: ClientPacket) -> Result<()> {
+        let (protocol_version, client_version) = match message {
+            ClientPacket::Hello {
+                protocol_version,
+                client_version,
+            } => (protocol_version, client_version),
+            _ => bail!("Client attempted to send a packet before saying hello"),
+        };
+
+        self.send(
+            client_id,
+            &Packet::Hello {
+                protocol_version: PROTOCOL_VERSION,
+                server_version: env!("CARGO_PKG_VERSION").to_owned(),
+            },
+        );
+
+        if protocol_version != PROTOCOL_VERSION {
+            let reason = FailJoinReason::VersionMismatch {
+                server: PROTOCOL_VERSION,
+                client: protocol_version,
+            };
+            self.send(client_id, &Packet::FailedJoin(reason));
+
+            bail!("Client {client_version} uses protocol version {protocol_version}, expected {PROTOCOL_VERSION}");
+        }
+
+        log::info!("{client_id:?}: client {client_version} said hello");
+        self.greeted.insert(client_id);
+
+        Ok(())
+    }
+
     fn handle_login_message(&mut self, client_id: ClientId, message: ClientPacket) -> Result<()> {
+        // hashing is slow, so there's only ever one attempt per client on the way
+        if self.pending_logins.contains_key(&client_id)
+            && matches!(message, ClientPacket::CreateAccount { .. } | ClientPacket::Login { .. })
+        {
+            log::warn!("{client_id:?}: tried to log in again before the last attempt finished");
+            return Ok(());
+        }
+
         match message {
             ClientPacket::CreateAccount {
                 username,
                 password,
-                character_name: name,
+                character_name,
             } => {
-                let mut name_cache = NameCache::load().unwrap();
-
-                if Player::path(&username).exists() {
-                    self.send(client_id, &Packet::FailedJoin(FailJoinReason::UsernameTaken));
+                if let Some(reason) = self.ban_reason(client_id, None) {
+                    self.send(client_id, &Packet::FailedJoin(reason));
+                    return Ok(());
                 }
 
-                if name_cache.contains(&name) {
-                    self.send(client_id, &Packet::FailedJoin(FailJoinReason::CharacterNameTaken));
+                if let Some(reason) = registration::check(&*self.storage, &username, &character_name)? {
+                    self.send(client_id, &Packet::FailedJoin(reason));
+                    return Ok(());
                 }
 
-                name_cache.insert(name.clone());
-                name_cache.save().unwrap();
-
-                let hash = Sha256::digest(password);
-                let password = Base64::encode_string(&hash);
-
-                let player = Player {
-                    username,
-                    password,
-                    name,
-                    position: Point2D::new(self.config.start.x, self.config.start.y),
-                    map: MapHash::start(),
-                    ..Default::default()
-                };
-
-                player.save().unwrap();
+                let start = Point2D::new(self.config.start.x, self.config.start.y);
+                let player = Player::new(&username, "", &character_name, MapHash::start(), start);
 
-                self.join_game(client_id, player);
+                self.pending_logins.insert(client_id, PendingLogin::Register(player));
+                self.passwords().queue(client_id, Job::Hash(password));
             }
-            ClientPacket::Login { username, password } => {
-                let incorrect = Packet::FailedJoin(FailJoinReason::LoginIncorrect);
+            ClientPacket::Login { username, password } => match self.storage.load_player(&username) {
+                Ok(Some(player)) => {
+                    let job = Job::Verify {
+                        password,
+                        stored: player.password.clone(),
+                    };
 
-                if let Some(player) = Self::check_password(&username, &password) {
-                    self.join_game(client_id, player);
-                } else {
-                    self.send(client_id, &incorrect);
+                    self.pending_logins.insert(client_id, PendingLogin::Login(player));
+                    self.passwords().queue(client_id, job);
                 }
-            }
+                Ok(None) => {
+                    log::warn!("Failed to log in, account doesn't exist");
+                    self.send(client_id, &Packet::FailedJoin(FailJoinReason::LoginIncorrect));
+                }
+                Err(e) => {
+                    log::warn!("Failed to log in, loading player errored: {e}");
+                    self.send(client_id, &Packet::FailedJoin(FailJoinReason::LoginIncorrect));
+                }
+            },
             _ => {
                 bail!("Client attempted to send a packet that is invalid while logged in");
             }
@@ -253,27 +561,67 @@ impl GameServer {
         Ok(())
     }
 
-    fn check_password(username: &str, password: &str) -> Option<Player> {
-        match Player::load(username) {
-            Ok(player) => {
-                let hash = Sha256::digest(password);
-                let password = Base64::encode_string(&hash);
-                if player.password == password {
-                    return Some(player);
-                } else {
-                    log::warn!("Failed to log in, passwords do not match");
+    /// Picks up a login or registration once the password thread is done with it
+    fn finish_login(&mut self, client_id: ClientId, outcome: Outcome) -> Result<()> {
+        // they could have disconnected in the meantime
+        let Some(pending) = self.pending_logins.remove(&client_id) else {
+            return Ok(());
+        };
+
+        match (pending, outcome) {
+            (PendingLogin::Register(mut player), Outcome::Hashed(hash)) => {
+                player.password = hash?;
+
+                match registration::register(&*self.storage, player)? {
+                    Registration::Created(player) => self.join_game(client_id, player),
+                    Registration::Rejected(reason) => self.send(client_id, &Packet::FailedJoin(reason)),
                 }
             }
-            Err(e) => {
-                log::warn!("Failed to log in, loading player errored: {e}");
+            (PendingLogin::Login(mut player), Outcome::Verified { verified, rehashed }) => {
+                if verified == Verified::Invalid {
+                    log::warn!("Failed to log in, passwords do not match");
+                    self.send(client_id, &Packet::FailedJoin(FailJoinReason::LoginIncorrect));
+                    return Ok(());
+                }
+
+                if let Some(hash) = rehashed {
+                    player.password = hash;
+                    if let Err(e) = self.storage.save_player(&player) {
+                        log::error!("Couldn't save rehashed password: {e}");
+                    }
+                }
+
+                match self.ban_reason(client_id, Some(&player.username)) {
+                    Some(reason) => self.send(client_id, &Packet::FailedJoin(reason)),
+                    None => self.join_game(client_id, player),
+                }
             }
+            _ => bail!("Password job finished with the wrong kind of outcome"),
         }
-        None
+
+        Ok(())
+    }
+
+    fn passwords(&self) -> &Worker {
+        self.passwords.as_ref().unwrap()
+    }
+
+    /// Why a client isn't allowed in, if their account or address is banned
+    fn ban_reason(&self, client_id: ClientId, username: Option<&str>) -> Option<FailJoinReason> {
+        // account bans still apply if we somehow can't tell where they're connecting from
+        let ban = self.moderation.ban(username, self.address(client_id))?;
+
+        Some(FailJoinReason::Banned {
+            reason: ban.reason.clone(),
+            until: ban.until,
+        })
     }
 
     fn handle_game_message(&mut self, client_id: ClientId, message: ClientPacket) -> Result<()> {
         match message {
-            ClientPacket::CreateAccount { .. } | ClientPacket::Login { .. } => unreachable!(),
+            ClientPacket::Hello { .. } | ClientPacket::CreateAccount { .. } | ClientPacket::Login { .. } => {
+                bail!("Client attempted to send a packet that is invalid while in game");
+            }
 
             ClientPacket::ChatMessage(channel, text) => {
                 self.process_chat_message(client_id, channel, &text);
@@ -282,59 +630,69 @@ impl GameServer {
                 let map_id = self.players[&client_id].map;
                 let map = &self.maps[&map_id];
 
-                let packet = Packet::MapData(Box::new(map.clone().into()));
+                let packet = Packet::MapData(Box::new(map.clone().into()), self.map_revision(map_id));
                 self.send(client_id, &packet);
             }
-            ClientPacket::SaveMap(map) => {
-                let map_id = self.players[&client_id].map;
-                let mut map = Map::from(*map);
-
-                map.settings.cache_key = Utc::now().timestamp_millis();
-
-                if let Err(e) = map.save() {
-                    log::error!("Couldn't save map {e}");
+            ClientPacket::EditMap { batch, revision, edits } => {
+                if self.require(client_id, AccessLevel::Mapper) {
+                    self.edit_map(client_id, batch, revision, edits);
+                } else {
+                    self.reject_edits(client_id, batch);
+                }
+            }
+            ClientPacket::EditorCursor(cursor) => {
+                let player = &self.players[&client_id];
+                if player.flags.in_map_editor {
+                    self.send_to_editors(player.map, client_id, &Packet::EditorCursor(client_id, cursor));
                 }
-
-                self.maps.insert(map_id, map.clone());
-
-                self.send_to_map(map_id, &Packet::MapData(Box::new(map.into())));
             }
             ClientPacket::Move {
+                sequence,
                 position,
                 direction,
                 velocity,
             } => {
                 let map_hash = self.players[&client_id].map;
-                let map = &self.maps[&map_hash];
+                // acknowledged even if it's rejected, the warp below tells them where they really are
+                self.players.get_mut(&client_id).unwrap().last_sequence = sequence;
 
-                let valid = check_collision_with(
-                    position.into(),
-                    map.zones.iter().filter(|zone| zone.data == ZoneData::Blocked),
-                    |zone| Box2D::from_origin_and_size(zone.position.into(), zone.size.into()),
-                )
-                .is_none();
+                let result = self.validate_move(client_id, position.into(), velocity.map(Into::into));
 
-                if valid {
+                if let Err(reason) = &result {
+                    log::warn!("{client_id:?}: rejected movement, {reason}");
+                }
+
+                if let Ok(budget) = result {
                     let player = self.players.get_mut(&client_id).unwrap();
 
+                    player.move_budget = budget;
+                    player.last_move = Some((self.time, position.into()));
                     player.position = position.into();
                     player.velocity = velocity.map(Into::into);
                     player.direction = direction;
+                    player.dirty = true;
+                    self.index_player(client_id);
 
                     let packet = Packet::PlayerMove {
                         client_id,
                         position,
                         direction,
                         velocity,
+                        sequence,
                     };
 
-                    self.send_map_except(map_hash, client_id, &packet);
+                    // the sender needs this too, it's how they know which moves we've seen
+                    self.send_movement(client_id, &packet);
                 } else {
                     // warping them to the default will just update them with the server truth
                     self.warp_player(client_id, map_hash, WarpParams::default());
                 }
             }
             ClientPacket::Warp(map_id, position) => {
+                if !self.require(client_id, AccessLevel::Mapper) {
+                    return Ok(());
+                }
+
                 // note: the requested map possibly doesn't exist
                 let map_hash = self.validate_map(&map_id);
 
@@ -352,109 +710,479 @@ impl GameServer {
                 self.send_map_editor(client_id, map_hash)?;
             }
             ClientPacket::MapEditor(open) => {
+                if open && !self.require(client_id, AccessLevel::Mapper) {
+                    return Ok(());
+                }
+
+                if !open {
+                    self.hide_cursor(client_id);
+                }
+
                 let player = self.players.get_mut(&client_id).unwrap();
                 player.flags.in_map_editor = open;
 
                 let map_id = player.map;
                 let flags = player.flags;
 
-                self.send_to_map(map_id, &Packet::Flags(client_id, flags));
+                self.send_to_observers(client_id, &Packet::Flags(client_id, flags));
 
                 if open {
                     self.send_map_editor(client_id, map_id)?;
                 }
             }
+            ClientPacket::AckSnapshot(sequence) => {
+                if let Some(history) = self.snapshots.get_mut(&client_id) {
+                    history.acknowledge(sequence);
+                }
+            }
         }
 
         Ok(())
     }
 
-    fn process_chat_message(&mut self, client_id: ClientId, channel: ChatChannel, message: &str) {
-        if let Some(command) = self.process_chat_command(client_id, message) {
-            log::info!("{client_id:?}: used the {command} command");
+    /// Applies edits from someone in the map editor and streams them to everyone else on the map
+    fn edit_map(&mut self, client_id: ClientId, batch: u32, revision: u32, edits: Vec<MapEdit>) {
+        let player = &self.players[&client_id];
+        if !player.flags.in_map_editor {
+            log::warn!("{client_id:?}: tried to edit a map without the map editor open");
+            self.reject_edits(client_id, batch);
+            return;
+        }
+
+        let map_id = player.map;
+        let log = self.edit_logs.entry(map_id).or_insert_with(EditLog::new);
+        let map = self.maps.get_mut(&map_id).unwrap();
+
+        if !log.covers(revision) {
+            // too far behind to tell what they meant, start them over with what we have
+            log::warn!(
+                "{client_id:?}: edited revision {revision} of a map that's now at {}",
+                log.revision()
+            );
+
+            let packet = Packet::MapData(Box::new(map.clone().into()), log.revision());
+            self.send(client_id, &packet);
+            return;
+        }
+
+        // edits that clash with someone else's are dropped, the echo tells the editor which ones made it
+        let mut applied = Vec::with_capacity(edits.len());
+        for edit in &edits {
+            let result = log
+                .rebase(revision, client_id, edit)
+                .map(|edit| map.apply(&edit).map(|()| edit));
+
+            match result {
+                Some(Ok(edit)) => applied.push(edit),
+                Some(Err(e)) => log::warn!("{client_id:?}: rejected map edit {edit:?}, {e}"),
+                None => log::debug!("{client_id:?}: dropped map edit {edit:?}, it conflicts with another"),
+            }
+        }
+
+        if applied.is_empty() {
+            self.reject_edits(client_id, batch);
             return;
         }
 
+        map.settings.cache_key = Utc::now().timestamp_millis();
+        map.dirty = true;
+
+        if applied.iter().any(MapEdit::changes_zones) {
+            self.spatial
+                .entry(map_id)
+                .or_insert_with(|| MapIndex::new(map))
+                .rebuild_zones(map);
+        }
+
+        let packet = Packet::MapEdits {
+            revision: log.push(client_id, applied.clone()),
+            author: client_id,
+            batch,
+            edits: applied,
+        };
+        self.send_to_map(map_id, &packet);
+    }
+
+    /// Echoes a batch of edits back empty, so the editor knows none of it was applied
+    fn reject_edits(&self, client_id: ClientId, batch: u32) {
+        let packet = Packet::MapEdits {
+            revision: self.map_revision(self.players[&client_id].map),
+            author: client_id,
+            batch,
+            edits: Vec::new(),
+        };
+        self.send(client_id, &packet);
+    }
+
+    /// Checks if a client's access level is at least `level`, telling them they aren't allowed if it isn't
+    fn require(&self, client_id: ClientId, level: AccessLevel) -> bool {
+        let access = self.players[&client_id].access;
+        if access >= level {
+            return true;
+        }
+
+        log::warn!("{client_id:?}: tried to do something that needs {level} access as a {access}");
+        let message = format!("You don't have permission to do that, it needs {level} access.");
+        self.send(client_id, &Packet::ChatLog(ChatChannel::Error, message));
+
+        false
+    }
+
+    /// Lets the other editors on a player's map know they've stopped editing it
+    fn hide_cursor(&self, client_id: ClientId) {
+        if let Some(player) = self.players.get(&client_id) {
+            if player.flags.in_map_editor {
+                self.send_to_editors(player.map, client_id, &Packet::EditorCursor(client_id, None));
+            }
+        }
+    }
+
+    fn map_revision(&self, map_hash: MapHash) -> u32 {
+        self.edit_logs.get(&map_hash).map(EditLog::revision).unwrap_or(0)
+    }
+
+    /// Checks a client's claimed movement against what the server thinks is possible.
+    ///
+    /// Moves are measured from the last one the client sent, and have to fit in the player's movement budget
+    /// and follow a path that nothing blocks. Returns what's left of the budget if it's allowed.
+    fn validate_move(
+        &self,
+        client_id: ClientId,
+        position: Point2D<f32>,
+        velocity: Option<Vector2D<f32>>,
+    ) -> Result<f32, String> {
         let player = &self.players[&client_id];
+        let map = &self.maps[&player.map];
+        let config = &self.config.movement;
+        let max_speed = player.max_speed();
+
+        if !position.x.is_finite() || !position.y.is_finite() {
+            return Err(String::from("position isn't a number"));
+        }
+
+        if let Some(velocity) = velocity {
+            let speed = velocity.length();
+            if !speed.is_finite() || speed > max_speed * (1.0 + config.speed_tolerance) {
+                return Err(format!("moving too fast ({speed:.1} > {max_speed:.1})"));
+            }
+        }
+
+        // the client is ahead of or behind us by however long the packet took to get here
+        let drift = (position - player.position).length();
+        let max_drift = max_speed * config.max_latency;
+        if drift > max_drift {
+            return Err(format!(
+                "position is too far from the server's ({drift:.1} > {max_drift:.1})"
+            ));
+        }
+
+        let (from, budget) = match player.last_move {
+            Some((time, from)) => {
+                let elapsed = self.time.saturating_duration_since(time).as_secs_f32();
+                let refill = max_speed * (1.0 + config.speed_tolerance) * elapsed;
+                (from, (player.move_budget + refill).min(max_drift))
+            }
+            None => (player.position, player.move_budget),
+        };
+
+        let distance = (position - from).length();
+        if distance > budget {
+            return Err(format!("moved too far too quickly ({distance:.1} > {budget:.1})"));
+        }
+
+        // the client moves in a straight line between moves, so anything in the way of that line was skipped over
+        if !player.flags.in_map_editor {
+            let start = physics::sprite_hitbox(from.into());
+            let end = physics::sprite_hitbox(position.into());
+            let blockers = self.spatial[&player.map]
+                .zones
+                .query(start.union(&end))
+                .into_iter()
+                .filter(|(i, _rect)| map.zones[*i].data == ZoneData::Blocked)
+                .map(|(_i, rect)| rect)
+                .collect::<Vec<_>>();
+
+            let obstacles = physics::Obstacles {
+                bounds: map.bounds(),
+                tiles: Some(&map.attributes),
+                blockers: &blockers,
+            };
+
+            let delta = position - from;
+            let reached = Point2D::from(physics::step(from.into(), delta.into(), 1.0, &obstacles));
+            if (reached - position).length() > PATH_TOLERANCE {
+                return Err(String::from("something is in the way"));
+            }
+        }
+
+        let hitbox = physics::sprite_hitbox(position.into());
+        let blocked = self.spatial[&player.map]
+            .zones
+            .query(hitbox)
+            .into_iter()
+            .any(|(i, _rect)| map.zones[i].data == ZoneData::Blocked);
+
+        if blocked && !player.flags.in_map_editor {
+            return Err(String::from("position is inside a blocked zone"));
+        }
+
+        // directional tiles can be stood in, only ones that block every way can't
+        let solid = physics::tiles_in(&map.attributes, hitbox, TileAttribute::is_solid).next();
+
+        if solid.is_some() && !player.flags.in_map_editor {
+            return Err(String::from("position is inside a blocked tile"));
+        }
+
+        Ok(budget - distance)
+    }
+
+    fn process_chat_message(&mut self, client_id: ClientId, channel: ChatChannel, message: &str) {
+        if let Some(line) = message.strip_prefix('/') {
+            self.process_chat_command(client_id, line);
+            return;
+        }
+
+        if self.muted(client_id) {
+            return;
+        }
+
         match channel {
             ChatChannel::Echo | ChatChannel::Error => {
                 log::warn!("Client tried to talk in an invalid channel");
             }
             ChatChannel::Server => {
+                if !self.require(client_id, AccessLevel::Moderator) {
+                    return;
+                }
+
                 let packet = Packet::ChatLog(ChatChannel::Server, message.to_string());
                 self.send_all(&packet);
             }
             ChatChannel::Say => {
+                let listeners = self.players_near(client_id, self.config.say_radius * TILE_SIZE as f32);
+                let player = &self.players[&client_id];
                 let full_text = format!("{}: {}", player.name, message);
 
-                let packet = Packet::ChatLog(ChatChannel::Say, full_text);
-                self.send_to_map(player.map, &packet);
+                self.send_list(&listeners, &Packet::ChatLog(ChatChannel::Say, full_text));
+                self.send_list(&listeners, &Packet::SpeechBubble(client_id, message.to_string()));
             }
             ChatChannel::Global => {
+                let player = &self.players[&client_id];
                 let full_text = format!("{}: {}", player.name, message);
                 let packet = Packet::ChatLog(ChatChannel::Global, full_text);
                 self.send_all(&packet);
             }
-        }
-    }
+            ChatChannel::Whisper => {
+                let error = String::from("Use /whisper <player name> <message> to whisper to someone.");
+                self.send(client_id, &Packet::ChatLog(ChatChannel::Error, error));
+            }
+            ChatChannel::Party => {
+                let members = match self.parties.get(client_id) {
+                    Some(party) => party.members.clone(),
+                    None => {
+                        let error =
+                            String::from("You aren't in a party, invite someone with /party invite <player name>.");
+                        self.send(client_id, &Packet::ChatLog(ChatChannel::Error, error));
+                        return;
+                    }
+                };
 
-    fn process_chat_command(&mut self, client_id: ClientId, message: &str) -> Option<&str> {
-        if let Some(args) = message.strip_prefix("/warp") {
-            let map_id = args.trim();
-            if !map_id.is_empty() {
-                let map_hash = self.validate_map(map_id);
-                self.warp_player(
-                    client_id,
-                    map_hash,
-                    WarpParams {
-                        direction: Some(Direction::South),
-                        ..Default::default()
-                    },
-                );
-            } else {
-                self.send(
-                    client_id,
-                    &Packet::ChatLog(ChatChannel::Error, String::from("Usage: /warp <map id>")),
-                );
+                let player = &self.players[&client_id];
+                let full_text = format!("{}: {}", player.name, message);
+                self.send_list(&members, &Packet::ChatLog(ChatChannel::Party, full_text));
             }
+            ChatChannel::Guild => {
+                let player = &self.players[&client_id];
+                let members = match self.guilds.of(&player.username) {
+                    Some(guild) => self.guild_members(guild),
+                    None => {
+                        let error = String::from("You aren't in a guild.");
+                        self.send(client_id, &Packet::ChatLog(ChatChannel::Error, error));
+                        return;
+                    }
+                };
 
-            return Some("/warp");
+                let full_text = format!("{}: {}", player.name, message);
+                self.send_list(&members, &Packet::ChatLog(ChatChannel::Guild, full_text));
+            }
         }
+    }
 
-        if let Some(args) = message.strip_prefix("/sprite") {
-            // wtb try blocks
-            let result: Result<(), &str> = (|| {
-                let (who, sprite) = args.trim().rsplit_once(' ').ok_or("Wrong number of arguments")?;
-                let sprite = sprite.parse().map_err(|_| "Invalid sprite, it must be a number")?;
+    /// Everyone on the same map within `radius` pixels of a player, including the player
+    fn players_near(&self, client_id: ClientId, radius: f32) -> Vec<ClientId> {
+        let player = &self.players[&client_id];
+        let index = match self.spatial.get(&player.map) {
+            Some(index) => index,
+            None => return vec![client_id],
+        };
 
-                let other_id = *self
-                    .players
-                    .iter()
-                    .find_map(|(cid, player)| (player.name == who).then_some(cid))
-                    .ok_or("Could not find the player, are they online?")?;
+        let center = player_center(player.position);
+        let area = physics::Rect::new(center.x - radius, center.y - radius, radius * 2.0, radius * 2.0);
 
-                let player = self.players.get_mut(&other_id).unwrap();
-                player.sprite = sprite;
-                if let Err(e) = player.save() {
-                    log::error!("Couldn't save player: {e}");
-                };
+        index
+            .players
+            .query(area)
+            .into_iter()
+            .map(|(other, _rect)| other)
+            .filter(|other| (player_center(self.players[other].position) - center).length() <= radius)
+            .collect()
+    }
 
-                let map_id = player.map;
-                let data = player.clone();
+    /// Checks if a player is muted, telling them for how long if they are
+    fn muted(&self, client_id: ClientId) -> bool {
+        let mute = match self.moderation.mute(&self.players[&client_id].username) {
+            Some(mute) => mute,
+            None => return false,
+        };
+
+        let remaining = format_duration(mute.until - Utc::now().timestamp());
+        let error = match &mute.reason {
+            Some(reason) => format!("You are muted for {remaining}: {reason}"),
+            None => format!("You are muted for {remaining}."),
+        };
+
+        self.send(client_id, &Packet::ChatLog(ChatChannel::Error, error));
+        true
+    }
 
-                self.send_to_map(map_id, &Packet::PlayerData(other_id, data.into()));
-                Ok(())
-            })();
+    /// Runs a chat command, `line` is the message without the leading slash
+    fn process_chat_command(&mut self, client_id: ClientId, line: &str) {
+        let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
 
-            if let Err(e) = result {
-                let error = format!("Error: {}\nUsage: /sprite <player name> <sprite #>", e);
+        let command = match Command::find(name) {
+            Some(command) => command,
+            None => {
+                let error = format!("There is no /{name} command, try /help to see what you can use");
                 self.send(client_id, &Packet::ChatLog(ChatChannel::Error, error));
-            } else {
-                return Some("/sprite");
+                return;
             }
+        };
+
+        if !self.require(client_id, command.access) {
+            return;
         }
 
-        None
+        let result = command
+            .parse(self, rest)
+            .and_then(|args| command.run(self, client_id, &args));
+
+        match result {
+            Ok(()) => log::info!("{client_id:?}: used the /{} command", command.name),
+            Err(e) => {
+                let error = format!("Error: {}\nUsage: {}", e, command.usage());
+                self.send(client_id, &Packet::ChatLog(ChatChannel::Error, error));
+            }
+        }
+    }
+
+    /// Looks up an online player by their character name
+    fn find_player(&self, name: &str) -> Option<ClientId> {
+        self.players
+            .iter()
+            .find_map(|(client_id, player)| player.name.eq_ignore_ascii_case(name).then_some(*client_id))
+    }
+
+    /// Looks up an online player by their username
+    fn find_account(&self, username: &str) -> Option<ClientId> {
+        self.players
+            .iter()
+            .find_map(|(client_id, player)| player.username.eq_ignore_ascii_case(username).then_some(*client_id))
+    }
+
+    fn address(&self, client_id: ClientId) -> Option<IpAddr> {
+        self.peer_map.get(&client_id).map(|endpoint| endpoint.addr().ip())
+    }
+
+    /// Removes a player from the game, telling them why first
+    fn kick(&mut self, client_id: ClientId, reason: String) {
+        self.send(client_id, &Packet::Kicked { reason });
+
+        if let Some(endpoint) = self.peer_map.remove(&client_id) {
+            self.network().remove(endpoint.resource_id());
+            self.endpoints.remove(&endpoint);
+            self.handle_disconnect(client_id);
+
+            log::info!(
+                "Client ({}) was kicked (total clients: {})",
+                endpoint.addr(),
+                self.endpoints.len()
+            );
+        }
+    }
+
+    fn save_moderation(&mut self) {
+        if let Err(e) = self.moderation.save() {
+            log::error!("Couldn't save bans, mutes and jails: {e}");
+        }
+    }
+
+    fn save_guilds(&self) {
+        if let Err(e) = self.guilds.save() {
+            log::error!("Couldn't save guilds: {e}");
+        }
+    }
+
+    fn save_mail(&self) {
+        if let Err(e) = self.mailbox.save() {
+            log::error!("Couldn't save mail: {e}");
+        }
+    }
+
+    /// Every member of a guild that's online
+    fn guild_members(&self, guild: &Guild) -> Vec<ClientId> {
+        self.players
+            .iter()
+            .filter(|(_, player)| guild.members.contains_key(&player.username.to_lowercase()))
+            .map(|(client_id, _)| *client_id)
+            .collect()
+    }
+
+    /// Takes a player out of their party, letting everyone left in it know `why`, like "left the party".
+    ///
+    /// Returns false if they weren't in one.
+    fn leave_party(&mut self, client_id: ClientId, why: &str) -> bool {
+        let party = match self.parties.leave(client_id) {
+            Some(party) => party,
+            None => return false,
+        };
+
+        let name = &self.players[&client_id].name;
+        let message = if party.members.len() < 2 {
+            format!("{name} {why}, so the party has been disbanded.")
+        } else {
+            let leader = &self.players[&party.leader].name;
+            format!("{name} {why}, {leader} is leading the party.")
+        };
+
+        self.send_list(&party.members, &Packet::ChatLog(ChatChannel::Party, message));
+        true
+    }
+
+    /// Hands over any whispers that were sent while a player was offline
+    fn deliver_mail(&mut self, client_id: ClientId) {
+        let letters = self.mailbox.take(&self.players[&client_id].username);
+        if letters.is_empty() {
+            return;
+        }
+
+        self.save_mail();
+
+        let message = match letters.len() {
+            1 => String::from("You got a whisper while you were away:"),
+            count => format!("You got {count} whispers while you were away:"),
+        };
+        self.send(client_id, &Packet::ChatLog(ChatChannel::Server, message));
+
+        let now = Utc::now().timestamp();
+        for letter in &letters {
+            let ago = format_duration(now - letter.sent);
+            let message = format!("From {} ({ago} ago): {}", letter.from, letter.message);
+            self.send(client_id, &Packet::ChatLog(ChatChannel::Whisper, message));
+        }
+
+        if let Some(letter) = letters.last() {
+            self.reply_to.insert(client_id, letter.from.clone());
+        }
     }
 
     fn send_map_editor(&self, client_id: ClientId, map_hash: MapHash) -> Result<()> {
@@ -492,6 +1220,18 @@ impl GameServer {
             player.position = self.config.start.position();
         }
 
+        if self
+            .config
+            .owners
+            .iter()
+            .any(|owner| owner.eq_ignore_ascii_case(&player.username))
+        {
+            player.access = AccessLevel::Owner;
+        }
+
+        // enough to cover their first moves arriving bunched together
+        player.move_budget = player.max_speed() * self.config.movement.max_latency;
+
         // Save their data
         self.players.insert(client_id, player.clone());
 
@@ -513,6 +1253,8 @@ impl GameServer {
             &Packet::ChatLog(ChatChannel::Server, "Welcome to Game™!".to_owned()),
         );
 
+        self.deliver_mail(client_id);
+
         // Send join message
         self.send_exclude(
             client_id,
@@ -522,35 +1264,141 @@ impl GameServer {
 
     fn tick(&mut self) {
         self.update_players();
+        self.update_interest();
+
+        if self.config.snapshots.enabled {
+            self.send_snapshots();
+        }
     }
 
-    fn update_players(&mut self) {
-        let dt = self.dt;
+    /// Sends every client a snapshot of the players it can see, if it's time for one
+    fn send_snapshots(&mut self) {
+        let interval = Duration::from_secs_f64(1.0 / self.config.snapshots.rate as f64);
+        if self.time - self.last_snapshot < interval {
+            return;
+        }
+        self.last_snapshot = self.time;
 
-        let mut to_warp = Vec::new();
+        let mut packets = Vec::new();
+        for (client_id, visible) in &self.visible {
+            let state = visible
+                .iter()
+                .map(|other| {
+                    let player = &self.players[other];
+                    let state = PlayerState::new(
+                        player.position.into(),
+                        player.direction,
+                        player.velocity.map(Into::into),
+                    );
+
+                    (*other, state)
+                })
+                .collect();
+
+            let history = self.snapshots.entry(*client_id).or_insert_with(SnapshotHistory::new);
+            if let Some(snapshot) = history.next(state) {
+                packets.push((*client_id, Packet::Snapshot(snapshot)));
+            }
+        }
+
+        for (client_id, packet) in packets {
+            self.send(client_id, &packet);
+        }
+    }
 
-        let sprite_size = Size2D::new(SPRITE_SIZE as f32, SPRITE_SIZE as f32 / 2.0);
-        let sprite_offset = Vector2D::new(0.0, SPRITE_SIZE as f32 / 2.0);
+    /// Sends players that have come into or gone out of view of each other since the last tick
+    fn update_interest(&mut self) {
+        let enter_radius = self.config.view_radius * TILE_SIZE as f32;
+        let leave_radius = enter_radius * VIEW_LEAVE_MARGIN;
 
-        let player_boxes = self
-            .players
-            .iter()
-            .map(|(client_id, player)| {
-                (
-                    *client_id,
-                    Box2D::from_origin_and_size(player.position + sprite_offset, sprite_size),
-                )
-            })
-            .collect::<Vec<_>>();
+        let mut entered = Vec::new();
+        let mut left = Vec::new();
+
+        for (&client_id, player) in &self.players {
+            let index = match self.spatial.get(&player.map) {
+                Some(index) => index,
+                None => continue,
+            };
+
+            let center = player_center(player.position);
+            let area = physics::Rect::new(
+                center.x - leave_radius,
+                center.y - leave_radius,
+                leave_radius * 2.0,
+                leave_radius * 2.0,
+            );
+
+            let visible = self.visible.get(&client_id);
+            let is_visible = |other: &ClientId| visible.map(|v| v.contains(other)).unwrap_or(false);
+
+            let nearby = index
+                .players
+                .query(area)
+                .into_iter()
+                .map(|(other, _rect)| other)
+                .filter(|other| *other != client_id)
+                .filter(|other| {
+                    let distance = (player_center(self.players[other].position) - center).length();
+                    if is_visible(other) {
+                        distance <= leave_radius
+                    } else {
+                        distance <= enter_radius
+                    }
+                })
+                .collect::<HashSet<_>>();
+
+            entered.extend(
+                nearby
+                    .iter()
+                    .filter(|other| !is_visible(other))
+                    .map(|&other| (client_id, other)),
+            );
+            if let Some(visible) = visible {
+                left.extend(visible.difference(&nearby).map(|&other| (client_id, other)));
+            }
+        }
+
+        for (client_id, other) in entered {
+            self.visible.entry(client_id).or_default().insert(other);
+
+            let data = self.players[&other].clone();
+            self.send(client_id, &Packet::PlayerData(other, data.into()));
+        }
+
+        for (client_id, other) in left {
+            if let Some(visible) = self.visible.get_mut(&client_id) {
+                visible.remove(&other);
+            }
+
+            self.send(client_id, &Packet::RemoveData(other));
+        }
+    }
+
+    /// Forgets everyone a player could see, and tells everyone that could see them that they're gone
+    fn hide_player(&mut self, client_id: ClientId) {
+        let observers = self.visible.insert(client_id, HashSet::new()).unwrap_or_default();
+
+        for observer in observers {
+            if let Some(visible) = self.visible.get_mut(&observer) {
+                visible.remove(&client_id);
+            }
+
+            self.send(observer, &Packet::RemoveData(client_id));
+        }
+    }
+
+    fn update_players(&mut self) {
+        let dt = self.dt.as_secs_f32();
+
+        let mut to_warp = Vec::new();
 
         for (client_id, player) in &mut self.players {
             let map = &self.maps[&player.map];
+            let index = self.spatial.get_mut(&player.map).unwrap();
             if let Some(velocity) = player.velocity {
-                let offset = velocity * dt.as_secs_f32();
-                let new_position = player.position + offset;
-                let mut valid = true;
+                let new_position = player.position + velocity * dt;
+                let mut warping = false;
 
-                // map bounds
                 if !player.flags.in_map_editor {
                     // map warps, lots of copy paste code lol
                     if let Some((direction, new_position)) = check_edge_warp(map, new_position) {
@@ -570,42 +1418,64 @@ impl GameServer {
                                     ..Default::default()
                                 },
                             ));
+                            warping = true;
                         }
-
-                        valid = false;
                     }
                 }
 
-                if !player.flags.in_map_editor {
-                    // block zones
-                    valid &= check_collision_with(
-                        player.position,
-                        map.zones.iter().filter(|zone| zone.data == ZoneData::Blocked),
-                        |zone| Box2D::from_origin_and_size(zone.position.into(), zone.size.into()),
-                    )
-                    .is_none();
-
-                    valid &= check_collision_with(
-                        player.position,
-                        player_boxes.iter().filter(|(cid, _box2d)| cid != client_id),
-                        |(_cid, box2d)| *box2d,
-                    )
-                    .is_none();
-                }
+                if !warping {
+                    let blockers = if player.flags.in_map_editor {
+                        Vec::new()
+                    } else {
+                        // only what's between here and where we're going can get in the way
+                        let start = physics::sprite_hitbox(player.position.into());
+                        let end = physics::sprite_hitbox(new_position.into());
+                        let area = start.union(&end);
+
+                        let zones = index
+                            .zones
+                            .query(area)
+                            .into_iter()
+                            .filter(|(i, _rect)| map.zones[*i].data == ZoneData::Blocked);
+                        let players = index
+                            .players
+                            .query(area)
+                            .into_iter()
+                            .filter(|(cid, _rect)| cid != client_id);
+
+                        zones
+                            .map(|(_i, rect)| rect)
+                            .chain(players.map(|(_cid, rect)| rect))
+                            .collect()
+                    };
+
+                    let obstacles = physics::Obstacles {
+                        bounds: map.bounds(),
+                        tiles: (!player.flags.in_map_editor).then_some(&map.attributes),
+                        blockers: &blockers,
+                    };
+
+                    let position = physics::step(player.position.into(), velocity.into(), dt, &obstacles);
+                    let position = Point2D::from(position);
 
-                if valid {
-                    player.position = new_position;
+                    if position != player.position {
+                        player.position = position;
+                        player.dirty = true;
+                        index
+                            .players
+                            .insert(*client_id, physics::sprite_hitbox(position.into()));
+                    }
                 }
             }
 
             if !player.flags.in_map_editor {
-                let warp = check_collision_with(
-                    player.position,
-                    map.zones
-                        .iter()
-                        .filter(|zone| matches!(zone.data, ZoneData::Warp(_, _, _))),
-                    |zone| Box2D::from_origin_and_size(zone.position.into(), zone.size.into()),
-                );
+                let hitbox = physics::sprite_hitbox(player.position.into());
+                let warp = index
+                    .zones
+                    .query(hitbox)
+                    .into_iter()
+                    .map(|(i, _rect)| &map.zones[i])
+                    .find(|zone| matches!(zone.data, ZoneData::Warp(_, _, _)));
 
                 if let Some(Zone {
                     data: ZoneData::Warp(map_id, position, direction),
@@ -635,50 +1505,87 @@ impl GameServer {
     fn validate_map(&mut self, map_id: &str) -> MapHash {
         let map_hash = MapHash::from(map_id);
         if let Entry::Vacant(e) = self.maps.entry(map_hash) {
-            e.insert(create_map(map_id));
+            let map = e.insert(create_map(&*self.storage, map_id));
+            self.spatial.insert(map_hash, MapIndex::new(map));
         }
 
         map_hash
     }
 
+    /// The middle of a map, in pixels
+    fn map_center(&self, map_hash: MapHash) -> Point2D<f32> {
+        let map = &self.maps[&map_hash];
+        Point2D::new(map.width as f32, map.height as f32) * TILE_SIZE as f32 / 2.0
+    }
+
+    /// Updates a player's hitbox in their map's spatial index, needed whenever they move
+    fn index_player(&mut self, client_id: ClientId) {
+        let player = &self.players[&client_id];
+        if let Some(index) = self.spatial.get_mut(&player.map) {
+            index
+                .players
+                .insert(client_id, physics::sprite_hitbox(player.position.into()));
+        }
+    }
+
     /// Warps the player to a specific map, sending all the correct packets
     fn warp_player(&mut self, client_id: ClientId, map_hash: MapHash, params: WarpParams) {
-        if !self.players.contains_key(&client_id) {
-            return;
-        }
+        let player = match self.players.get(&client_id) {
+            Some(player) => player,
+            None => return,
+        };
+
+        // jailed players can't leave, anything that would take them somewhere else puts them back in the middle
+        let jail = self
+            .moderation
+            .jail(&player.username)
+            .map(MapHash::from)
+            .filter(|jail| self.maps.contains_key(jail));
+
+        let (map_hash, params) = match jail {
+            Some(jail) if jail != map_hash => {
+                let params = WarpParams {
+                    position: Some(self.map_center(jail)),
+                    ..params
+                };
+                (jail, params)
+            }
+            _ => (map_hash, params),
+        };
 
         let old_map = self.players[&client_id].map;
 
         // check if we're actually changing maps, or if we're just moving to a new position.
-        if params.initial || self.players[&client_id].map != map_hash {
+        let changing_maps = params.initial || old_map != map_hash;
+        if changing_maps {
+            if !params.initial {
+                if let Some(index) = self.spatial.get_mut(&old_map) {
+                    index.players.remove(client_id);
+                }
+            }
+
+            // everyone nearby on the new map is sent by the next interest update
+            self.hide_player(client_id);
             if !params.initial {
-                self.send_map_except(old_map, client_id, &Packet::RemoveData(client_id));
+                self.hide_cursor(client_id);
             }
 
             self.players.get_mut(&client_id).unwrap().map = map_hash;
             let cache_key = self.maps[&map_hash].settings.cache_key;
+            let revision = self.map_revision(map_hash);
 
-            self.send(client_id, &Packet::ChangeMap(map_hash, cache_key));
-
-            let packets = self
-                .players
-                .iter()
-                .filter(|(_, player_data)| player_data.map == map_hash)
-                .map(|(&cid, data)| Packet::PlayerData(cid, data.clone().into()))
-                .collect::<Vec<_>>();
-
-            for packet in packets {
-                self.send(client_id, &packet);
-            }
+            self.send(client_id, &Packet::ChangeMap(map_hash, cache_key, revision));
 
             let player_data = self.players[&client_id].clone();
-            self.send_to_map(map_hash, &Packet::PlayerData(client_id, player_data.into()));
+            self.send(client_id, &Packet::PlayerData(client_id, player_data.into()));
         }
 
         if let Some(player) = self.players.get_mut(&client_id) {
             if let Some(position) = params.position {
                 player.position = position;
             }
+            // the budget carries over, so being warped back for a bad move doesn't hand out a new one
+            player.last_move = Some((self.time, player.position));
             if let Some(direction) = params.direction {
                 player.direction = direction;
                 player.velocity = None;
@@ -689,15 +1596,80 @@ impl GameServer {
                 position: player.position.into(),
                 direction: player.direction,
                 velocity: player.velocity.map(Into::into),
+                sequence: player.last_sequence,
             };
 
-            player.save().unwrap();
-            self.send_to_map(map_hash, &packet);
+            // moving around a map happens all the time, like being snapped back for a bad move, so it waits for the
+            // autosave instead
+            if changing_maps {
+                match self.storage.save_player(player) {
+                    Ok(()) => player.dirty = false,
+                    Err(e) => {
+                        player.dirty = true;
+                        log::error!("Couldn't save player: {e}");
+                    }
+                }
+            } else {
+                player.dirty = true;
+            }
+            self.send_movement(client_id, &packet);
+            self.index_player(client_id);
+
+            // they might still be walking the same way, which snapshots would otherwise leave out
+            for history in self.snapshots.values_mut() {
+                history.forget(client_id);
+            }
         }
     }
 
     pub fn maintain(&mut self) {
-        // lol
+        let interval = Duration::from_secs(self.config.autosave_interval);
+        if self.time - self.last_autosave >= interval {
+            self.last_autosave = self.time;
+            self.save_dirty();
+        }
+    }
+
+    /// Saves every player and map, regardless of if they've changed
+    fn save_all(&mut self) {
+        for player in self.players.values_mut() {
+            player.dirty = true;
+        }
+
+        for map in self.maps.values_mut() {
+            map.dirty = true;
+        }
+
+        self.save_dirty();
+    }
+
+    /// Saves every player and map that has changed since it was last saved
+    fn save_dirty(&mut self) {
+        let mut players = 0;
+        for player in self.players.values_mut().filter(|player| player.dirty) {
+            match self.storage.save_player(player) {
+                Ok(()) => {
+                    player.dirty = false;
+                    players += 1;
+                }
+                Err(e) => log::error!("Couldn't save player {}: {e}", player.username),
+            }
+        }
+
+        let mut maps = 0;
+        for map in self.maps.values_mut().filter(|map| map.dirty) {
+            match self.storage.save_map(map) {
+                Ok(()) => {
+                    map.dirty = false;
+                    maps += 1;
+                }
+                Err(e) => log::error!("Couldn't save map {}: {e}", map.id),
+            }
+        }
+
+        if players > 0 || maps > 0 {
+            log::info!("Saved {players} players and {maps} maps");
+        }
     }
 }
 
@@ -730,6 +1702,27 @@ impl GameServer {
         }
     }
 
+    /// Sends a player's movement to everyone that can see them, or just to themselves if snapshots are carrying it
+    pub fn send_movement(&self, client_id: ClientId, message: &Packet) {
+        if self.config.snapshots.enabled {
+            self.send(client_id, message);
+        } else {
+            self.send_to_observers(client_id, message);
+        }
+    }
+
+    /// Sends a packet about a player to everyone that can see them, including themselves
+    pub fn send_to_observers(&self, client_id: ClientId, message: &Packet) {
+        let mut clients = self
+            .visible
+            .get(&client_id)
+            .map(|visible| visible.iter().copied().collect::<Vec<_>>())
+            .unwrap_or_default();
+        clients.push(client_id);
+
+        self.send_list(&clients, message);
+    }
+
     pub fn send_to_map(&self, map_id: MapHash, message: &Packet) {
         let bytes = rmp_serde::to_vec(&message).unwrap();
         for (client_id, player) in &self.players {
@@ -740,14 +1733,16 @@ impl GameServer {
         }
     }
 
-    pub fn send_map_except(&self, map_id: MapHash, exclude_id: ClientId, message: &Packet) {
-        let bytes = rmp_serde::to_vec(&message).unwrap();
-        for (client_id, player) in &self.players {
-            if player.map == map_id && *client_id != exclude_id {
-                let endpoint = self.peer_map[client_id];
-                self.network().send(endpoint, &bytes);
-            }
-        }
+    /// Sends a packet to everyone with the map editor open on a map, apart from `exclude`
+    pub fn send_to_editors(&self, map_id: MapHash, exclude: ClientId, message: &Packet) {
+        let editors = self
+            .players
+            .iter()
+            .filter(|(&client_id, player)| client_id != exclude && player.map == map_id && player.flags.in_map_editor)
+            .map(|(&client_id, _)| client_id)
+            .collect::<Vec<_>>();
+
+        self.send_list(&editors, message);
     }
 
     pub fn send_all(&self, message: &Packet) {
@@ -758,42 +1753,30 @@ impl GameServer {
     }
 }
 
-fn check_bounds(position: Point2D<f32>, bounds: Box2D<f32>) -> Option<Direction> {
-    let bounds = bounds.to_rect();
-    let sprite = sprite_box(position);
+fn check_bounds(position: Point2D<f32>, bounds: physics::Rect) -> Option<Direction> {
+    let sprite = physics::sprite_hitbox(position.into());
 
-    if sprite.min.y <= bounds.min_y() {
+    if sprite.top() <= bounds.top() {
         Some(Direction::North)
-    } else if sprite.max.y >= bounds.max_y() {
+    } else if sprite.bottom() >= bounds.bottom() {
         Some(Direction::South)
-    } else if sprite.min.x <= bounds.min_x() {
+    } else if sprite.left() <= bounds.left() {
         Some(Direction::West)
-    } else if sprite.max.x >= bounds.max_x() {
+    } else if sprite.right() >= bounds.right() {
         Some(Direction::East)
     } else {
         None
     }
 }
 
-fn check_collision_with<'a, T>(
-    position: Point2D<f32>,
-    mut blockers: impl Iterator<Item = &'a T>,
-    map_with: impl Fn(&'a T) -> Box2D<f32>,
-) -> Option<&'a T> {
-    let sprite = sprite_box(position);
-
-    blockers.find(|item| sprite.intersects(&map_with(item)))
-}
-
 fn check_edge_warp(map: &Map, position: Point2D<f32>) -> Option<(Direction, Point2D<f32>)> {
-    let map_box = map.to_box2d();
+    let bounds = map.bounds();
 
-    if let Some(direction) = check_bounds(position, map_box) {
-        let map_rect = map_box.to_rect();
+    if let Some(direction) = check_bounds(position, bounds) {
         let new_position = match direction {
-            Direction::North => Point2D::new(position.x, map_rect.max_y() - SPRITE_SIZE as f32),
+            Direction::North => Point2D::new(position.x, bounds.bottom() - SPRITE_SIZE as f32),
             Direction::South => Point2D::new(position.x, -SPRITE_SIZE as f32 / 2.0),
-            Direction::West => Point2D::new(map_rect.max_x() - SPRITE_SIZE as f32, position.y),
+            Direction::West => Point2D::new(bounds.right() - SPRITE_SIZE as f32, position.y),
             Direction::East => Point2D::new(0.0, position.y),
         };
 
@@ -803,15 +1786,13 @@ fn check_edge_warp(map: &Map, position: Point2D<f32>) -> Option<(Direction, Poin
     }
 }
 
-fn create_map(id: &str) -> Map {
-    let map = Map::new(id, 20, 15);
-    map.save().unwrap();
-    map
+/// Middle of a player's sprite, what view distances are measured from
+fn player_center(position: Point2D<f32>) -> Point2D<f32> {
+    position + Vector2D::new(SPRITE_SIZE as f32 / 2.0, SPRITE_SIZE as f32 / 2.0)
 }
 
-fn sprite_box(position: Point2D<f32>) -> Box2D<f32> {
-    const SPRITE_SIZE2D: Size2D<f32> = Size2D::new(SPRITE_SIZE as f32, SPRITE_SIZE as f32 / 2.0);
-    const SPRITE_OFFSET: Vector2D<f32> = Vector2D::new(0.0, SPRITE_SIZE as f32 / 2.0);
-
-    Box2D::from_origin_and_size(position + SPRITE_OFFSET, SPRITE_SIZE2D)
+fn create_map(storage: &dyn Storage, id: &str) -> Map {
+    let map = Map::new(id, 20, 15);
+    storage.save_map(&map).unwrap();
+    map
 }
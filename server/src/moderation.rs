@@ -0,0 +1,121 @@
+use std::{collections::HashMap, fs::OpenOptions, io::Write, net::IpAddr, path::PathBuf};
+
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::data::write_atomic;
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum BanTarget {
+    /// A username, always lowercase
+    Account(String),
+    Address(IpAddr),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Ban {
+    pub reason: Option<String>,
+    /// When the ban runs out as a unix timestamp, or `None` if it's permanent
+    pub until: Option<i64>,
+    pub target: BanTarget,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Mute {
+    pub reason: Option<String>,
+    /// When the mute runs out as a unix timestamp
+    pub until: i64,
+}
+
+/// Bans, mutes and jails handed out by moderators, kept in the runtime folder so they last between restarts.
+///
+/// Players are looked up by their username in lowercase.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Moderation {
+    #[serde(default)]
+    pub bans: Vec<Ban>,
+    #[serde(default)]
+    pub mutes: HashMap<String, Mute>,
+    /// The id of the map each jailed player is stuck on
+    #[serde(default)]
+    pub jails: HashMap<String, String>,
+}
+
+impl Moderation {
+    pub fn path() -> PathBuf {
+        let mut path = common::server_runtime!();
+        path.push("moderation.toml");
+
+        path
+    }
+
+    /// Path of the audit log, every moderator action is appended to it
+    pub fn log_path() -> PathBuf {
+        let mut path = common::server_runtime!();
+        path.push("moderation.log");
+
+        path
+    }
+
+    /// Loads everything, starting out empty if nothing's been saved yet
+    pub fn load() -> Result<Self> {
+        match std::fs::read_to_string(Self::path()) {
+            Ok(contents) => Ok(toml::from_str(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Saves everything, dropping whatever has run out first
+    pub fn save(&mut self) -> Result<()> {
+        let now = Utc::now().timestamp();
+        self.bans
+            .retain(|ban| !matches!(ban.until, Some(until) if until <= now));
+        self.mutes.retain(|_, mute| mute.until > now);
+
+        write_atomic(Self::path(), toml::to_string_pretty(self)?.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Finds a ban that's keeping out an account or an address, if there is one
+    pub fn ban(&self, username: Option<&str>, address: Option<IpAddr>) -> Option<&Ban> {
+        let now = Utc::now().timestamp();
+        let username = username.map(str::to_lowercase);
+
+        self.bans.iter().find(|ban| {
+            let applies = match &ban.target {
+                BanTarget::Account(account) => username.as_ref() == Some(account),
+                BanTarget::Address(banned) => address == Some(*banned),
+            };
+
+            applies && !matches!(ban.until, Some(until) if until <= now)
+        })
+    }
+
+    pub fn mute(&self, username: &str) -> Option<&Mute> {
+        let now = Utc::now().timestamp();
+        self.mutes.get(&username.to_lowercase()).filter(|mute| mute.until > now)
+    }
+
+    pub fn jail(&self, username: &str) -> Option<&str> {
+        self.jails.get(&username.to_lowercase()).map(AsRef::as_ref)
+    }
+
+    /// Writes down something a moderator did
+    pub fn audit(moderator: &str, action: &str) {
+        let line = format!("{} {moderator}: {action}\n", Utc::now().to_rfc3339());
+
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(Self::log_path())
+            .and_then(|mut file| file.write_all(line.as_bytes()));
+
+        if let Err(e) = result {
+            log::error!("Couldn't write to the moderation log: {e}");
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+use common::network::ClientId;
+
+/// Most players that can be in a party at once
+pub const MAX_PARTY_SIZE: usize = 8;
+
+/// A group of online players that share party chat, it's gone once there's less than two of them
+#[derive(Clone, Debug)]
+pub struct Party {
+    pub leader: ClientId,
+    /// Everyone in the party, including the leader
+    pub members: Vec<ClientId>,
+}
+
+/// Every party on the server, they only last as long as their members are online
+#[derive(Default)]
+pub struct Parties {
+    parties: Vec<Party>,
+    /// Who invited each player, by the invited player
+    invites: HashMap<ClientId, ClientId>,
+}
+
+impl Parties {
+    /// The party a player is in, if they're in one
+    pub fn get(&self, client_id: ClientId) -> Option<&Party> {
+        self.parties.iter().find(|party| party.members.contains(&client_id))
+    }
+
+    /// Invites a player to join the inviter's party, replacing any invite they already had
+    pub fn invite(&mut self, client_id: ClientId, other_id: ClientId) -> Result<(), String> {
+        if self.get(other_id).is_some() {
+            return Err(String::from("They're already in a party"));
+        }
+
+        if let Some(party) = self.get(client_id) {
+            if party.leader != client_id {
+                return Err(String::from("Only the party leader can invite people"));
+            }
+
+            if party.members.len() >= MAX_PARTY_SIZE {
+                return Err(format!("Parties can't have more than {MAX_PARTY_SIZE} members"));
+            }
+        }
+
+        self.invites.insert(other_id, client_id);
+        Ok(())
+    }
+
+    /// Puts a player in the party they were invited to, starting one if whoever invited them wasn't in one yet
+    pub fn accept(&mut self, client_id: ClientId) -> Result<&Party, String> {
+        let leader = self
+            .invites
+            .remove(&client_id)
+            .ok_or("You haven't been invited to a party")?;
+
+        if self.get(client_id).is_some() {
+            return Err(String::from("You're already in a party, leave it first"));
+        }
+
+        let index = match self.parties.iter().position(|party| party.members.contains(&leader)) {
+            Some(index) => index,
+            None => {
+                self.parties.push(Party {
+                    leader,
+                    members: vec![leader],
+                });
+                self.parties.len() - 1
+            }
+        };
+
+        let party = &mut self.parties[index];
+        if party.members.len() >= MAX_PARTY_SIZE {
+            return Err(String::from("That party is full"));
+        }
+
+        party.members.push(client_id);
+        Ok(party)
+    }
+
+    /// Takes a player out of their party and forgets their invites, handing the party to someone else if they
+    /// were leading it.
+    ///
+    /// Returns what's left of the party, if it's down to one member it has been disbanded.
+    pub fn leave(&mut self, client_id: ClientId) -> Option<Party> {
+        self.invites
+            .retain(|invited, inviter| *invited != client_id && *inviter != client_id);
+
+        let index = self
+            .parties
+            .iter()
+            .position(|party| party.members.contains(&client_id))?;
+
+        let party = &mut self.parties[index];
+        party.members.retain(|member| *member != client_id);
+        if party.leader == client_id {
+            party.leader = party.members[0];
+        }
+
+        if party.members.len() < 2 {
+            Some(self.parties.remove(index))
+        } else {
+            Some(party.clone())
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::{sync::mpsc, thread};
+
+use anyhow::{anyhow, Result};
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+use base64ct::{Base64, Encoding};
+use common::network::ClientId;
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+
+/// Result of checking a password against a stored hash
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Verified {
+    /// The password matched and the hash is up to date
+    Valid,
+    /// The password matched, but the hash is outdated and should be replaced
+    NeedsRehash,
+    /// The password did not match
+    Invalid,
+}
+
+fn argon2() -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, Params::default())
+}
+
+/// Hashes a password with a fresh salt, returning it as a PHC string.
+///
+/// The string looks like `$argon2id$v=19$m=4096,t=3,p=1$<salt>$<hash>`, so the
+/// algorithm, version and parameters are stored alongside the hash itself.
+pub fn hash(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = argon2()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow!("couldn't hash password: {e}"))?;
+
+    Ok(hash.to_string())
+}
+
+/// Checks a password against a stored hash.
+///
+/// Hashes that don't parse as a PHC string are assumed to be the legacy unsalted
+/// base64 SHA-256 format, which are always reported as needing a rehash.
+pub fn verify(password: &str, stored: &str) -> Verified {
+    let hash = match PasswordHash::new(stored) {
+        Ok(hash) => hash,
+        Err(_) => return verify_legacy(password, stored),
+    };
+
+    if argon2().verify_password(password.as_bytes(), &hash).is_err() {
+        return Verified::Invalid;
+    }
+
+    if needs_rehash(&hash) {
+        Verified::NeedsRehash
+    } else {
+        Verified::Valid
+    }
+}
+
+fn needs_rehash(hash: &PasswordHash) -> bool {
+    let current = Params::default();
+
+    hash.algorithm != Algorithm::Argon2id.ident()
+        || hash.version != Some(Version::V0x13.into())
+        || Params::try_from(hash).map_or(true, |params| {
+            params.m_cost() != current.m_cost()
+                || params.t_cost() != current.t_cost()
+                || params.p_cost() != current.p_cost()
+        })
+}
+
+fn verify_legacy(password: &str, stored: &str) -> Verified {
+    let hash = Sha256::digest(password);
+    if Base64::encode_string(&hash) == stored {
+        Verified::NeedsRehash
+    } else {
+        Verified::Invalid
+    }
+}
+
+/// Password work that's too slow to do in the game loop
+pub enum Job {
+    /// Hashes the password for a new account
+    Hash(String),
+    /// Checks a password against a stored hash, rehashing it if it's outdated
+    Verify { password: String, stored: String },
+}
+
+pub enum Outcome {
+    Hashed(Result<String>),
+    Verified {
+        verified: Verified,
+        /// A fresh hash to store instead, if the password matched an outdated one
+        rehashed: Option<String>,
+    },
+}
+
+impl Job {
+    fn run(self) -> Outcome {
+        match self {
+            Job::Hash(password) => Outcome::Hashed(hash(&password)),
+            Job::Verify { password, stored } => {
+                let verified = verify(&password, &stored);
+
+                // upgrade the stored hash while we have the plain text
+                let rehashed = match verified {
+                    Verified::NeedsRehash => match hash(&password) {
+                        Ok(hash) => Some(hash),
+                        Err(e) => {
+                            log::error!("Couldn't rehash password: {e}");
+                            None
+                        }
+                    },
+                    _ => None,
+                };
+
+                Outcome::Verified { verified, rehashed }
+            }
+        }
+    }
+}
+
+/// Hashes and verifies passwords on a thread of it's own, one at a time
+pub struct Worker {
+    jobs: mpsc::Sender<(ClientId, Job)>,
+}
+
+impl Worker {
+    /// Starts the thread, `done` is called from it with the outcome of each job
+    pub fn spawn(done: impl Fn(ClientId, Outcome) + Send + 'static) -> Self {
+        let (jobs, queue) = mpsc::channel::<(ClientId, Job)>();
+
+        thread::Builder::new()
+            .name(String::from("passwords"))
+            .spawn(move || {
+                // stops once the worker is dropped
+                for (client_id, job) in queue {
+                    done(client_id, job.run());
+                }
+            })
+            .expect("spawn password thread");
+
+        Self { jobs }
+    }
+
+    pub fn queue(&self, client_id: ClientId, job: Job) {
+        if self.jobs.send((client_id, job)).is_err() {
+            log::error!("{client_id:?}: couldn't queue password job, the thread has stopped");
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Packets a client may send per second on average
+    pub packets_per_second: f64,
+    /// Packets a client may send at once before being limited
+    pub burst: f64,
+    /// How many times a client may go over the limit before being disconnected
+    pub max_strikes: u32,
+    /// How long it takes for a single strike to be forgiven, in seconds
+    pub strike_decay: f64,
+}
+
+impl Default for RateLimitConfig {
+    /// Enough for a mapper walking around while editing near players that are moving, who sends moves, map edits
+    /// 10 times a second, their cursor and snapshot acks 5 times a second each
+    fn default() -> Self {
+        Self {
+            packets_per_second: 60.0,
+            burst: 120.0,
+            max_strikes: 10,
+            strike_decay: 10.0,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    /// Makes sure every value is a positive number, so none of them can break the limiter
+    pub fn validate(&self) -> Result<()> {
+        let positive = |value: f64| value.is_finite() && value > 0.0;
+
+        ensure!(
+            positive(self.packets_per_second),
+            "rate_limit.packets_per_second must be more than 0"
+        );
+        ensure!(positive(self.burst), "rate_limit.burst must be more than 0");
+        ensure!(
+            positive(self.strike_decay),
+            "rate_limit.strike_decay must be more than 0"
+        );
+
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Limit {
+    /// The packet should be handled
+    Allow,
+    /// The packet should be dropped, the client got a strike
+    Drop,
+    /// The client is out of strikes and should be disconnected
+    Disconnect,
+}
+
+/// Token bucket for a single client, with a strike counter for repeat offenders
+pub struct RateLimiter {
+    tokens: f64,
+    strikes: u32,
+    last_update: Instant,
+    last_strike: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig, now: Instant) -> Self {
+        Self {
+            tokens: config.burst,
+            strikes: 0,
+            last_update: now,
+            last_strike: now,
+        }
+    }
+
+    /// Counts a packet against the limit, returning what should happen to it
+    pub fn check(&mut self, config: &RateLimitConfig, now: Instant) -> Limit {
+        let elapsed = now.saturating_duration_since(self.last_update);
+        self.last_update = now;
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * config.packets_per_second).min(config.burst);
+
+        let decay = Duration::from_secs_f64(config.strike_decay);
+        while self.strikes > 0 && now.saturating_duration_since(self.last_strike) >= decay {
+            self.strikes -= 1;
+            self.last_strike += decay;
+        }
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Limit::Allow;
+        }
+
+        self.strikes += 1;
+        self.last_strike = now;
+
+        if self.strikes > config.max_strikes {
+            Limit::Disconnect
+        } else {
+            Limit::Drop
+        }
+    }
+}
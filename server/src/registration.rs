@@ -0,0 +1,169 @@
+use std::ops::RangeInclusive;
+
+use anyhow::Result;
+use common::network::server::FailJoinReason;
+
+use crate::data::{CreatePlayer, Player, Storage};
+
+const USERNAME_LENGTH: RangeInclusive<usize> = 3..=20;
+const CHARACTER_NAME_LENGTH: RangeInclusive<usize> = 3..=16;
+
+/// Names that can't be used as a username or character name, compared case-insensitively
+const RESERVED_NAMES: &[&str] = &[
+    "admin",
+    "administrator",
+    "gm",
+    "mod",
+    "moderator",
+    "owner",
+    "server",
+    "system",
+];
+
+pub enum Registration {
+    Created(Player),
+    Rejected(FailJoinReason),
+}
+
+/// Checks that a new account's names are valid and free, returning why it can't be created if they aren't.
+///
+/// This is cheap, so it's done before going to the trouble of hashing the account's password.
+pub fn check(storage: &dyn Storage, username: &str, character_name: &str) -> Result<Option<FailJoinReason>> {
+    if let Err(reason) = validate_username(username).and_then(|_| validate_character_name(character_name)) {
+        return Ok(Some(reason));
+    }
+
+    if storage.load_player(username)?.is_some() {
+        return Ok(Some(FailJoinReason::UsernameTaken));
+    }
+
+    if storage.find_player(character_name)?.is_some() {
+        return Ok(Some(FailJoinReason::CharacterNameTaken));
+    }
+
+    Ok(None)
+}
+
+/// Creates a new account that's already been checked and had it's password hashed, reserving both of it's names.
+///
+/// Someone else could have taken either name since it was checked, an existing account is never overwritten.
+pub fn register(storage: &dyn Storage, player: Player) -> Result<Registration> {
+    Ok(match storage.create_player(&player)? {
+        CreatePlayer::Created => Registration::Created(player),
+        CreatePlayer::UsernameTaken => Registration::Rejected(FailJoinReason::UsernameTaken),
+        CreatePlayer::NameTaken => Registration::Rejected(FailJoinReason::CharacterNameTaken),
+    })
+}
+
+pub fn validate_username(username: &str) -> Result<(), FailJoinReason> {
+    if !USERNAME_LENGTH.contains(&username.chars().count()) {
+        return Err(FailJoinReason::UsernameLength {
+            min: *USERNAME_LENGTH.start(),
+            max: *USERNAME_LENGTH.end(),
+        });
+    }
+
+    // usernames are used as file names, so keep them boring
+    if !username.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(FailJoinReason::UsernameInvalid);
+    }
+
+    if is_reserved(username) {
+        return Err(FailJoinReason::NameReserved);
+    }
+
+    Ok(())
+}
+
+pub fn validate_character_name(name: &str) -> Result<(), FailJoinReason> {
+    if !CHARACTER_NAME_LENGTH.contains(&name.chars().count()) {
+        return Err(FailJoinReason::CharacterNameLength {
+            min: *CHARACTER_NAME_LENGTH.start(),
+            max: *CHARACTER_NAME_LENGTH.end(),
+        });
+    }
+
+    // words of letters and numbers, separated by single spaces
+    let valid = name
+        .split(' ')
+        .all(|word| !word.is_empty() && word.chars().all(|c| c.is_ascii_alphanumeric()));
+
+    if !valid {
+        return Err(FailJoinReason::CharacterNameInvalid);
+    }
+
+    if is_reserved(name) {
+        return Err(FailJoinReason::NameReserved);
+    }
+
+    Ok(())
+}
+
+fn is_reserved(name: &str) -> bool {
+    RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(name.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usernames_have_to_be_the_right_length() {
+        let length = FailJoinReason::UsernameLength { min: 3, max: 20 };
+
+        assert_eq!(validate_username("ab"), Err(length.clone()));
+        assert_eq!(validate_username(&"a".repeat(21)), Err(length));
+        assert_eq!(validate_username("abc"), Ok(()));
+        assert_eq!(validate_username(&"a".repeat(20)), Ok(()));
+    }
+
+    #[test]
+    fn usernames_are_letters_numbers_and_underscores() {
+        assert_eq!(validate_username("Some_User42"), Ok(()));
+
+        for username in ["some user", "some-user", "../user", "usér", "user\0"] {
+            assert_eq!(
+                validate_username(username),
+                Err(FailJoinReason::UsernameInvalid),
+                "{username:?} was allowed"
+            );
+        }
+    }
+
+    #[test]
+    fn character_names_have_to_be_the_right_length() {
+        let length = FailJoinReason::CharacterNameLength { min: 3, max: 16 };
+
+        assert_eq!(validate_character_name("Al"), Err(length.clone()));
+        assert_eq!(validate_character_name(&"a".repeat(17)), Err(length));
+        assert_eq!(validate_character_name("Bob"), Ok(()));
+        assert_eq!(validate_character_name(&"a".repeat(16)), Ok(()));
+    }
+
+    #[test]
+    fn character_names_are_words_separated_by_single_spaces() {
+        assert_eq!(validate_character_name("Sir Bob 2"), Ok(()));
+
+        for name in ["Sir  Bob", " Bob", "Bob ", "Bob_2", "Bob!", "Bób"] {
+            assert_eq!(
+                validate_character_name(name),
+                Err(FailJoinReason::CharacterNameInvalid),
+                "{name:?} was allowed"
+            );
+        }
+    }
+
+    #[test]
+    fn reserved_names_are_rejected_in_any_case() {
+        for name in ["admin", "Admin", "ADMIN", "MoDeRaToR", "System"] {
+            assert_eq!(validate_username(name), Err(FailJoinReason::NameReserved));
+            assert_eq!(validate_character_name(name), Err(FailJoinReason::NameReserved));
+        }
+
+        // only the whole name is reserved
+        assert_eq!(validate_username("admin2"), Ok(()));
+        assert_eq!(validate_character_name("Admin Bob"), Ok(()));
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::VecDeque;
+
+use common::network::{
+    snapshot::{self, Snapshot, SnapshotState},
+    ClientId,
+};
+use serde::{Deserialize, Serialize};
+
+/// Most snapshots kept around per client waiting to be acknowledged
+const HISTORY_LENGTH: usize = 32;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SnapshotConfig {
+    /// Sends movement in batched snapshots instead of a packet per change
+    pub enabled: bool,
+    /// How many snapshots are sent per second
+    pub rate: u32,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rate: 20,
+        }
+    }
+}
+
+/// Snapshots sent to a single client, so new ones can be encoded against what it's acknowledged
+pub struct SnapshotHistory {
+    next_sequence: u32,
+    acknowledged: Option<u32>,
+    /// Sent snapshots from the last acknowledged one onward as the client decodes them, oldest first
+    sent: VecDeque<(u32, SnapshotState)>,
+}
+
+impl SnapshotHistory {
+    pub fn new() -> Self {
+        Self {
+            next_sequence: 0,
+            acknowledged: None,
+            sent: VecDeque::new(),
+        }
+    }
+
+    /// Builds the next snapshot of `state`, or `None` if nothing changed since the last one sent
+    pub fn next(&mut self, state: SnapshotState) -> Option<Snapshot> {
+        let changed = match self.sent.back() {
+            Some((_, last)) => snapshot::changed(last, &state),
+            None => !state.is_empty(),
+        };
+        if !changed {
+            return None;
+        }
+
+        let baseline = self
+            .acknowledged
+            .and_then(|acknowledged| self.sent.iter().find(|(sequence, _)| *sequence == acknowledged))
+            .map(|(sequence, baseline)| (*sequence, baseline));
+
+        let snapshot = Snapshot::encode(self.next_sequence, &state, baseline);
+        let decoded = snapshot
+            .decode(baseline.map(|(_, baseline)| baseline))
+            .expect("snapshots decode against the baseline they were encoded with");
+
+        self.sent.push_back((self.next_sequence, decoded));
+        if self.sent.len() > HISTORY_LENGTH {
+            self.sent.pop_front();
+        }
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+
+        Some(snapshot)
+    }
+
+    /// Makes the next snapshot send a player in full, for when they've moved somewhere the client can't extrapolate
+    pub fn forget(&mut self, client_id: ClientId) {
+        for (_, state) in &mut self.sent {
+            state.remove(&client_id);
+        }
+    }
+
+    /// Marks a snapshot as received, anything older than it won't be needed again
+    pub fn acknowledge(&mut self, sequence: u32) {
+        let newer = !matches!(self.acknowledged, Some(acknowledged) if acknowledged >= sequence);
+
+        if newer && self.sent.iter().any(|(sent, _)| *sent == sequence) {
+            self.acknowledged = Some(sequence);
+            self.sent.retain(|(sent, _)| *sent >= sequence);
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+use common::{
+    network::{edit::MAX_MAP_SIZE, ClientId},
+    physics::Rect,
+    TILE_SIZE,
+};
+
+use crate::data::Map;
+
+/// Width and height of a single cell, in pixels
+const CELL_SIZE: f32 = TILE_SIZE as f32 * 4.0;
+
+/// Last cell along either axis of the biggest map, nothing can be indexed past it
+const MAX_CELL: i32 = (MAX_MAP_SIZE as f32 * TILE_SIZE as f32 / CELL_SIZE) as i32;
+
+/// Buckets rectangles into a grid of cells, so finding everything in an area only looks at what's nearby
+pub struct SpatialHash<K> {
+    cells: HashMap<(i32, i32), Vec<K>>,
+    entries: HashMap<K, Rect>,
+}
+
+impl<K: Copy + Eq + Hash> SpatialHash<K> {
+    pub fn new() -> Self {
+        Self {
+            cells: HashMap::new(),
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Adds a rectangle to the index, replacing it if the key was already in it
+    pub fn insert(&mut self, key: K, rect: Rect) {
+        if let Some(old) = self.entries.get(&key) {
+            // most moves don't leave the cells they were already in
+            if cells(old).eq(cells(&rect)) {
+                self.entries.insert(key, rect);
+                return;
+            }
+
+            self.remove(key);
+        }
+
+        for cell in cells(&rect) {
+            self.cells.entry(cell).or_default().push(key);
+        }
+        self.entries.insert(key, rect);
+    }
+
+    pub fn remove(&mut self, key: K) -> Option<Rect> {
+        let rect = self.entries.remove(&key)?;
+
+        for cell in cells(&rect) {
+            if let Some(keys) = self.cells.get_mut(&cell) {
+                keys.retain(|other| *other != key);
+                if keys.is_empty() {
+                    self.cells.remove(&cell);
+                }
+            }
+        }
+
+        Some(rect)
+    }
+
+    /// Finds everything overlapping `area`
+    pub fn query(&self, area: Rect) -> Vec<(K, Rect)> {
+        let mut found: Vec<(K, Rect)> = Vec::new();
+        // anything spanning more than one cell shows up in each of them
+        let mut seen = HashSet::new();
+
+        for cell in cells(&area) {
+            for key in self.cells.get(&cell).into_iter().flatten() {
+                let rect = self.entries[key];
+                if rect.overlaps(&area) && seen.insert(*key) {
+                    found.push((*key, rect));
+                }
+            }
+        }
+
+        found
+    }
+}
+
+/// Every cell a rectangle touches, anything off the edge of the biggest map goes in the cells along that edge
+fn cells(rect: &Rect) -> impl Iterator<Item = (i32, i32)> {
+    let to_cell = |value: f32| ((value / CELL_SIZE).floor() as i32).clamp(0, MAX_CELL);
+
+    let (min_x, max_x) = (to_cell(rect.left()), to_cell(rect.right()));
+    let (min_y, max_y) = (to_cell(rect.top()), to_cell(rect.bottom()));
+
+    (min_y..=max_y).flat_map(move |y| (min_x..=max_x).map(move |x| (x, y)))
+}
+
+/// Spatial indexes of everything that can be collided with on a single map
+pub struct MapIndex {
+    /// Zones, keyed by their position in `Map::zones`
+    pub zones: SpatialHash<usize>,
+    /// Hitboxes of every player on the map
+    pub players: SpatialHash<ClientId>,
+}
+
+impl MapIndex {
+    pub fn new(map: &Map) -> Self {
+        let mut index = Self {
+            zones: SpatialHash::new(),
+            players: SpatialHash::new(),
+        };
+
+        index.rebuild_zones(map);
+        index
+    }
+
+    /// Rebuilds the zone index from scratch, needed whenever the map's zones are replaced
+    pub fn rebuild_zones(&mut self, map: &Map) {
+        self.zones = SpatialHash::new();
+
+        for (i, zone) in map.zones.iter().enumerate() {
+            self.zones.insert(i, Rect::from(zone));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rects_spanning_cells_are_found_once() {
+        let mut hash = SpatialHash::new();
+        hash.insert(1, Rect::new(100.0, 100.0, CELL_SIZE * 3.0, CELL_SIZE * 2.0));
+        assert!(cells(&hash.entries[&1]).count() > 1);
+
+        let found = hash.query(Rect::new(0.0, 0.0, CELL_SIZE * 5.0, CELL_SIZE * 5.0));
+        assert_eq!(
+            found,
+            vec![(1, Rect::new(100.0, 100.0, CELL_SIZE * 3.0, CELL_SIZE * 2.0))]
+        );
+    }
+
+    #[test]
+    fn moving_across_cells_leaves_nothing_behind() {
+        let mut hash = SpatialHash::new();
+        hash.insert(1, Rect::new(10.0, 10.0, 48.0, 48.0));
+        hash.insert(1, Rect::new(CELL_SIZE * 10.0, CELL_SIZE * 10.0, 48.0, 48.0));
+
+        assert!(hash.query(Rect::new(0.0, 0.0, CELL_SIZE, CELL_SIZE)).is_empty());
+        assert_eq!(hash.cells.len(), 1);
+        assert!(hash.cells.values().all(|keys| keys == &[1]));
+
+        hash.remove(1);
+        assert!(hash.cells.is_empty());
+        assert!(hash.entries.is_empty());
+    }
+
+    #[test]
+    fn rects_off_the_map_are_clamped_into_it() {
+        let far = MAX_MAP_SIZE as f32 * TILE_SIZE as f32 * 10.0;
+
+        let mut hash = SpatialHash::new();
+        hash.insert(1, Rect::new(-500.0, -500.0, 48.0, 48.0));
+        hash.insert(2, Rect::new(far, far, 48.0, 48.0));
+        hash.insert(3, Rect::new(-far, 10.0, far * 2.0, 48.0));
+
+        let found = |area| {
+            let mut keys = hash.query(area).into_iter().map(|(key, _)| key).collect::<Vec<_>>();
+            keys.sort();
+            keys
+        };
+
+        assert_eq!(found(Rect::new(-600.0, -600.0, 200.0, 200.0)), vec![1]);
+        assert_eq!(found(Rect::new(far - 10.0, far - 10.0, 100.0, 100.0)), vec![2]);
+        assert_eq!(
+            found(Rect::new(-far * 2.0, -far * 2.0, far * 4.0, far * 4.0)),
+            vec![1, 2, 3]
+        );
+    }
+}